@@ -0,0 +1,217 @@
+//! 布尔查询表达式
+//!
+//! 语法(优先级从低到高):
+//!
+//! ```text
+//! expr    = and_expr ("or" and_expr)*
+//! and_expr = unary ("and" unary)*
+//! unary   = "not" unary | primary
+//! primary = "(" expr ")" | TERM | QUOTED
+//! ```
+//!
+//! 关键字不区分大小写, 需要搜索关键字本身时使用引号, 例如`"and"`。
+
+/// 解析后的表达式语法树
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  Term(String),
+  Not(Box<Expr>),
+  And(Box<Expr>, Box<Expr>),
+  Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+  pub fn parse(input: &str) -> Result<Expr, &'static str> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.parse_or()?;
+    if parser.pos != parser.tokens.len() {
+      return Err("Unexpected token in expression");
+    }
+    Ok(expr)
+  }
+
+  /// 判断一行是否满足表达式, 每个Term都是一个子串匹配器
+  pub fn matches(&self, line: &str) -> bool {
+    match self {
+      Expr::Term(term) => line.contains(term.as_str()),
+      Expr::Not(inner) => !inner.matches(line),
+      Expr::And(lhs, rhs) => lhs.matches(line) && rhs.matches(line),
+      Expr::Or(lhs, rhs) => lhs.matches(line) || rhs.matches(line),
+    }
+  }
+
+  /// 将所有Term转为小写, 用于大小写不敏感的匹配
+  pub fn to_lowercase(&self) -> Expr {
+    match self {
+      Expr::Term(term) => Expr::Term(term.to_lowercase()),
+      Expr::Not(inner) => Expr::Not(Box::new(inner.to_lowercase())),
+      Expr::And(lhs, rhs) => Expr::And(Box::new(lhs.to_lowercase()), Box::new(rhs.to_lowercase())),
+      Expr::Or(lhs, rhs) => Expr::Or(Box::new(lhs.to_lowercase()), Box::new(rhs.to_lowercase())),
+    }
+  }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+  And,
+  Or,
+  Not,
+  LParen,
+  RParen,
+  Term(String),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, &'static str> {
+  let mut tokens = Vec::new();
+  let mut chars = input.chars().peekable();
+
+  while let Some(&c) = chars.peek() {
+    match c {
+      c if c.is_whitespace() => {
+        chars.next();
+      }
+      '(' => {
+        chars.next();
+        tokens.push(Token::LParen);
+      }
+      ')' => {
+        chars.next();
+        tokens.push(Token::RParen);
+      }
+      '"' | '\'' => {
+        chars.next();
+        let mut term = String::new();
+        loop {
+          match chars.next() {
+            Some(ch) if ch == c => break,
+            Some('\\') => match chars.next() {
+              Some(escaped) => term.push(escaped),
+              None => return Err("Unterminated quoted term in expression"),
+            },
+            Some(ch) => term.push(ch),
+            None => return Err("Unterminated quoted term in expression"),
+          }
+        }
+        tokens.push(Token::Term(term));
+      }
+      _ => {
+        let mut word = String::new();
+        while let Some(&ch) = chars.peek() {
+          if ch.is_whitespace() || ch == '(' || ch == ')' {
+            break;
+          }
+          word.push(ch);
+          chars.next();
+        }
+        let token = match word.to_lowercase().as_str() {
+          "and" => Token::And,
+          "or" => Token::Or,
+          "not" => Token::Not,
+          _ => Token::Term(word),
+        };
+        tokens.push(token);
+      }
+    }
+  }
+  Ok(tokens)
+}
+
+// 递归下降解析器
+struct Parser {
+  tokens: Vec<Token>,
+  pos: usize,
+}
+
+impl Parser {
+  fn eat(&mut self, token: &Token) -> bool {
+    if self.tokens.get(self.pos) == Some(token) {
+      self.pos += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn parse_or(&mut self) -> Result<Expr, &'static str> {
+    let mut expr = self.parse_and()?;
+    while self.eat(&Token::Or) {
+      expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+    }
+    Ok(expr)
+  }
+
+  fn parse_and(&mut self) -> Result<Expr, &'static str> {
+    let mut expr = self.parse_unary()?;
+    while self.eat(&Token::And) {
+      expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+    }
+    Ok(expr)
+  }
+
+  fn parse_unary(&mut self) -> Result<Expr, &'static str> {
+    if self.eat(&Token::Not) {
+      return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+    }
+    self.parse_primary()
+  }
+
+  fn parse_primary(&mut self) -> Result<Expr, &'static str> {
+    if self.eat(&Token::LParen) {
+      let expr = self.parse_or()?;
+      if !self.eat(&Token::RParen) {
+        return Err("Missing closing parenthesis in expression");
+      }
+      return Ok(expr);
+    }
+    match self.tokens.get(self.pos) {
+      Some(Token::Term(term)) => {
+        let term = term.clone();
+        self.pos += 1;
+        Ok(Expr::Term(term))
+      }
+      Some(_) => Err("Unexpected token in expression"),
+      None => Err("Unexpected end of expression"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn term(s: &str) -> Box<Expr> {
+    Box::new(Expr::Term(s.to_string()))
+  }
+
+  #[test]
+  fn precedence() {
+    assert_eq!(
+      Expr::Or(
+        term("a"),
+        Box::new(Expr::And(term("b"), Box::new(Expr::Not(term("c")))))
+      ),
+      Expr::parse("a or b AND not c").unwrap()
+    );
+    assert_eq!(
+      Expr::And(Box::new(Expr::Or(term("a"), term("b"))), term("and")),
+      Expr::parse("(a or b) and \"and\"").unwrap()
+    );
+  }
+
+  #[test]
+  fn evaluate() {
+    let expr = Expr::parse("timeout and not retry").unwrap();
+    assert!(expr.matches("read timeout"));
+    assert!(!expr.matches("read timeout, retry 1"));
+    assert!(!expr.matches("ok"));
+  }
+
+  #[test]
+  fn malformed() {
+    assert!(Expr::parse("a and").is_err());
+    assert!(Expr::parse("(a or b").is_err());
+    assert!(Expr::parse("a b").is_err());
+    assert!(Expr::parse("'open").is_err());
+  }
+}
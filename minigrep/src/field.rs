@@ -0,0 +1,299 @@
+//! 结构化字段搜索
+//!
+//! 支持两种按行组织的结构化格式:
+//! - logfmt: `key=value key2="quoted value"`
+//! - 分隔符格式(CSV/TSV): 字段之间以分隔符隔开, 支持双引号包裹的字段以及`""`转义
+//!
+//! 记录以行为单位, 不支持跨行的引号字段。
+
+/// 字段选择器
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+  /// 按名称选择: logfmt中的key, 或分隔符格式中表头的列名
+  Name(String),
+  /// 按列号选择, 从1开始
+  Column(usize),
+}
+
+impl Selector {
+  /// 纯数字解析为列号, 其余解析为名称
+  pub fn parse(s: &str) -> Result<Selector, &'static str> {
+    if s.is_empty() {
+      return Err("Field selector must not be empty");
+    }
+    if s.bytes().all(|b| b.is_ascii_digit()) {
+      return parse_column(s).map(Selector::Column);
+    }
+    Ok(Selector::Name(s.to_string()))
+  }
+}
+
+/// 解析从1开始的列号
+pub fn parse_column(s: &str) -> Result<usize, &'static str> {
+  match s.parse::<usize>() {
+    Ok(n) if n > 0 => Ok(n),
+    _ => Err("Column must be a positive number"),
+  }
+}
+
+/// 解析分隔符参数, `tab`和`\t`都代表制表符
+pub fn parse_delimiter(s: &str) -> Result<char, &'static str> {
+  match s {
+    "tab" | "\\t" => return Ok('\t'),
+    _ => {}
+  }
+  let mut chars = s.chars();
+  match (chars.next(), chars.next()) {
+    (Some('"'), None) => Err("Delimiter must not be a quote"),
+    (Some(c), None) => Ok(c),
+    _ => Err("Delimiter must be a single character"),
+  }
+}
+
+/// 行的结构化格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+  Logfmt,
+  Delimited(char),
+}
+
+/// 结构化搜索的选项
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldOptions {
+  pub format: Format,
+  /// 只在该字段内匹配查询, 为None时匹配整行
+  pub field: Option<Selector>,
+  /// 输出时只重新输出这些字段, 为空时输出整行
+  pub select: Vec<Selector>,
+}
+
+impl FieldOptions {
+  /// 分隔符格式下按名称选择字段时, 第一行作为表头
+  pub fn has_header(&self) -> bool {
+    match self.format {
+      Format::Logfmt => false,
+      Format::Delimited(_) => self
+        .field
+        .iter()
+        .chain(self.select.iter())
+        .any(|s| matches!(s, Selector::Name(_))),
+    }
+  }
+}
+
+/// 解析后的一行记录, 每个字段附带可选的名称
+#[derive(Debug, PartialEq)]
+pub struct Record {
+  fields: Vec<(Option<String>, String)>,
+}
+
+impl Record {
+  pub fn parse(line: &str, format: Format, header: Option<&[String]>) -> Record {
+    let fields = match format {
+      Format::Logfmt => parse_logfmt(line)
+        .into_iter()
+        .map(|(k, v)| (Some(k), v))
+        .collect(),
+      Format::Delimited(delimiter) => split_delimited(line, delimiter)
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| (header.and_then(|h| h.get(i).cloned()), v))
+        .collect(),
+    };
+    Record { fields }
+  }
+
+  pub fn get(&self, selector: &Selector) -> Option<&str> {
+    match selector {
+      Selector::Name(name) => self
+        .fields
+        .iter()
+        .find(|(k, _)| k.as_deref() == Some(name.as_str()))
+        .map(|(_, v)| v.as_str()),
+      Selector::Column(n) => self.fields.get(n - 1).map(|(_, v)| v.as_str()),
+    }
+  }
+
+  /// 按原格式重新输出选中的字段
+  ///
+  /// 分隔符格式下缺失的字段输出为空值, logfmt下直接省略。
+  pub fn render(&self, select: &[Selector], format: Format) -> String {
+    match format {
+      Format::Logfmt => select
+        .iter()
+        .filter_map(|s| {
+          let value = self.get(s)?;
+          let key = match s {
+            Selector::Name(name) => name.clone(),
+            Selector::Column(n) => self.fields[n - 1].0.clone().unwrap_or_default(),
+          };
+          Some(format!("{}={}", key, quote_logfmt(value)))
+        })
+        .collect::<Vec<_>>()
+        .join(" "),
+      Format::Delimited(delimiter) => select
+        .iter()
+        .map(|s| quote_delimited(self.get(s).unwrap_or(""), delimiter))
+        .collect::<Vec<_>>()
+        .join(&delimiter.to_string()),
+    }
+  }
+}
+
+/// 按分隔符切分一行, 处理双引号包裹的字段和`""`转义
+pub fn split_delimited(line: &str, delimiter: char) -> Vec<String> {
+  let mut fields = Vec::new();
+  let mut field = String::new();
+  let mut in_quotes = false;
+  let mut chars = line.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    if in_quotes {
+      if c == '"' {
+        if chars.peek() == Some(&'"') {
+          chars.next();
+          field.push('"');
+        } else {
+          in_quotes = false;
+        }
+      } else {
+        field.push(c);
+      }
+    } else if c == delimiter {
+      fields.push(std::mem::take(&mut field));
+    } else if c == '"' && field.is_empty() {
+      in_quotes = true;
+    } else {
+      field.push(c);
+    }
+  }
+  fields.push(field);
+  fields
+}
+
+/// 解析一行logfmt, 没有`=`的key值为空字符串
+pub fn parse_logfmt(line: &str) -> Vec<(String, String)> {
+  let mut pairs = Vec::new();
+  let mut chars = line.chars().peekable();
+
+  loop {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+      chars.next();
+    }
+    if chars.peek().is_none() {
+      break;
+    }
+
+    let mut key = String::new();
+    while let Some(&c) = chars.peek() {
+      if c == '=' || c.is_whitespace() {
+        break;
+      }
+      key.push(c);
+      chars.next();
+    }
+
+    let mut value = String::new();
+    if chars.peek() == Some(&'=') {
+      chars.next();
+      if chars.peek() == Some(&'"') {
+        chars.next();
+        while let Some(c) = chars.next() {
+          match c {
+            '"' => break,
+            '\\' => match chars.next() {
+              Some('n') => value.push('\n'),
+              Some('t') => value.push('\t'),
+              Some(other) => value.push(other),
+              None => break,
+            },
+            _ => value.push(c),
+          }
+        }
+      } else {
+        while let Some(&c) = chars.peek() {
+          if c.is_whitespace() {
+            break;
+          }
+          value.push(c);
+          chars.next();
+        }
+      }
+    }
+    pairs.push((key, value));
+  }
+  pairs
+}
+
+fn quote_logfmt(value: &str) -> String {
+  let needs_quotes = value.is_empty() ||
+    value
+      .chars()
+      .any(|c| c.is_whitespace() || c == '=' || c == '"' || c == '\\');
+  if !needs_quotes {
+    return value.to_string();
+  }
+  let mut quoted = String::with_capacity(value.len() + 2);
+  quoted.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\t' => quoted.push_str("\\t"),
+      _ => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+fn quote_delimited(value: &str, delimiter: char) -> String {
+  if value
+    .chars()
+    .any(|c| c == delimiter || c == '"' || c == '\r' || c == '\n')
+  {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value.to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn split_quoted_csv() {
+    assert_eq!(
+      vec!["a", "b, c", "say \"hi\"", ""],
+      split_delimited(r#"a,"b, c","say ""hi""","#, ',')
+    );
+  }
+
+  #[test]
+  fn parse_logfmt_pairs() {
+    assert_eq!(
+      vec![
+        ("level".to_string(), "error".to_string()),
+        ("msg".to_string(), "read \"x\" timeout".to_string()),
+        ("retry".to_string(), "".to_string()),
+      ],
+      parse_logfmt(r#"level=error msg="read \"x\" timeout" retry"#)
+    );
+  }
+
+  #[test]
+  fn render_selected_fields() {
+    let header = vec!["name".to_string(), "note".to_string()];
+    let record = Record::parse(r#"bob,"x,y""#, Format::Delimited(','), Some(&header));
+    let select = vec![Selector::Name("note".to_string()), Selector::Column(1)];
+    assert_eq!(r#""x,y",bob"#, record.render(&select, Format::Delimited(',')));
+
+    let record = Record::parse("a=1 msg=\"two words\"", Format::Logfmt, None);
+    assert_eq!(
+      "msg=\"two words\"",
+      record.render(&[Selector::Name("msg".to_string())], Format::Logfmt)
+    );
+  }
+}
@@ -0,0 +1,113 @@
+//! 命令行选项的定义
+//!
+//! `Config::new`解析参数、生成补全脚本和man手册都以这张表为准, 新增选项只需在表中
+//! 添加一项并在`Config::new`中处理对应的[`Opt`]。
+
+/// 选项设置的配置项, `Config::new`按它分派
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opt {
+  Field,
+  Column,
+  Delimiter,
+  Select,
+  Expr,
+  Sort,
+  Unique,
+  Stats,
+  Generate,
+}
+
+/// 一个命令行选项
+#[derive(Debug)]
+pub struct Flag {
+  /// 不带`--`前缀的名称
+  pub name: &'static str,
+  pub opt: Opt,
+  /// 选项值的占位名, 为None时是不带值的开关
+  pub value: Option<&'static str>,
+  /// 选项值的可选范围, 为空时可以是任意值
+  pub choices: &'static [&'static str],
+  pub help: &'static str,
+}
+
+impl Flag {
+  pub fn takes_value(&self) -> bool {
+    self.value.is_some()
+  }
+}
+
+pub const FLAGS: &[Flag] = &[
+  Flag {
+    name: "field",
+    opt: Opt::Field,
+    value: Some("NAME"),
+    choices: &[],
+    help: "Only match inside the named field, a logfmt key or a CSV header column",
+  },
+  Flag {
+    name: "column",
+    opt: Opt::Column,
+    value: Some("N"),
+    choices: &[],
+    help: "Only match inside the N-th delimited column, counting from 1",
+  },
+  Flag {
+    name: "delimiter",
+    opt: Opt::Delimiter,
+    value: Some("CHAR"),
+    choices: &[],
+    help: "Parse lines as CSV/TSV split by CHAR, use tab for TSV",
+  },
+  Flag {
+    name: "select",
+    opt: Opt::Select,
+    value: Some("FIELDS"),
+    choices: &[],
+    help: "Print only these comma separated fields of matching lines",
+  },
+  Flag {
+    name: "expr",
+    opt: Opt::Expr,
+    value: Some("EXPR"),
+    choices: &[],
+    help: "Match a boolean expression of terms with and, or, not and parentheses instead of QUERY",
+  },
+  Flag {
+    name: "sort",
+    opt: Opt::Sort,
+    value: Some("KEY"),
+    choices: &["path", "mtime", "matches"],
+    help: "Order the searched files by path, modification time or match count",
+  },
+  Flag {
+    name: "unique",
+    opt: Opt::Unique,
+    value: None,
+    choices: &[],
+    help: "Collapse identical matching lines and print their counts",
+  },
+  Flag {
+    name: "stats",
+    opt: Opt::Stats,
+    value: None,
+    choices: &[],
+    help: "Print files scanned, bytes read, matches and elapsed time at the end",
+  },
+  Flag {
+    name: "generate",
+    opt: Opt::Generate,
+    value: Some("TARGET"),
+    choices: &[
+      "completions=bash",
+      "completions=zsh",
+      "completions=fish",
+      "man",
+    ],
+    help: "Print a shell completion script or the roff man page and exit",
+  },
+];
+
+/// 按名称查找选项, name不带`--`前缀
+pub fn find(name: &str) -> Option<&'static Flag> {
+  FLAGS.iter().find(|flag| flag.name == name)
+}
@@ -0,0 +1,205 @@
+//! 根据`flags::FLAGS`生成shell补全脚本和roff格式的man手册
+
+use crate::flags::Flag;
+use crate::flags::FLAGS;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+  Bash,
+  Zsh,
+  Fish,
+}
+
+/// `--generate`要输出的内容
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Generate {
+  Completions(Shell),
+  Man,
+}
+
+impl Generate {
+  pub fn parse(s: &str) -> Result<Generate, &'static str> {
+    match s {
+      "completions=bash" => Ok(Generate::Completions(Shell::Bash)),
+      "completions=zsh" => Ok(Generate::Completions(Shell::Zsh)),
+      "completions=fish" => Ok(Generate::Completions(Shell::Fish)),
+      "man" => Ok(Generate::Man),
+      _ => Err("Generate target must be completions=bash, completions=zsh, completions=fish or man"),
+    }
+  }
+
+  pub fn render(&self) -> String {
+    match self {
+      Generate::Completions(Shell::Bash) => bash(),
+      Generate::Completions(Shell::Zsh) => zsh(),
+      Generate::Completions(Shell::Fish) => fish(),
+      Generate::Man => man(),
+    }
+  }
+}
+
+fn bash() -> String {
+  let names: Vec<String> = FLAGS.iter().map(|f| format!("--{}", f.name)).collect();
+  let mut out = String::new();
+  out.push_str("_minigrep() {\n");
+  out.push_str("  local cur prev\n");
+  out.push_str("  cur=\"${COMP_WORDS[COMP_CWORD]}\"\n");
+  out.push_str("  prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+  out.push_str("  case \"$prev\" in\n");
+  for flag in FLAGS.iter().filter(|f| f.takes_value()) {
+    if flag.choices.is_empty() {
+      out.push_str(&format!("    --{})\n      COMPREPLY=()\n      return\n      ;;\n", flag.name));
+    } else {
+      out.push_str(&format!(
+        "    --{})\n      COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n      return\n      ;;\n",
+        flag.name,
+        flag.choices.join(" ")
+      ));
+    }
+  }
+  out.push_str("  esac\n");
+  out.push_str("  if [[ \"$cur\" == -* ]]; then\n");
+  out.push_str(&format!(
+    "    COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n",
+    names.join(" ")
+  ));
+  out.push_str("    return\n");
+  out.push_str("  fi\n");
+  out.push_str("  COMPREPLY=($(compgen -f -- \"$cur\"))\n");
+  out.push_str("}\n");
+  out.push_str("complete -o filenames -F _minigrep minigrep\n");
+  out
+}
+
+fn zsh() -> String {
+  let mut out = String::new();
+  out.push_str("#compdef minigrep\n\n");
+  out.push_str("_arguments -s \\\n");
+  for flag in FLAGS {
+    let help = zsh_escape(flag.help);
+    let spec = match flag.value {
+      None => format!("'--{}[{}]'", flag.name, help),
+      Some(value) => format!(
+        "'--{}=[{}]:{}:{}'",
+        flag.name,
+        help,
+        value,
+        if flag.choices.is_empty() {
+          " ".to_string()
+        } else {
+          format!("({})", flag.choices.join(" "))
+        }
+      ),
+    };
+    out.push_str(&format!("  {} \\\n", spec));
+  }
+  out.push_str("  '1:query: ' \\\n");
+  out.push_str("  '*:file:_files'\n");
+  out
+}
+
+// _arguments的说明文字中 [ ] : 和单引号需要转义
+fn zsh_escape(s: &str) -> String {
+  s.replace('\'', "'\\''")
+    .replace('[', "\\[")
+    .replace(']', "\\]")
+    .replace(':', "\\:")
+}
+
+fn fish() -> String {
+  let mut out = String::new();
+  for flag in FLAGS {
+    let mut line = format!("complete -c minigrep -l {}", flag.name);
+    if flag.takes_value() {
+      if flag.choices.is_empty() {
+        line.push_str(" -r");
+      } else {
+        line.push_str(&format!(" -x -a '{}'", flag.choices.join(" ")));
+      }
+    }
+    line.push_str(&format!(" -d '{}'", flag.help.replace('\'', "\\'")));
+    out.push_str(&line);
+    out.push('\n');
+  }
+  out
+}
+
+fn man() -> String {
+  let mut out = String::new();
+  out.push_str(&format!(
+    ".TH MINIGREP 1 \"\" \"minigrep {}\" \"User Commands\"\n",
+    env!("CARGO_PKG_VERSION")
+  ));
+  out.push_str(".SH NAME\n");
+  out.push_str("minigrep \\- search files for lines matching a query\n");
+  out.push_str(".SH SYNOPSIS\n");
+  out.push_str(".B minigrep\n");
+  out.push_str("[\\fIOPTIONS\\fR] \\fIQUERY\\fR \\fIFILE\\fR...\n");
+  out.push_str(".br\n");
+  out.push_str(".B minigrep\n");
+  out.push_str("[\\fIOPTIONS\\fR] \\fB\\-\\-expr\\fR \\fIEXPR\\fR \\fIFILE\\fR...\n");
+  out.push_str(".SH DESCRIPTION\n");
+  out.push_str(
+    "Print the lines of each FILE that contain QUERY. Directories are searched recursively. \
+     When more than one file is searched, each line is prefixed with its path.\n",
+  );
+  out.push_str(".SH OPTIONS\n");
+  for flag in FLAGS {
+    out.push_str(".TP\n");
+    out.push_str(&man_flag(flag));
+    out.push_str(&roff_escape(flag.help));
+    if !flag.choices.is_empty() {
+      out.push_str(&format!(
+        ". One of: {}",
+        roff_escape(&flag.choices.join(", "))
+      ));
+    }
+    out.push_str(".\n");
+  }
+  out.push_str(".SH ENVIRONMENT\n");
+  out.push_str(".TP\n");
+  out.push_str(".B CASE_INSENSITIVE\n");
+  out.push_str("When set, queries and expressions match regardless of case.\n");
+  out.push_str(".SH EXIT STATUS\n");
+  out.push_str("0 on success, 1 when the arguments are invalid or a file cannot be read.\n");
+  out
+}
+
+fn man_flag(flag: &Flag) -> String {
+  match flag.value {
+    Some(value) => format!("\\fB\\-\\-{}\\fR \\fI{}\\fR\n", roff_escape(flag.name), value),
+    None => format!("\\fB\\-\\-{}\\fR\n", roff_escape(flag.name)),
+  }
+}
+
+fn roff_escape(s: &str) -> String {
+  s.replace('\\', "\\e").replace('-', "\\-")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn every_flag_is_documented() {
+    let outputs = [
+      Generate::Completions(Shell::Bash).render(),
+      Generate::Completions(Shell::Zsh).render(),
+      Generate::Completions(Shell::Fish).render(),
+      Generate::Man.render(),
+    ];
+    for flag in FLAGS {
+      assert!(outputs[0].contains(&format!("--{}", flag.name)));
+      assert!(outputs[1].contains(&format!("'--{}", flag.name)));
+      assert!(outputs[2].contains(&format!("-l {} ", flag.name)));
+      assert!(outputs[3].contains(&format!("\\-\\-{}", roff_escape(flag.name))));
+    }
+  }
+
+  #[test]
+  fn choices_are_completed() {
+    assert!(bash().contains("compgen -W \"path mtime matches\""));
+    assert!(zsh().contains("'--sort=[Order the searched files by path, modification time or match count]:KEY:(path mtime matches)'"));
+    assert!(fish().contains("complete -c minigrep -l sort -x -a 'path mtime matches'"));
+  }
+}
@@ -1,55 +1,252 @@
 use std::env;
 use std::error::Error;
 use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use expr::Expr;
+use field::FieldOptions;
+use field::Format;
+use field::Record;
+use field::Selector;
+use flags::Opt;
+use generate::Generate;
+use report::FileMatches;
+use report::SortKey;
+use report::Stats;
+
+pub mod expr;
+pub mod field;
+pub mod flags;
+pub mod generate;
+pub mod report;
 
 pub struct Config {
   pub query: String,
-  pub filename: String,
+  // 要搜索的文件, 目录会被递归遍历
+  pub filenames: Vec<String>,
   pub case_sensitive: bool,
+  // 布尔查询表达式, 给出`--expr`时取代query, 此时query保存表达式原文
+  pub expr: Option<Expr>,
+  // 结构化字段搜索, 为None时按整行搜索
+  pub fields: Option<FieldOptions>,
+  // 多文件时的输出顺序, 为None时按命令行给出的顺序
+  pub sort: Option<SortKey>,
+  // 合并相同的匹配行并输出次数
+  pub unique: bool,
+  // 结束时输出统计信息
+  pub stats: bool,
+  // 只输出补全脚本或man手册, 此时不需要query和文件
+  pub generate: Option<Generate>,
 }
 
 impl Config {
   // &'static str代表字符串字面量类型,这里是指Err中的'not enough arguments'
-  pub fn new(mut args: env::Args) -> Result<Config, &'static str> {
+  pub fn new(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
     args.next();
-    let query = match args.next() {
+
+    let mut positionals = Vec::new();
+    let mut field = None;
+    let mut delimiter = None;
+    let mut select = Vec::new();
+    let mut expr = None;
+    let mut sort = None;
+    let mut unique = false;
+    let mut stats = false;
+    let mut generate = None;
+
+    while let Some(arg) = args.next() {
+      if !arg.starts_with("--") {
+        positionals.push(arg);
+        continue;
+      }
+      // 同时支持`--flag value`和`--flag=value`两种写法
+      let (name, inline) = match arg.find('=') {
+        Some(i) => (&arg[2..i], Some(arg[i + 1..].to_string())),
+        None => (&arg[2..], None),
+      };
+      let flag = match flags::find(name) {
+        Some(flag) => flag,
+        None => return Err("Unknown option"),
+      };
+      let value = if flag.takes_value() {
+        match inline.or_else(|| args.next()) {
+          Some(value) => value,
+          None => return Err("Option is missing its value"),
+        }
+      } else if inline.is_some() {
+        return Err("Option does not take a value");
+      } else {
+        String::new()
+      };
+      match flag.opt {
+        Opt::Unique => unique = true,
+        Opt::Stats => stats = true,
+        Opt::Field => field = Some(Selector::Name(value)),
+        Opt::Column => field = Some(Selector::Column(field::parse_column(&value)?)),
+        Opt::Delimiter => delimiter = Some(field::parse_delimiter(&value)?),
+        Opt::Expr => expr = Some(value),
+        Opt::Sort => sort = Some(SortKey::parse(&value)?),
+        Opt::Generate => generate = Some(Generate::parse(&value)?),
+        Opt::Select => {
+          for s in value.split(',') {
+            select.push(Selector::parse(s)?);
+          }
+        }
+      }
+    }
+
+    if generate.is_some() {
+      return Ok(Config {
+        query: String::new(),
+        filenames: Vec::new(),
+        case_sensitive: true,
+        expr: None,
+        fields: None,
+        sort: None,
+        unique: false,
+        stats: false,
+        generate,
+      });
+    }
+
+    let mut positionals = positionals.into_iter();
+    let query = match expr.clone().or_else(|| positionals.next()) {
       Some(arg) => arg,
       None => return Err("Didn't get a query string"),
     };
-    let filename = match args.next() {
-      Some(arg) => arg,
-      None => return Err("Didn't get a file name"),
-    };
+    let filenames: Vec<String> = positionals.collect();
+    if filenames.is_empty() {
+      return Err("Didn't get a file name");
+    }
     // env::var函数会返回一个Result作为结果
     // 只有在环境变量被设置时，该结果才会是包含环境变量值的Ok变体
     // 而在环境变量未被设置时，该结果则会是一个Err变体
     let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+    let expr = match expr {
+      Some(source) => Some(Expr::parse(&source)?),
+      None => None,
+    };
+
+    // 指定了分隔符时按CSV/TSV解析, 否则`--column`默认按逗号分隔, `--field`按logfmt解析
+    let format = match (delimiter, &field) {
+      (Some(d), _) => Some(Format::Delimited(d)),
+      (None, Some(Selector::Column(_))) => Some(Format::Delimited(',')),
+      (None, Some(Selector::Name(_))) => Some(Format::Logfmt),
+      (None, None) if !select.is_empty() => Some(Format::Logfmt),
+      (None, None) => None,
+    };
+    let fields = format.map(|format| FieldOptions {
+      format,
+      field,
+      select,
+    });
 
     Ok(Config {
       query,
-      filename,
+      filenames,
       case_sensitive,
+      expr,
+      fields,
+      sort,
+      unique,
+      stats,
+      generate,
     })
   }
 }
 
 // Box<dyn Error> => trait对象
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-  // ? 运算符取代了expect
-  // ? 运算符可以将错误值返回给函数的调 用者来进行处理。
-  let contents = fs::read_to_string(config.filename)?;
+  if let Some(target) = config.generate {
+    print!("{}", target.render());
+    return Ok(());
+  }
+
+  let start = Instant::now();
+  let paths = report::collect_paths(&config.filenames)?;
+  let mut stats = Stats::default();
+  let mut files = Vec::with_capacity(paths.len());
 
-  let results = if config.case_sensitive {
-    search(&config.query, &contents)
+  for (path, walked) in paths {
+    // ? 运算符取代了expect
+    // ? 运算符可以将错误值返回给函数的调 用者来进行处理。
+    let contents = match fs::read_to_string(&path) {
+      Ok(contents) => contents,
+      // 遍历目录时跳过二进制等非UTF-8文件
+      Err(e) if walked && e.kind() == io::ErrorKind::InvalidData => continue,
+      // 与grep一样, 目录中个别文件读不了时给出警告并继续搜索
+      Err(e) if walked => {
+        eprintln!("minigrep: {}: {}", path.display(), e);
+        continue;
+      }
+      Err(e) => return Err(e.into()),
+    };
+    let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+    let lines = search_contents(&config, &contents);
+
+    stats.files += 1;
+    stats.bytes += contents.len();
+    stats.matches += lines.len();
+    files.push(FileMatches {
+      path,
+      modified,
+      lines,
+    });
+  }
+
+  if let Some(key) = config.sort {
+    report::sort(&mut files, key);
+  }
+
+  if config.unique {
+    for (count, line) in report::unique(&files) {
+      println!("{:>7} {}", count, line);
+    }
   } else {
-    search_case_insensitive(&config.query, &contents)
-  };
-  for line in results {
-    println!("{}", line);
+    // 与grep一样, 搜索多个文件时在每行前加上文件路径
+    let with_path = files.len() > 1 ||
+      config
+        .filenames
+        .iter()
+        .any(|f| Path::new(f).is_dir());
+    for file in &files {
+      for line in &file.lines {
+        if with_path {
+          println!("{}:{}", file.path.display(), line);
+        } else {
+          println!("{}", line);
+        }
+      }
+    }
+  }
+
+  if config.stats {
+    stats.elapsed = start.elapsed();
+    println!();
+    println!("{}", stats);
   }
 
   Ok(())
 }
+
+// 按配置在一个文件的内容中搜索
+fn search_contents(config: &Config, contents: &str) -> Vec<String> {
+  if let Some(options) = &config.fields {
+    return search_fields(options, contents, line_matcher(config));
+  }
+
+  let results = if let Some(expr) = &config.expr {
+    search_expr(expr, contents, config.case_sensitive)
+  } else if config.case_sensitive {
+    search(&config.query, contents)
+  } else {
+    search_case_insensitive(&config.query, contents)
+  };
+  results.into_iter().map(String::from).collect()
+}
+
 // 显式生命周期'a
 // 生命周期参数指定了哪一个参数的生命周期会和返回值的生命周期产生关联。
 pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
@@ -83,10 +280,107 @@ pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a st
     .collect()
 }
 
+/// 按表达式搜索, 每一行都要对表达式中的所有匹配器求值
+pub fn search_expr<'a>(expr: &Expr, contents: &'a str, case_sensitive: bool) -> Vec<&'a str> {
+  if case_sensitive {
+    return contents.lines().filter(|line| expr.matches(line)).collect();
+  }
+  let expr = expr.to_lowercase();
+  contents
+    .lines()
+    .filter(|line| expr.matches(&line.to_lowercase()))
+    .collect()
+}
+
+// 根据配置构造判断一段文本是否命中的闭包
+fn line_matcher(config: &Config) -> Box<dyn Fn(&str) -> bool> {
+  let case_sensitive = config.case_sensitive;
+  match &config.expr {
+    Some(expr) if case_sensitive => {
+      let expr = expr.clone();
+      Box::new(move |text| expr.matches(text))
+    }
+    Some(expr) => {
+      let expr = expr.to_lowercase();
+      Box::new(move |text| expr.matches(&text.to_lowercase()))
+    }
+    None if case_sensitive => {
+      let query = config.query.clone();
+      Box::new(move |text| text.contains(&query))
+    }
+    None => {
+      let query = config.query.to_lowercase();
+      Box::new(move |text| text.to_lowercase().contains(&query))
+    }
+  }
+}
+
+/// 在结构化记录中搜索, 只在指定字段内匹配, 并按需只输出选中的字段
+pub fn search_fields(
+  options: &FieldOptions,
+  contents: &str,
+  is_match: impl Fn(&str) -> bool,
+) -> Vec<String> {
+  let mut lines = contents.lines();
+  let header = match options.format {
+    Format::Delimited(delimiter) if options.has_header() => lines
+      .next()
+      .map(|line| field::split_delimited(line, delimiter)),
+    _ => None,
+  };
+
+  lines
+    .filter_map(|line| {
+      let record = Record::parse(line, options.format, header.as_deref());
+      let haystack = match &options.field {
+        Some(selector) => record.get(selector)?,
+        None => line,
+      };
+      if !is_match(haystack) {
+        return None;
+      }
+      if options.select.is_empty() {
+        Some(line.to_string())
+      } else {
+        Some(record.render(&options.select, options.format))
+      }
+    })
+    .collect()
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
 
+  fn args(args: &[&str]) -> impl Iterator<Item = String> {
+    let mut all = vec!["minigrep".to_string()];
+    all.extend(args.iter().map(|a| a.to_string()));
+    all.into_iter()
+  }
+
+  #[test]
+  fn every_flag_parses() {
+    for flag in flags::FLAGS {
+      let name = format!("--{}", flag.name);
+      let value = match flag.opt {
+        _ if !flag.choices.is_empty() => flag.choices[0],
+        Opt::Column => "2",
+        Opt::Delimiter => ";",
+        _ => "level",
+      };
+      let mut list = vec![name.as_str()];
+      if flag.takes_value() {
+        list.push(value);
+      }
+      list.extend(["query", "file"]);
+      assert!(Config::new(args(&list)).is_ok(), "--{}", flag.name);
+
+      let inline = format!("--{}={}", flag.name, value);
+      let result = Config::new(args(&[&inline, "query", "file"]));
+      assert_eq!(flag.takes_value(), result.is_ok(), "{}", inline);
+    }
+  }
+
   #[test]
   fn case_sensitive() {
     let query = "duct";
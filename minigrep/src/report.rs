@@ -0,0 +1,151 @@
+//! 多文件搜索结果的收集、排序、去重与统计
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// `--sort`支持的排序方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+  /// 按路径字典序
+  Path,
+  /// 按修改时间, 旧的在前
+  Mtime,
+  /// 按匹配行数, 多的在前
+  Matches,
+}
+
+impl SortKey {
+  pub fn parse(s: &str) -> Result<SortKey, &'static str> {
+    match s {
+      "path" => Ok(SortKey::Path),
+      "mtime" => Ok(SortKey::Mtime),
+      "matches" => Ok(SortKey::Matches),
+      _ => Err("Sort key must be one of path, mtime, matches"),
+    }
+  }
+}
+
+/// 单个文件的搜索结果
+#[derive(Debug)]
+pub struct FileMatches {
+  pub path: PathBuf,
+  pub modified: Option<SystemTime>,
+  pub lines: Vec<String>,
+}
+
+/// 展开命令行给出的路径, 目录会被递归遍历
+///
+/// 返回的布尔值表示该文件是否来自目录遍历。目录项按文件名排序, 保证输出稳定。
+pub fn collect_paths(paths: &[String]) -> io::Result<Vec<(PathBuf, bool)>> {
+  let mut files = Vec::new();
+  for path in paths {
+    let path = Path::new(path);
+    if path.is_dir() {
+      walk(path, &mut files)?;
+    } else {
+      files.push((path.to_path_buf(), false));
+    }
+  }
+  Ok(files)
+}
+
+// 遍历时不跟随指向目录的符号链接, 否则指向上级目录的链接会无限递归
+fn walk(dir: &Path, files: &mut Vec<(PathBuf, bool)>) -> io::Result<()> {
+  let mut entries = fs::read_dir(dir)?
+    .map(|entry| entry.and_then(|e| Ok((e.path(), e.file_type()?))))
+    .collect::<io::Result<Vec<_>>>()?;
+  entries.sort_by(|a, b| a.0.cmp(&b.0));
+  for (path, file_type) in entries {
+    if file_type.is_dir() {
+      // 子目录读不了时同样只给出警告
+      if let Err(e) = walk(&path, files) {
+        eprintln!("minigrep: {}: {}", path.display(), e);
+      }
+    } else if file_type.is_symlink() && path.is_dir() {
+      continue;
+    } else {
+      files.push((path, true));
+    }
+  }
+  Ok(())
+}
+
+pub fn sort(files: &mut [FileMatches], key: SortKey) {
+  // sort_by与sort_by_key都是稳定排序, 相等的元素保持原有顺序
+  match key {
+    SortKey::Path => files.sort_by(|a, b| a.path.cmp(&b.path)),
+    SortKey::Mtime => files.sort_by_key(|f| f.modified),
+    SortKey::Matches => files.sort_by_key(|f| std::cmp::Reverse(f.lines.len())),
+  }
+}
+
+/// 合并所有文件中相同的匹配行并计数, 效果类似`sort | uniq -c`
+pub fn unique(files: &[FileMatches]) -> Vec<(usize, &str)> {
+  let mut counts = BTreeMap::new();
+  for line in files.iter().flat_map(|f| f.lines.iter()) {
+    *counts.entry(line.as_str()).or_insert(0) += 1;
+  }
+  counts.into_iter().map(|(line, count)| (count, line)).collect()
+}
+
+/// `--stats`输出的统计信息
+#[derive(Debug, Default)]
+pub struct Stats {
+  pub files: usize,
+  pub bytes: usize,
+  pub matches: usize,
+  pub elapsed: Duration,
+}
+
+impl fmt::Display for Stats {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "{} files scanned", self.files)?;
+    writeln!(f, "{} bytes read", self.bytes)?;
+    writeln!(f, "{} matches", self.matches)?;
+    write!(f, "{:.6} seconds elapsed", self.elapsed.as_secs_f64())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn file(path: &str, secs: u64, lines: &[&str]) -> FileMatches {
+    FileMatches {
+      path: PathBuf::from(path),
+      modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+      lines: lines.iter().map(|l| l.to_string()).collect(),
+    }
+  }
+
+  #[test]
+  fn sort_by_key() {
+    let mut files = vec![
+      file("b", 1, &["x"]),
+      file("c", 3, &["x", "y", "z"]),
+      file("a", 2, &["x", "y"]),
+    ];
+    let order = |files: &[FileMatches]| -> Vec<String> {
+      files.iter().map(|f| f.path.display().to_string()).collect()
+    };
+
+    sort(&mut files, SortKey::Path);
+    assert_eq!(vec!["a", "b", "c"], order(&files));
+    sort(&mut files, SortKey::Mtime);
+    assert_eq!(vec!["b", "a", "c"], order(&files));
+    sort(&mut files, SortKey::Matches);
+    assert_eq!(vec!["c", "a", "b"], order(&files));
+  }
+
+  #[test]
+  fn unique_counts() {
+    let files = vec![file("a", 0, &["y", "x"]), file("b", 0, &["x"])];
+    assert_eq!(vec![(2, "x"), (1, "y")], unique(&files));
+  }
+}
@@ -0,0 +1,414 @@
+//! 驱动编译后的minigrep二进制文件, 在临时目录中的测试数据上验证输出和退出码
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::SystemTime;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// 临时目录, 离开作用域时删除
+struct Fixture {
+  root: PathBuf,
+}
+
+impl Fixture {
+  fn new() -> Fixture {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let root = env::temp_dir().join(format!("minigrep-test-{}-{}", std::process::id(), id));
+    fs::create_dir_all(&root).unwrap();
+    Fixture { root }
+  }
+
+  fn file(&self, name: &str, contents: &str) -> &Fixture {
+    let path = self.root.join(name);
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, contents).unwrap();
+    self
+  }
+
+  fn bytes(&self, name: &str, contents: &[u8]) -> &Fixture {
+    fs::write(self.root.join(name), contents).unwrap();
+    self
+  }
+
+  fn touch(&self, name: &str, secs: u64) -> &Fixture {
+    let file = File::options().write(true).open(self.root.join(name)).unwrap();
+    file
+      .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+      .unwrap();
+    self
+  }
+
+  fn path(&self, name: &str) -> String {
+    self.root.join(name).display().to_string()
+  }
+
+  fn run(&self, args: &[&str]) -> Output {
+    minigrep(&self.root, args, false)
+  }
+
+  fn run_insensitive(&self, args: &[&str]) -> Output {
+    minigrep(&self.root, args, true)
+  }
+}
+
+impl Drop for Fixture {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.root);
+  }
+}
+
+fn minigrep(dir: &Path, args: &[&str], case_insensitive: bool) -> Output {
+  let mut command = Command::new(env!("CARGO_BIN_EXE_minigrep"));
+  command.current_dir(dir).args(args);
+  if case_insensitive {
+    command.env("CASE_INSENSITIVE", "1");
+  } else {
+    command.env_remove("CASE_INSENSITIVE");
+  }
+  command.output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+  String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+  String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+fn assert_success(output: &Output, expected: &str) {
+  assert_eq!("", stderr(output));
+  assert_eq!(expected, stdout(output));
+  assert_eq!(Some(0), output.status.code());
+}
+
+fn assert_usage_error(output: &Output, message: &str) {
+  assert_eq!("", stdout(output));
+  assert_eq!(format!("Problem parsing arguments: {}\n", message), stderr(output));
+  assert_eq!(Some(1), output.status.code());
+}
+
+const POEM: &str = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.
+";
+
+#[test]
+fn plain_search() {
+  let fixture = Fixture::new();
+  fixture.file("poem.txt", POEM);
+  assert_success(&fixture.run(&["ust", "poem.txt"]), "Rust:\nTrust me.\n");
+  assert_success(&fixture.run(&["nothing", "poem.txt"]), "");
+}
+
+#[test]
+fn case_insensitive_env() {
+  let fixture = Fixture::new();
+  fixture.file("poem.txt", POEM);
+  assert_success(&fixture.run(&["RUST", "poem.txt"]), "");
+  assert_success(
+    &fixture.run_insensitive(&["RUST", "poem.txt"]),
+    "Rust:\nTrust me.\n",
+  );
+}
+
+#[test]
+fn missing_arguments() {
+  let fixture = Fixture::new();
+  assert_usage_error(&fixture.run(&[]), "Didn't get a query string");
+  assert_usage_error(&fixture.run(&["query"]), "Didn't get a file name");
+  assert_usage_error(&fixture.run(&["--field"]), "Option is missing its value");
+}
+
+#[test]
+fn invalid_options() {
+  let fixture = Fixture::new();
+  assert_usage_error(&fixture.run(&["--bogus", "x", "q", "f"]), "Unknown option");
+  assert_usage_error(
+    &fixture.run(&["--column", "0", "q", "f"]),
+    "Column must be a positive number",
+  );
+  assert_usage_error(
+    &fixture.run(&["--delimiter", "ab", "q", "f"]),
+    "Delimiter must be a single character",
+  );
+  assert_usage_error(
+    &fixture.run(&["--sort", "size", "q", "f"]),
+    "Sort key must be one of path, mtime, matches",
+  );
+  assert_usage_error(
+    &fixture.run(&["--select", "a,,b", "q", "f"]),
+    "Field selector must not be empty",
+  );
+}
+
+#[test]
+fn missing_file() {
+  let fixture = Fixture::new();
+  let output = fixture.run(&["q", "missing.txt"]);
+  assert_eq!("", stdout(&output));
+  assert!(stderr(&output).starts_with("Application error: "));
+  assert_eq!(Some(1), output.status.code());
+}
+
+#[test]
+fn logfmt_field() {
+  let fixture = Fixture::new();
+  fixture.file(
+    "app.log",
+    "level=error msg=\"read timeout\" id=1\nlevel=info msg=\"error budget ok\" id=2\n",
+  );
+  assert_success(
+    &fixture.run(&["--field", "level", "error", "app.log"]),
+    "level=error msg=\"read timeout\" id=1\n",
+  );
+  assert_success(
+    &fixture.run(&["--field=msg", "error", "app.log", "--select", "id,level"]),
+    "id=2 level=info\n",
+  );
+}
+
+#[test]
+fn csv_columns_and_header() {
+  let fixture = Fixture::new();
+  fixture.file(
+    "data.csv",
+    "name,level,msg\nbob,error,\"timeout, again\"\nann,info,timeout\n",
+  );
+  assert_success(
+    &fixture.run(&["--column", "2", "err", "data.csv"]),
+    "bob,error,\"timeout, again\"\n",
+  );
+  assert_success(
+    &fixture.run(&[
+      "--delimiter",
+      ",",
+      "--field",
+      "msg",
+      "timeout",
+      "data.csv",
+      "--select",
+      "msg,1",
+    ]),
+    "\"timeout, again\",bob\ntimeout,ann\n",
+  );
+}
+
+#[test]
+fn tsv_delimiter() {
+  let fixture = Fixture::new();
+  fixture.file("data.tsv", "a\tb c\tx\nd\te\tb\n");
+  assert_success(
+    &fixture.run(&["--delimiter", "tab", "--column", "3", "b", "data.tsv"]),
+    "d\te\tb\n",
+  );
+  assert_success(
+    &fixture.run(&["--delimiter", "\\t", "--select", "2", "x", "data.tsv"]),
+    "b c\n",
+  );
+}
+
+#[test]
+fn boolean_expression() {
+  let fixture = Fixture::new();
+  fixture.file(
+    "app.log",
+    "read timeout\nread timeout, retry 1\nTIMEOUT ok\nfine\n",
+  );
+  assert_success(
+    &fixture.run(&["--expr", "timeout and not retry", "app.log"]),
+    "read timeout\n",
+  );
+  assert_success(
+    &fixture.run_insensitive(&["app.log", "--expr", "(timeout or fine) and not 'retry'"]),
+    "read timeout\nTIMEOUT ok\nfine\n",
+  );
+  assert_usage_error(
+    &fixture.run(&["--expr", "timeout and", "app.log"]),
+    "Unexpected end of expression",
+  );
+  assert_usage_error(
+    &fixture.run(&["--expr", "(a or b", "app.log"]),
+    "Missing closing parenthesis in expression",
+  );
+}
+
+#[test]
+fn expression_inside_field() {
+  let fixture = Fixture::new();
+  fixture.file("app.log", "level=error msg=retry\nlevel=info msg=error\n");
+  assert_success(
+    &fixture.run(&["--field", "level", "--expr", "error or warn", "app.log"]),
+    "level=error msg=retry\n",
+  );
+}
+
+#[test]
+fn multiple_files_and_directories() {
+  let fixture = Fixture::new();
+  fixture
+    .file("b.txt", "err b\n")
+    .file("a.txt", "ok\nerr a\n")
+    .file("dir/sub/c.txt", "err c\n");
+  assert_success(
+    &fixture.run(&["err", "b.txt", "a.txt"]),
+    "b.txt:err b\na.txt:err a\n",
+  );
+  assert_success(
+    &fixture.run(&["err", "dir"]),
+    &format!("{}:err c\n", Path::new("dir").join("sub").join("c.txt").display()),
+  );
+}
+
+#[test]
+fn directory_walk_skips_binary_files() {
+  let fixture = Fixture::new();
+  fixture
+    .file("dir/a.txt", "err\n")
+    .bytes("dir/b.bin", b"\xff\xfe err");
+  assert_success(
+    &fixture.run(&["err", "dir"]),
+    &format!("{}:err\n", Path::new("dir").join("a.txt").display()),
+  );
+
+  let output = fixture.run(&["err", &fixture.path("dir/b.bin")]);
+  assert!(stderr(&output).starts_with("Application error: "));
+  assert_eq!(Some(1), output.status.code());
+}
+
+#[cfg(unix)]
+#[test]
+fn directory_walk_skips_symlinked_directories() {
+  let fixture = Fixture::new();
+  fixture
+    .file("dir/a.txt", "err\n")
+    .file("other/b.txt", "err\n");
+  std::os::unix::fs::symlink("..", fixture.root.join("dir/parent")).unwrap();
+  std::os::unix::fs::symlink("../other", fixture.root.join("dir/other")).unwrap();
+  assert_success(
+    &fixture.run(&["err", "dir"]),
+    &format!("{}:err\n", Path::new("dir").join("a.txt").display()),
+  );
+}
+
+#[cfg(unix)]
+#[test]
+fn directory_walk_warns_and_continues() {
+  let fixture = Fixture::new();
+  fixture.file("dir/a.txt", "err\n").file("dir/c.txt", "err\n");
+  std::os::unix::fs::symlink("missing.txt", fixture.root.join("dir/b.txt")).unwrap();
+  let output = fixture.run(&["err", "dir"]);
+  let dir = Path::new("dir");
+  assert_eq!(
+    format!(
+      "{}:err\n{}:err\n",
+      dir.join("a.txt").display(),
+      dir.join("c.txt").display()
+    ),
+    stdout(&output)
+  );
+  assert!(stderr(&output).starts_with(&format!("minigrep: {}: ", dir.join("b.txt").display())));
+  assert_eq!(Some(0), output.status.code());
+}
+
+#[test]
+fn sort_orders() {
+  let fixture = Fixture::new();
+  fixture
+    .file("b.txt", "x\n")
+    .file("c.txt", "x\nx\nx\n")
+    .file("a.txt", "x\nx\n")
+    .touch("b.txt", 100)
+    .touch("c.txt", 300)
+    .touch("a.txt", 200);
+  let args = |key| ["--sort", key, "x", "b.txt", "c.txt", "a.txt"];
+
+  assert_success(
+    &fixture.run(&args("path")),
+    "a.txt:x\na.txt:x\nb.txt:x\nc.txt:x\nc.txt:x\nc.txt:x\n",
+  );
+  assert_success(
+    &fixture.run(&args("mtime")),
+    "b.txt:x\na.txt:x\na.txt:x\nc.txt:x\nc.txt:x\nc.txt:x\n",
+  );
+  assert_success(
+    &fixture.run(&args("matches")),
+    "c.txt:x\nc.txt:x\nc.txt:x\na.txt:x\na.txt:x\nb.txt:x\n",
+  );
+}
+
+#[test]
+fn unique_counts() {
+  let fixture = Fixture::new();
+  fixture
+    .file("a.txt", "err b\nerr a\n")
+    .file("b.txt", "err a\nok\n");
+  assert_success(
+    &fixture.run(&["--unique", "err", "a.txt", "b.txt"]),
+    "      2 err a\n      1 err b\n",
+  );
+}
+
+#[test]
+fn stats_summary() {
+  let fixture = Fixture::new();
+  fixture.file("a.txt", "err\nok\n").file("b.txt", "err err\n");
+  let output = fixture.run(&["--stats", "err", "a.txt", "b.txt"]);
+  assert_eq!(Some(0), output.status.code());
+
+  let stdout = stdout(&output);
+  let lines: Vec<&str> = stdout.lines().collect();
+  assert_eq!(
+    vec![
+      "a.txt:err",
+      "b.txt:err err",
+      "",
+      "2 files scanned",
+      "15 bytes read",
+      "2 matches",
+    ],
+    lines[..6]
+  );
+  assert!(lines[6].ends_with(" seconds elapsed"));
+  assert_eq!(7, lines.len());
+}
+
+#[test]
+fn generate_completions_and_man() {
+  let fixture = Fixture::new();
+  for target in &["completions=bash", "completions=zsh", "completions=fish"] {
+    let output = fixture.run(&["--generate", target]);
+    assert_eq!("", stderr(&output));
+    assert_eq!(Some(0), output.status.code());
+    let script = stdout(&output);
+    for flag in &["field", "column", "delimiter", "select", "expr", "sort", "unique", "stats"] {
+      assert!(script.contains(flag), "{} is missing {}", target, flag);
+    }
+  }
+
+  let output = fixture.run(&["--generate=man"]);
+  assert_eq!(Some(0), output.status.code());
+  assert!(stdout(&output).starts_with(".TH MINIGREP 1"));
+
+  assert_usage_error(
+    &fixture.run(&["--generate", "completions=tcsh"]),
+    "Generate target must be completions=bash, completions=zsh, completions=fish or man",
+  );
+  assert_usage_error(
+    &fixture.run(&["--stats=yes", "q", "f"]),
+    "Option does not take a value",
+  );
+}
@@ -0,0 +1,289 @@
+//! 访问日志
+//!
+//! 每个请求处理完之后写一行, 支持Common/Combined Log Format和JSON lines。
+//! 写入文件时可以调用[`AccessLog::reopen`]重新打开文件, 配合logrotate等工具
+//! 在收到SIGHUP时切换日志文件。
+
+use std::fmt;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::date;
+
+/// 日志格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+  /// `127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] "GET / HTTP/1.1" 200 1234`
+  Common,
+  /// Common格式之后再加上Referer和User-Agent
+  Combined,
+  /// 每行一个JSON对象, 额外包含处理耗时
+  Json,
+}
+
+impl FromStr for LogFormat {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> Result<LogFormat, &'static str> {
+    match s.to_ascii_lowercase().as_str() {
+      "common" => Ok(LogFormat::Common),
+      "combined" => Ok(LogFormat::Combined),
+      "json" => Ok(LogFormat::Json),
+      _ => Err("unknown log format"),
+    }
+  }
+}
+
+/// 日志写到哪里
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogTarget {
+  Stdout,
+  /// 追加写入文件, 文件不存在时创建
+  File(PathBuf),
+}
+
+/// 一条访问记录
+#[derive(Debug, Clone)]
+pub struct Entry<'a> {
+  pub peer_addr: Option<SocketAddr>,
+  /// 开始处理请求的时间
+  pub time: SystemTime,
+  pub method: &'a str,
+  pub target: &'a str,
+  pub version: &'a str,
+  pub status: u16,
+  /// 发送的响应体字节数
+  pub bytes: u64,
+  /// 从读完请求到写完响应的耗时
+  pub duration: Duration,
+  pub referer: Option<&'a str>,
+  pub user_agent: Option<&'a str>,
+}
+
+impl<'a> Entry<'a> {
+  /// 按`format`格式化为一行, 不包括换行符
+  pub fn format(&self, format: LogFormat) -> String {
+    match format {
+      LogFormat::Common => self.common(),
+      LogFormat::Combined => format!(
+        "{} \"{}\" \"{}\"",
+        self.common(),
+        clf_escape(self.referer.unwrap_or("-")),
+        clf_escape(self.user_agent.unwrap_or("-"))
+      ),
+      LogFormat::Json => self.json(),
+    }
+  }
+
+  fn common(&self) -> String {
+    let host = match self.peer_addr {
+      Some(addr) => addr.ip().to_string(),
+      None => "-".to_string(),
+    };
+    let bytes = match self.bytes {
+      0 => "-".to_string(),
+      n => n.to_string(),
+    };
+    format!(
+      "{} - - [{}] \"{} {} {}\" {} {}",
+      host,
+      date::format_clf(self.time),
+      clf_escape(self.method),
+      clf_escape(self.target),
+      self.version,
+      self.status,
+      bytes
+    )
+  }
+
+  fn json(&self) -> String {
+    let optional = |value: Option<&str>| match value {
+      Some(value) => json_string(value),
+      None => "null".to_string(),
+    };
+    let peer_addr = self.peer_addr.map(|addr| addr.ip().to_string());
+    format!(
+      "{{\"time\":\"{}\",\"remote_addr\":{},\"method\":{},\"target\":{},\"version\":\"{}\",\"status\":{},\"bytes\":{},\"duration_ms\":{:.3},\"referer\":{},\"user_agent\":{}}}",
+      date::format_rfc3339(self.time),
+      optional(peer_addr.as_deref()),
+      json_string(self.method),
+      json_string(self.target),
+      self.version,
+      self.status,
+      self.bytes,
+      self.duration.as_secs_f64() * 1000.0,
+      optional(self.referer),
+      optional(self.user_agent)
+    )
+  }
+}
+
+/// 访问日志, 可以在多个worker之间共享, 每条记录整行写入
+pub struct AccessLog {
+  format: LogFormat,
+  target: LogTarget,
+  file: Mutex<Option<File>>,
+}
+
+impl AccessLog {
+  /// 创建访问日志, 写入文件时会立即打开文件
+  pub fn new(format: LogFormat, target: LogTarget) -> io::Result<AccessLog> {
+    let file = match &target {
+      LogTarget::Stdout => None,
+      LogTarget::File(path) => Some(open(path)?),
+    };
+    Ok(AccessLog {
+      format,
+      target,
+      file: Mutex::new(file),
+    })
+  }
+
+  /// 记录一个请求, 写入失败时只打印错误, 不影响请求处理
+  pub fn log(&self, entry: &Entry) {
+    let mut line = entry.format(self.format);
+    line.push('\n');
+    let mut file = self.file.lock().unwrap();
+    let result = match file.as_mut() {
+      Some(file) => file.write_all(line.as_bytes()),
+      None => io::stdout().lock().write_all(line.as_bytes()),
+    };
+    if let Err(e) = result {
+      eprintln!("Failed to write access log: {}", e);
+    }
+  }
+
+  /// 重新打开日志文件, 日志写到stdout时什么也不做
+  ///
+  /// 日志文件被改名或删除之后调用, 之后的记录会写到同一路径下的新文件中。
+  pub fn reopen(&self) -> io::Result<()> {
+    if let LogTarget::File(path) = &self.target {
+      let reopened = open(path)?;
+      *self.file.lock().unwrap() = Some(reopened);
+    }
+    Ok(())
+  }
+}
+
+impl fmt::Debug for AccessLog {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("AccessLog")
+      .field("format", &self.format)
+      .field("target", &self.target)
+      .finish()
+  }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+  OpenOptions::new().create(true).append(true).open(path)
+}
+
+// 日志字段放在双引号里, 转义引号、反斜杠和控制字符, 防止伪造日志行
+fn clf_escape(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+fn json_string(s: &str) -> String {
+  let mut out = String::with_capacity(s.len() + 2);
+  out.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs;
+  use std::time::UNIX_EPOCH;
+
+  use super::*;
+
+  fn entry() -> Entry<'static> {
+    Entry {
+      peer_addr: Some("127.0.0.1:51000".parse().unwrap()),
+      time: UNIX_EPOCH + Duration::from_secs(784111777),
+      method: "GET",
+      target: "/a \"b\"",
+      version: "HTTP/1.1",
+      status: 200,
+      bytes: 1234,
+      duration: Duration::from_micros(1500),
+      referer: None,
+      user_agent: Some("curl/8.0"),
+    }
+  }
+
+  #[test]
+  fn format_lines() {
+    assert_eq!(
+      "127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] \"GET /a \\\"b\\\" HTTP/1.1\" 200 1234 \"-\" \"curl/8.0\"",
+      entry().format(LogFormat::Combined)
+    );
+    assert_eq!(
+      "{\"time\":\"1994-11-06T08:49:37.000Z\",\"remote_addr\":\"127.0.0.1\",\"method\":\"GET\",\"target\":\"/a \\\"b\\\"\",\"version\":\"HTTP/1.1\",\"status\":200,\"bytes\":1234,\"duration_ms\":1.500,\"referer\":null,\"user_agent\":\"curl/8.0\"}",
+      entry().format(LogFormat::Json)
+    );
+    let empty = Entry {
+      bytes: 0,
+      peer_addr: None,
+      ..entry()
+    };
+    assert!(empty
+      .format(LogFormat::Common)
+      .starts_with("- - - [06/Nov/1994"));
+    assert!(empty.format(LogFormat::Common).ends_with(" 200 -"));
+  }
+
+  #[test]
+  fn reopen_after_rotation() {
+    let dir = env::temp_dir().join(format!("web-server-log-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("access.log");
+    let log = AccessLog::new(LogFormat::Common, LogTarget::File(path.clone())).unwrap();
+
+    log.log(&entry());
+    fs::rename(&path, dir.join("access.log.1")).unwrap();
+    log.log(&entry());
+    log.reopen().unwrap();
+    log.log(&entry());
+
+    assert_eq!(
+      2,
+      fs::read_to_string(dir.join("access.log.1"))
+        .unwrap()
+        .lines()
+        .count()
+    );
+    assert_eq!(1, fs::read_to_string(&path).unwrap().lines().count());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
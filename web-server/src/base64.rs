@@ -0,0 +1,81 @@
+//! 标准Base64编码(RFC 4648), 带`=`填充
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(data: &[u8]) -> String {
+  let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+  for chunk in data.chunks(3) {
+    let b = [
+      chunk[0],
+      chunk.get(1).copied().unwrap_or(0),
+      chunk.get(2).copied().unwrap_or(0),
+    ];
+    let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+    for i in 0..4 {
+      if i <= chunk.len() {
+        out.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+      } else {
+        out.push('=');
+      }
+    }
+  }
+  out
+}
+
+/// 解码, 长度不是4的倍数、包含非法字符或填充位置不对时返回None
+pub fn decode(s: &str) -> Option<Vec<u8>> {
+  let bytes = s.as_bytes();
+  if !bytes.len().is_multiple_of(4) {
+    return None;
+  }
+  let mut out = Vec::with_capacity(bytes.len() / 4 * 3);
+  for (i, chunk) in bytes.chunks(4).enumerate() {
+    let last = i == bytes.len() / 4 - 1;
+    let padding = chunk.iter().rev().take_while(|&&b| b == b'=').count();
+    if padding > 2 || (padding > 0 && !last) {
+      return None;
+    }
+    let mut n = 0u32;
+    for &b in &chunk[..4 - padding] {
+      n = (n << 6) | value(b)? as u32;
+    }
+    n <<= 6 * padding as u32;
+    out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+  }
+  Some(out)
+}
+
+fn value(b: u8) -> Option<u8> {
+  match b {
+    b'A'..=b'Z' => Some(b - b'A'),
+    b'a'..=b'z' => Some(b - b'a' + 26),
+    b'0'..=b'9' => Some(b - b'0' + 52),
+    b'+' => Some(62),
+    b'/' => Some(63),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn round_trip() {
+    let cases: &[(&str, &str)] = &[
+      ("", ""),
+      ("f", "Zg=="),
+      ("fo", "Zm8="),
+      ("foo", "Zm9v"),
+      ("foob", "Zm9vYg=="),
+      ("Aladdin:open sesame", "QWxhZGRpbjpvcGVuIHNlc2FtZQ=="),
+    ];
+    for (plain, encoded) in cases {
+      assert_eq!(*encoded, encode(plain.as_bytes()));
+      assert_eq!(Some(plain.as_bytes().to_vec()), decode(encoded));
+    }
+    assert_eq!(None, decode("Zg="));
+    assert_eq!(None, decode("Zg==Zg=="));
+    assert_eq!(None, decode("Z!=="));
+  }
+}
@@ -1,53 +1,104 @@
+use std::env;
 use std::fs;
-use std::io::prelude::*;
-use std::net::TcpListener;
-use std::net::TcpStream;
+use std::process;
 use std::thread;
 use std::time::Duration;
-use web_server::ThreadPool;
+use web_server::compression::Compression;
+use web_server::config;
+use web_server::middleware::Chain;
+use web_server::middleware::RequestId;
+use web_server::request::Request;
+use web_server::response::Response;
+use web_server::response::StatusCode;
+use web_server::router::Router;
+use web_server::server::Server;
+#[cfg(unix)]
+use web_server::signal;
+use web_server::static_files::StaticFiles;
+use web_server::websocket;
+use web_server::websocket::Message;
 
 fn main() {
-  let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-
-  let pool = ThreadPool::new(4);
+  let mut args: Vec<String> = env::args().skip(1).collect();
+  if args.iter().any(|a| a == "-h" || a == "--help") {
+    print!("{}", config::USAGE);
+    return;
+  }
+  // 只有这个程序使用的选项, 不交给ServerBuilder
+  let listing = args.iter().any(|a| a == "--static-listing");
+  args.retain(|a| a != "--static-listing");
+  let builder = Server::builder().apply_args(args).unwrap_or_else(|e| {
+    eprintln!("{}\n\n{}", e, config::USAGE);
+    process::exit(2);
+  });
 
-  for stream in listener.incoming().take(2) {
-    let stream = stream.unwrap();
+  let mut router = Router::new();
+  router
+    .get("/", |_: &mut Request| {
+      html_file(StatusCode::Ok, "welcome.html")
+    })
+    .get("/sleep", |_: &mut Request| {
+      thread::sleep(Duration::from_secs(5));
+      html_file(StatusCode::Ok, "welcome.html")
+    })
+    .get("/ws/echo", |request: &mut Request| {
+      websocket::upgrade(request, |mut ws| {
+        while let Ok(Some(message)) = ws.recv() {
+          let reply = match message {
+            Message::Text(_) | Message::Binary(_) => message,
+            _ => continue,
+          };
+          if ws.send(reply).is_err() {
+            break;
+          }
+        }
+      })
+    })
+    .get(
+      "/static/*",
+      StaticFiles::new("public")
+        .listing(listing)
+        .cache_control("public, max-age=60"),
+    )
+    .not_found(|_: &mut Request| html_file(StatusCode::NotFound, "404.html"));
 
-    pool.execute(|| {
-      handle_connection(stream);
-    });
+  let app = Chain::new(router)
+    .with(RequestId::new())
+    .with(Compression::new());
+  let server = builder.build(app).unwrap_or_else(|e| {
+    eprintln!("Failed to start server: {}", e);
+    process::exit(1);
+  });
+  eprintln!("Listening on {}", server.local_addr());
+  if let Some(addr) = server.tls_local_addr() {
+    eprintln!("Listening on {} (HTTPS)", addr);
   }
+  // 其他平台上没有信号, 只能直接结束进程
+  #[cfg(unix)]
+  let handle = server.handle();
+  #[cfg(unix)]
+  signal::notify(
+    &[signal::SIGHUP, signal::SIGINT, signal::SIGTERM],
+    move |signum| {
+      if signum != signal::SIGHUP {
+        handle.shutdown();
+      } else if let Err(e) = handle.reopen_access_log() {
+        eprintln!("Failed to reopen access log: {}", e);
+      }
+    },
+  )
+  .unwrap();
 
-  println!("Shutting down.");
+  server.run().unwrap();
+  eprintln!("Server stopped.");
 }
 
-fn handle_connection(mut stream: TcpStream) {
-  let mut buffer = [0; 512];
-
-  stream.read(&mut buffer).unwrap();
-
-  // “lossy”部分暗示了这个函数遇到无效UTF-8序列时的行为：它会用 （U+FFFD REPLACEMENT CHARACTER）来替换所有无效的序列。
-  // println!("Request: {}", String::from_utf8_lossy(&buffer[..]));
-
-  let get = b"GET / HTTP/1.1\r\n";
-  let sleep = b"GET /sleep HTTP/1.1\r\n";
-  let (status_line, filename) = if buffer.starts_with(get) {
-    ("HTTP/1.1 200 OK\r\n", "welcome.html")
-  } else if buffer.starts_with(sleep) {
-    thread::sleep(Duration::from_secs(5));
-    ("HTTP/1.1 200 OK\r\n", "welcome.html")
-  } else {
-    ("HTTP/1.1 404 NOT FOUND\r\n", "404.html")
-  };
-  let contents = fs::read_to_string(filename).unwrap();
-  let response = format!(
-    "{}Content-Length: {}\r\n\r\n{}",
-    status_line,
-    contents.len(),
-    contents
-  );
-
-  stream.write(response.as_bytes()).unwrap();
-  stream.flush().unwrap();
+fn html_file(status: StatusCode, filename: &str) -> Response {
+  match fs::read_to_string(filename) {
+    Ok(contents) => Response::html(status, contents),
+    Err(e) => {
+      eprintln!("Failed to read {}: {}", filename, e);
+      Response::text(status, status.reason())
+    }
+  }
 }
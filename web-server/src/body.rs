@@ -0,0 +1,150 @@
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+/// 响应体
+///
+/// 可以是内存中的字节, 也可以是一个按需读取的`Read`, 后者在写出时边读边发送,
+/// 不需要把整个响应体读入内存。
+pub enum Body {
+  Bytes(Vec<u8>),
+  Reader {
+    reader: Box<dyn Read + Send>,
+    /// 已知长度时使用`Content-Length`, 否则使用chunked编码
+    length: Option<u64>,
+  },
+}
+
+impl Body {
+  pub fn empty() -> Body {
+    Body::Bytes(Vec::new())
+  }
+
+  pub fn reader<R: Read + Send + 'static>(reader: R, length: Option<u64>) -> Body {
+    Body::Reader {
+      reader: Box::new(reader),
+      length,
+    }
+  }
+
+  /// 响应体的长度, 长度未知的流返回None
+  pub fn len(&self) -> Option<u64> {
+    match self {
+      Body::Bytes(bytes) => Some(bytes.len() as u64),
+      Body::Reader { length, .. } => *length,
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == Some(0)
+  }
+
+  /// 内存中的响应体, 流式响应体返回None
+  pub fn as_bytes(&self) -> Option<&[u8]> {
+    match self {
+      Body::Bytes(bytes) => Some(bytes),
+      Body::Reader { .. } => None,
+    }
+  }
+
+  /// 读出整个响应体
+  pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+    match self {
+      Body::Bytes(bytes) => Ok(bytes),
+      Body::Reader { mut reader, length } => {
+        let mut bytes = Vec::new();
+        match length {
+          Some(length) => reader.take(length).read_to_end(&mut bytes)?,
+          None => reader.read_to_end(&mut bytes)?,
+        };
+        Ok(bytes)
+      }
+    }
+  }
+
+  /// 写出响应体, chunked为true时使用chunked编码, 返回写出的响应体字节数(不含chunk的框架)
+  pub(crate) fn write_to<W: Write>(&mut self, w: &mut W, chunked: bool) -> io::Result<u64> {
+    match self {
+      Body::Bytes(bytes) if chunked => {
+        if !bytes.is_empty() {
+          write!(w, "{:x}\r\n", bytes.len())?;
+          w.write_all(bytes)?;
+          w.write_all(b"\r\n")?;
+        }
+        w.write_all(b"0\r\n\r\n")?;
+        Ok(bytes.len() as u64)
+      }
+      Body::Bytes(bytes) => {
+        w.write_all(bytes)?;
+        Ok(bytes.len() as u64)
+      }
+      Body::Reader { reader, length } => {
+        let mut reader: Box<dyn Read> = match length {
+          Some(length) => Box::new(reader.take(*length)),
+          None => Box::new(reader),
+        };
+        if !chunked {
+          return io::copy(&mut reader, w);
+        }
+        let mut written = 0;
+        let mut buf = [0; 8192];
+        loop {
+          let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+          };
+          write!(w, "{:x}\r\n", n)?;
+          w.write_all(&buf[..n])?;
+          w.write_all(b"\r\n")?;
+          // 流式响应体可能很久才产生下一段数据, 每段都及时发送出去
+          w.flush()?;
+          written += n as u64;
+        }
+        w.write_all(b"0\r\n\r\n")?;
+        Ok(written)
+      }
+    }
+  }
+}
+
+impl Default for Body {
+  fn default() -> Body {
+    Body::empty()
+  }
+}
+
+impl fmt::Debug for Body {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Body::Bytes(bytes) => write!(f, "Body::Bytes({} bytes)", bytes.len()),
+      Body::Reader { length, .. } => write!(f, "Body::Reader(length: {:?})", length),
+    }
+  }
+}
+
+impl From<Vec<u8>> for Body {
+  fn from(bytes: Vec<u8>) -> Body {
+    Body::Bytes(bytes)
+  }
+}
+
+impl From<String> for Body {
+  fn from(s: String) -> Body {
+    Body::Bytes(s.into_bytes())
+  }
+}
+
+impl From<&str> for Body {
+  fn from(s: &str) -> Body {
+    Body::Bytes(s.as_bytes().to_vec())
+  }
+}
+
+impl From<&[u8]> for Body {
+  fn from(bytes: &[u8]) -> Body {
+    Body::Bytes(bytes.to_vec())
+  }
+}
@@ -0,0 +1,584 @@
+//! 阻塞的HTTP/1.1客户端
+//!
+//! 用于集成测试和反向代理: 连接服务器、发送[`Request`]并解析出[`Response`]。
+//! 响应正常结束并且双方都允许持久连接时, 连接会放回连接池, 之后请求同一个地址时复用。
+//!
+//! 响应体总是完整读入内存, 不支持HTTPS。
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use web_server::client::Client;
+//!
+//! let client = Client::new();
+//! let response = client.get("http://127.0.0.1:7878/").unwrap();
+//! println!("{}", response.status.code());
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::header::HeaderMap;
+use crate::parser;
+use crate::parser::ParseError;
+use crate::request::Request;
+use crate::request::Version;
+use crate::response::Response;
+use crate::response::StatusCode;
+
+/// 请求失败的原因
+#[derive(Debug)]
+pub enum ClientError {
+  /// 无法解析的URL, 或者不是`http://`
+  InvalidUrl(String),
+  Io(io::Error),
+  /// 连接、读取或写入超时
+  Timeout,
+  /// 响应格式错误
+  Malformed(&'static str),
+  /// 响应的首部或响应体超过了限制
+  TooLarge,
+  /// 响应还没收完连接就被关闭了
+  UnexpectedEof,
+}
+
+impl fmt::Display for ClientError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ClientError::InvalidUrl(url) => write!(f, "invalid url: {}", url),
+      ClientError::Io(e) => write!(f, "io error: {}", e),
+      ClientError::Timeout => write!(f, "timed out"),
+      ClientError::Malformed(reason) => write!(f, "malformed response: {}", reason),
+      ClientError::TooLarge => write!(f, "response is too large"),
+      ClientError::UnexpectedEof => write!(f, "connection closed in the middle of a response"),
+    }
+  }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+  fn from(e: io::Error) -> ClientError {
+    match e.kind() {
+      io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ClientError::Timeout,
+      _ => ClientError::Io(e),
+    }
+  }
+}
+
+impl From<ParseError> for ClientError {
+  fn from(e: ParseError) -> ClientError {
+    match e {
+      ParseError::Malformed(reason) => ClientError::Malformed(reason),
+      ParseError::Io(e) => e.into(),
+      ParseError::UnexpectedEof => ClientError::UnexpectedEof,
+      ParseError::HeaderTooLarge | ParseError::BodyTooLarge => ClientError::TooLarge,
+      ParseError::Timeout => ClientError::Timeout,
+    }
+  }
+}
+
+/// HTTP/1.1客户端, 可以在多个线程之间共享
+pub struct Client {
+  connect_timeout: Duration,
+  read_timeout: Duration,
+  write_timeout: Duration,
+  idle_timeout: Duration,
+  max_idle_per_host: usize,
+  max_header_size: usize,
+  max_body_size: usize,
+  // 按地址保存的空闲连接, 最近放回的在最后
+  idle: Mutex<HashMap<String, Vec<Connection>>>,
+}
+
+impl Client {
+  pub fn new() -> Client {
+    Client {
+      connect_timeout: Duration::from_secs(5),
+      read_timeout: Duration::from_secs(30),
+      write_timeout: Duration::from_secs(30),
+      // 比服务器默认的5秒空闲超时短, 尽量不去复用服务器正要关闭的连接
+      idle_timeout: Duration::from_secs(4),
+      max_idle_per_host: 16,
+      max_header_size: 64 * 1024,
+      max_body_size: 10 * 1024 * 1024,
+      idle: Mutex::new(HashMap::new()),
+    }
+  }
+
+  pub fn connect_timeout(mut self, timeout: Duration) -> Client {
+    self.connect_timeout = timeout;
+    self
+  }
+
+  /// 等待响应时每次读取的超时
+  pub fn read_timeout(mut self, timeout: Duration) -> Client {
+    self.read_timeout = timeout;
+    self
+  }
+
+  pub fn write_timeout(mut self, timeout: Duration) -> Client {
+    self.write_timeout = timeout;
+    self
+  }
+
+  /// 空闲连接在连接池中保留的最长时间
+  pub fn idle_timeout(mut self, timeout: Duration) -> Client {
+    self.idle_timeout = timeout;
+    self
+  }
+
+  /// 每个地址最多保留的空闲连接数, 为0时不复用连接
+  pub fn max_idle_per_host(mut self, max: usize) -> Client {
+    self.max_idle_per_host = max;
+    self
+  }
+
+  /// 响应体的最大字节数, 超过时返回[`ClientError::TooLarge`]
+  pub fn max_body_size(mut self, size: usize) -> Client {
+    self.max_body_size = size;
+    self
+  }
+
+  /// 对`http://host[:port]/path`发送GET请求
+  pub fn get(&self, url: &str) -> Result<Response, ClientError> {
+    let (addr, host, target) = split_url(url)?;
+    let mut request = Request::new("GET", &target);
+    request.headers.insert("Host", host);
+    self.send(&addr, request)
+  }
+
+  /// 把`request`发送到`addr`(例如`127.0.0.1:8080`)并读取响应
+  ///
+  /// 没有`Host`首部时使用`addr`。从连接池取出的连接在收到任何响应之前就被关闭时,
+  /// 如果请求是幂等的, 会换一个连接重试。
+  pub fn send(&self, addr: &str, mut request: Request) -> Result<Response, ClientError> {
+    if !request.headers.contains("Host") {
+      request.headers.insert("Host", addr);
+    }
+    let mut raw = Vec::new();
+    request.write_to(&mut raw)?;
+    let close = request.headers.has_token("Connection", "close");
+
+    loop {
+      let (mut conn, reused) = match self.take_idle(addr) {
+        Some(conn) => (conn, true),
+        None => (self.connect(addr)?, false),
+      };
+      match self.exchange(&mut conn, &raw, &request.method) {
+        Ok((response, keep_alive)) => {
+          if keep_alive && !close && conn.buf.is_empty() {
+            self.put_idle(addr, conn);
+          }
+          return Ok(response);
+        }
+        // 服务器关闭空闲连接和我们复用它几乎同时发生
+        Err(e) if reused && conn.received == 0 && is_closed(&e) && is_idempotent(&request) => {
+          continue
+        }
+        Err(e) => return Err(e),
+      }
+    }
+  }
+
+  fn connect(&self, addr: &str) -> Result<Connection, ClientError> {
+    let mut last_error = None;
+    for socket_addr in addr.to_socket_addrs()? {
+      match TcpStream::connect_timeout(&socket_addr, self.connect_timeout) {
+        Ok(stream) => {
+          stream.set_read_timeout(Some(self.read_timeout))?;
+          stream.set_write_timeout(Some(self.write_timeout))?;
+          return Ok(Connection {
+            stream,
+            buf: Vec::new(),
+            received: 0,
+            idle_since: Instant::now(),
+          });
+        }
+        Err(e) => last_error = Some(e),
+      }
+    }
+    Err(match last_error {
+      Some(e) => e.into(),
+      None => ClientError::InvalidUrl(addr.to_string()),
+    })
+  }
+
+  fn take_idle(&self, addr: &str) -> Option<Connection> {
+    let mut idle = self.idle.lock().unwrap();
+    let connections = idle.get_mut(addr)?;
+    while let Some(mut conn) = connections.pop() {
+      if conn.idle_since.elapsed() < self.idle_timeout && conn.is_open() {
+        conn.received = 0;
+        return Some(conn);
+      }
+    }
+    None
+  }
+
+  fn put_idle(&self, addr: &str, mut conn: Connection) {
+    let mut idle = self.idle.lock().unwrap();
+    let connections = idle.entry(addr.to_string()).or_default();
+    connections.retain(|c| c.idle_since.elapsed() < self.idle_timeout);
+    if connections.len() < self.max_idle_per_host {
+      conn.idle_since = Instant::now();
+      connections.push(conn);
+    }
+  }
+
+  // 发送请求并读取响应, 返回响应和连接能否继续使用
+  fn exchange(
+    &self,
+    conn: &mut Connection,
+    request: &[u8],
+    method: &str,
+  ) -> Result<(Response, bool), ClientError> {
+    conn.stream.write_all(request)?;
+    loop {
+      let head_end = conn.fill_until(|buf| parser::find(buf, b"\r\n\r\n"), self.max_header_size)?;
+      let head = std::str::from_utf8(&conn.buf[..head_end])
+        .map_err(|_| ClientError::Malformed("response head is not valid UTF-8"))?;
+      let (version, status, headers) = parse_head(head)?;
+      conn.buf.drain(..head_end + 4);
+      // 100 Continue等中间响应之后还有真正的响应
+      if (100..200).contains(&status.code()) && status != StatusCode::SwitchingProtocols {
+        continue;
+      }
+
+      let mut keep_alive = match version {
+        Version::Http11 => !headers.has_token("Connection", "close"),
+        Version::Http10 => headers.has_token("Connection", "keep-alive"),
+      } && status != StatusCode::SwitchingProtocols;
+      let body = if method == "HEAD" || !status.allows_body() {
+        Vec::new()
+      } else if headers.has_token("Transfer-Encoding", "chunked") {
+        self.read_chunked(conn)?
+      } else if headers.contains("Transfer-Encoding") {
+        return Err(ClientError::Malformed("unsupported transfer encoding"));
+      } else if let Some(length) = parser::content_length(&headers)? {
+        if length > self.max_body_size {
+          return Err(ClientError::TooLarge);
+        }
+        conn.fill_until(|buf| (buf.len() >= length).then_some(length), length)?;
+        conn.buf.drain(..length).collect()
+      } else {
+        // 没有说明长度的响应体一直读到连接关闭
+        keep_alive = false;
+        self.read_to_end(conn)?
+      };
+
+      let mut response = Response::new(status).with_body(body);
+      response.headers = headers;
+      return Ok((response, keep_alive));
+    }
+  }
+
+  fn read_chunked(&self, conn: &mut Connection) -> Result<Vec<u8>, ClientError> {
+    let mut body = Vec::new();
+    loop {
+      let line = conn.read_line(self.max_header_size)?;
+      // 忽略chunk扩展
+      let size = line.split(';').next().unwrap_or("").trim();
+      let size = usize::from_str_radix(size, 16)
+        .map_err(|_| ClientError::Malformed("invalid chunk size"))?;
+      if size == 0 {
+        break;
+      }
+      if size > self.max_body_size - body.len() {
+        return Err(ClientError::TooLarge);
+      }
+      conn.fill_until(|buf| (buf.len() >= size + 2).then_some(size), size + 2)?;
+      body.extend(conn.buf.drain(..size));
+      if conn.buf.drain(..2).as_slice() != b"\r\n" {
+        return Err(ClientError::Malformed("chunk is not terminated by CRLF"));
+      }
+    }
+    // 跳过trailer首部, 直到空行
+    while !conn.read_line(self.max_header_size)?.is_empty() {}
+    Ok(body)
+  }
+
+  fn read_to_end(&self, conn: &mut Connection) -> Result<Vec<u8>, ClientError> {
+    let mut body = std::mem::take(&mut conn.buf);
+    while body.len() <= self.max_body_size {
+      match conn.fill()? {
+        0 => return Ok(body),
+        _ => body.append(&mut conn.buf),
+      }
+    }
+    Err(ClientError::TooLarge)
+  }
+}
+
+impl Default for Client {
+  fn default() -> Client {
+    Client::new()
+  }
+}
+
+struct Connection {
+  stream: TcpStream,
+  // 读到但还没有用掉的字节
+  buf: Vec<u8>,
+  // 这次请求收到的字节数, 用来判断能不能安全地重试
+  received: usize,
+  idle_since: Instant,
+}
+
+impl Connection {
+  fn fill(&mut self) -> Result<usize, ClientError> {
+    let mut chunk = [0; 16 * 1024];
+    let n = loop {
+      match self.stream.read(&mut chunk) {
+        Ok(n) => break n,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(e) => return Err(e.into()),
+      }
+    };
+    self.buf.extend_from_slice(&chunk[..n]);
+    self.received += n;
+    Ok(n)
+  }
+
+  // 一直读取, 直到`done`在缓冲区上返回Some; 缓冲区超过`limit`之后还没有完成时返回TooLarge
+  fn fill_until<F>(&mut self, done: F, limit: usize) -> Result<usize, ClientError>
+  where
+    F: Fn(&[u8]) -> Option<usize>,
+  {
+    loop {
+      if let Some(result) = done(&self.buf) {
+        return Ok(result);
+      }
+      if self.buf.len() > limit {
+        return Err(ClientError::TooLarge);
+      }
+      if self.fill()? == 0 {
+        return Err(ClientError::UnexpectedEof);
+      }
+    }
+  }
+
+  // 读出一行, 不包括结尾的CRLF
+  fn read_line(&mut self, limit: usize) -> Result<String, ClientError> {
+    let end = self.fill_until(|buf| parser::find(buf, b"\r\n"), limit)?;
+    let line = String::from_utf8_lossy(&self.buf[..end]).into_owned();
+    self.buf.drain(..end + 2);
+    Ok(line)
+  }
+
+  // 空闲连接被对端关闭后会变为可读, 读到EOF
+  fn is_open(&self) -> bool {
+    if self.stream.set_nonblocking(true).is_err() {
+      return false;
+    }
+    let open = matches!(
+      self.stream.peek(&mut [0]),
+      Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+    );
+    open && self.stream.set_nonblocking(false).is_ok()
+  }
+}
+
+fn parse_head(head: &str) -> Result<(Version, StatusCode, HeaderMap), ClientError> {
+  let mut lines = head.split("\r\n");
+  let status_line = lines.next().unwrap_or("");
+  let (version, rest) = status_line
+    .split_once(' ')
+    .ok_or(ClientError::Malformed("invalid status line"))?;
+  let version = match version {
+    "HTTP/1.1" => Version::Http11,
+    "HTTP/1.0" => Version::Http10,
+    _ => return Err(ClientError::Malformed("unsupported HTTP version")),
+  };
+  // 原因短语可以为空, 也可以省略前面的空格
+  let code = rest.split(' ').next().unwrap_or("");
+  let code = match code.parse::<u16>() {
+    Ok(n) if code.len() == 3 && (100..600).contains(&n) => n,
+    _ => return Err(ClientError::Malformed("invalid status code")),
+  };
+  let mut headers = HeaderMap::new();
+  for line in lines {
+    let (name, value) = parser::parse_header(line)?;
+    headers.append(&name, value);
+  }
+  Ok((version, StatusCode::from_code(code), headers))
+}
+
+// 拆出`http://host[:port]/path`中用于连接的地址、Host首部和请求目标
+fn split_url(url: &str) -> Result<(String, &str, String), ClientError> {
+  let invalid = || ClientError::InvalidUrl(url.to_string());
+  let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+  let (authority, target) = match rest.find(['/', '?', '#']) {
+    Some(i) => (&rest[..i], &rest[i..]),
+    None => (rest, ""),
+  };
+  if authority.is_empty() || authority.contains('@') {
+    return Err(invalid());
+  }
+  let target = target.split('#').next().unwrap_or("");
+  let target = match target.starts_with('/') {
+    true => target.to_string(),
+    false => format!("/{}", target),
+  };
+  // IPv6地址写在方括号里, 例如`[::1]:8080`
+  let has_port = match authority.rfind(']') {
+    Some(end) => authority[end..].contains(':'),
+    None => authority.contains(':'),
+  };
+  let addr = match has_port {
+    true => authority.to_string(),
+    false => format!("{}:80", authority),
+  };
+  Ok((addr, authority, target))
+}
+
+// 连接在收到响应之前被关闭
+fn is_closed(e: &ClientError) -> bool {
+  match e {
+    ClientError::UnexpectedEof => true,
+    ClientError::Io(e) => matches!(
+      e.kind(),
+      io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe
+    ),
+    _ => false,
+  }
+}
+
+fn is_idempotent(request: &Request) -> bool {
+  matches!(
+    request.method.as_str(),
+    "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS" | "TRACE"
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::TcpListener;
+  use std::thread;
+
+  use super::*;
+  use crate::server::Server;
+
+  // 接受一个连接, 读完请求首部之后写出`raw`并关闭连接
+  fn raw_server(raw: &'static [u8]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut head = Vec::new();
+      let mut byte = [0];
+      while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+        head.push(byte[0]);
+      }
+      stream.write_all(raw).unwrap();
+    });
+    addr
+  }
+
+  fn body(response: Response) -> String {
+    String::from_utf8(response.body.into_bytes().unwrap()).unwrap()
+  }
+
+  #[test]
+  fn parse_responses() {
+    let client = Client::new();
+    let addr = raw_server(
+      b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+        5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nTrailer: x\r\n\r\n",
+    );
+    let response = client.get(&format!("http://{}/", addr)).unwrap();
+    assert_eq!(StatusCode::Ok, response.status);
+    assert_eq!("hello world", body(response));
+
+    // 没有长度的响应体读到连接关闭为止, 不认识的状态码原样保留
+    let addr = raw_server(b"HTTP/1.0 418 I'm a teapot\r\nX-Tea: yes\r\n\r\nshort and stout");
+    let response = client.get(&format!("http://{}/pot", addr)).unwrap();
+    assert_eq!(StatusCode::Other(418), response.status);
+    assert_eq!(Some("yes"), response.headers.get("x-tea"));
+    assert_eq!("short and stout", body(response));
+
+    let addr = raw_server(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\ntoo short");
+    assert!(matches!(
+      client.get(&format!("http://{}/", addr)),
+      Err(ClientError::UnexpectedEof)
+    ));
+    let addr = raw_server(b"HTTP/2 200 OK\r\n\r\n");
+    assert!(matches!(
+      client.get(&format!("http://{}/", addr)),
+      Err(ClientError::Malformed(_))
+    ));
+    let addr = raw_server(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n");
+    assert!(matches!(
+      Client::new()
+        .max_body_size(10)
+        .get(&format!("http://{}/", addr)),
+      Err(ClientError::TooLarge)
+    ));
+    assert!(matches!(
+      client.get("https://example.com/"),
+      Err(ClientError::InvalidUrl(_))
+    ));
+  }
+
+  #[test]
+  fn reuse_connections() {
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .idle_timeout(Duration::from_millis(200))
+      .build(|request: &mut Request| {
+        let port = request.peer_addr.unwrap().port().to_string();
+        match request.method.as_str() {
+          "POST" => Response::text(StatusCode::Created, String::from_utf8_lossy(&request.body)),
+          _ => Response::text(StatusCode::Ok, port),
+        }
+      })
+      .unwrap();
+    let addr = server.local_addr().to_string();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    let client = Client::new();
+    let url = format!("http://{}/", addr);
+    let first = body(client.get(&url).unwrap());
+    assert_eq!(first, body(client.get(&url).unwrap()));
+
+    let mut request = Request::new("POST", "/");
+    request.body = b"posted".to_vec();
+    let response = client.send(&addr, request).unwrap();
+    assert_eq!(StatusCode::Created, response.status);
+    assert_eq!("posted", body(response));
+
+    // 服务器已经关闭了空闲连接, 客户端换一个新连接
+    thread::sleep(Duration::from_millis(400));
+    let response = client.get(&url).unwrap();
+    assert_ne!(first, body(response));
+
+    let mut request = Request::new("HEAD", "/");
+    request.headers.insert("Connection", "close");
+    let response = client.send(&addr, request).unwrap();
+    assert!(response.body.is_empty());
+    assert!(response.headers.contains("Content-Length"));
+
+    handle.shutdown();
+    running.join().unwrap();
+  }
+
+  #[test]
+  fn read_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = Client::new().read_timeout(Duration::from_millis(100));
+    let started = Instant::now();
+    let result = client.get(&format!("http://{}/", addr));
+    assert!(matches!(result, Err(ClientError::Timeout)));
+    assert!(started.elapsed() < Duration::from_secs(2));
+  }
+}
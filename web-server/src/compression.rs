@@ -0,0 +1,338 @@
+//! 响应压缩中间件
+//!
+//! 按`Accept-Encoding`的q值在gzip和deflate之间协商, 只压缩可压缩的MIME类型、
+//! 并且超过一定大小的响应体。压缩器只能一次处理整个输入, 流式响应体需要先读入内存。
+
+use crate::body::Body;
+use crate::deflate;
+use crate::header::HeaderMap;
+use crate::middleware::Middleware;
+use crate::middleware::Next;
+use crate::mime;
+use crate::request::Request;
+use crate::response::Response;
+use crate::response::StatusCode;
+
+/// 支持的内容编码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+  Gzip,
+  /// HTTP中的deflate指zlib格式(RFC 1950), 而不是裸的deflate数据
+  Deflate,
+}
+
+impl Encoding {
+  /// `Content-Encoding`中的名字
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Encoding::Gzip => "gzip",
+      Encoding::Deflate => "deflate",
+    }
+  }
+
+  pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+    match self {
+      Encoding::Gzip => deflate::gzip(data),
+      Encoding::Deflate => deflate::zlib(data),
+    }
+  }
+
+  fn matches(&self, coding: &str) -> bool {
+    match self {
+      Encoding::Gzip => {
+        coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip")
+      }
+      Encoding::Deflate => coding.eq_ignore_ascii_case("deflate"),
+    }
+  }
+}
+
+/// 根据`Accept-Encoding`从`supported`中选出q值最高的编码
+///
+/// 没有列出的编码使用`*`的q值, 也没有`*`时不可接受; q值为0表示拒绝。
+/// q值相同时按`supported`中的顺序优先。都不可接受时返回None, 即不压缩。
+///
+/// ```
+/// use web_server::compression::{negotiate, Encoding};
+///
+/// let supported = [Encoding::Gzip, Encoding::Deflate];
+/// assert_eq!(Some(Encoding::Deflate), negotiate("gzip;q=0.5, deflate", &supported));
+/// assert_eq!(None, negotiate("gzip;q=0, identity", &supported));
+/// ```
+pub fn negotiate(accept_encoding: &str, supported: &[Encoding]) -> Option<Encoding> {
+  let mut codings: Vec<(&str, f32)> = Vec::new();
+  for item in accept_encoding.split(',') {
+    let mut parts = item.split(';');
+    let coding = parts.next().unwrap_or("").trim();
+    if coding.is_empty() {
+      continue;
+    }
+    let mut q = Some(1.0);
+    for param in parts {
+      if let Some((name, value)) = param.split_once('=') {
+        if name.trim().eq_ignore_ascii_case("q") {
+          q = parse_q(value.trim());
+        }
+      }
+    }
+    // q值不合法时忽略这一项
+    if let Some(q) = q {
+      codings.push((coding, q));
+    }
+  }
+
+  let wildcard = codings.iter().find(|(c, _)| *c == "*").map(|(_, q)| *q);
+  let mut best: Option<(Encoding, f32)> = None;
+  for &encoding in supported {
+    let q = codings
+      .iter()
+      .find(|(c, _)| encoding.matches(c))
+      .map(|(_, q)| *q)
+      .or(wildcard)
+      .unwrap_or(0.0);
+    if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+      best = Some((encoding, q));
+    }
+  }
+  best.map(|(encoding, _)| encoding)
+}
+
+// q值是0到1之间最多三位小数
+fn parse_q(value: &str) -> Option<f32> {
+  let q: f32 = value.parse().ok()?;
+  if (0.0..=1.0).contains(&q) && value.len() <= 5 {
+    Some(q)
+  } else {
+    None
+  }
+}
+
+/// 压缩响应体的中间件
+///
+/// 满足以下条件时才压缩:
+///
+/// - 响应体不小于`min_size`(默认1KiB); 流式响应体(例如静态文件)只有长度已知、
+///   并且不超过`max_reader_size`(默认1MiB)时才压缩, 压缩前会整个读入内存;
+/// - `Content-Type`是可压缩的类型, 见[`mime::is_compressible`];
+/// - 响应还没有`Content-Encoding`, 也没有`Cache-Control: no-transform`;
+/// - 不是206、204、304等响应;
+/// - 压缩后确实变小了。
+///
+/// 可能被压缩的响应都会在`Vary`中加上`Accept-Encoding`, 让缓存区分不同的编码。
+/// 压缩后强ETag会变成弱ETag, 因为字节内容已经和未压缩的版本不同。
+#[derive(Debug, Clone)]
+pub struct Compression {
+  encodings: Vec<Encoding>,
+  min_size: usize,
+  max_reader_size: u64,
+}
+
+impl Compression {
+  pub fn new() -> Compression {
+    Compression {
+      encodings: vec![Encoding::Gzip, Encoding::Deflate],
+      min_size: 1024,
+      max_reader_size: 1024 * 1024,
+    }
+  }
+
+  /// 小于`min_size`字节的响应体不压缩, 压缩小响应得不偿失
+  pub fn min_size(mut self, min_size: usize) -> Compression {
+    self.min_size = min_size;
+    self
+  }
+
+  /// 超过`max_reader_size`字节或长度未知的流式响应体不压缩, 避免把大文件读入内存
+  pub fn max_reader_size(mut self, max_reader_size: u64) -> Compression {
+    self.max_reader_size = max_reader_size;
+    self
+  }
+
+  /// 服务器支持的编码, q值相同时靠前的优先
+  pub fn encodings(mut self, encodings: &[Encoding]) -> Compression {
+    self.encodings = encodings.to_vec();
+    self
+  }
+
+  fn should_compress(&self, response: &Response) -> bool {
+    let compressible = response
+      .headers
+      .get("Content-Type")
+      .is_some_and(mime::is_compressible);
+    let size = match &response.body {
+      Body::Bytes(bytes) => bytes.len() as u64,
+      Body::Reader {
+        length: Some(length),
+        ..
+      } if *length <= self.max_reader_size => *length,
+      Body::Reader { .. } => return false,
+    };
+    compressible
+      && size >= self.min_size as u64
+      && response.status.allows_body()
+      && response.status != StatusCode::PartialContent
+      && !response.headers.contains("Content-Encoding")
+      && !response.headers.has_token("Cache-Control", "no-transform")
+  }
+}
+
+impl Default for Compression {
+  fn default() -> Compression {
+    Compression::new()
+  }
+}
+
+impl Middleware for Compression {
+  fn handle(&self, request: &mut Request, next: Next) -> Response {
+    let accept_encoding = request.header("Accept-Encoding").map(|a| a.to_string());
+    let mut response = next.run(request);
+    if !self.should_compress(&response) {
+      return response;
+    }
+    add_vary(&mut response.headers, "Accept-Encoding");
+
+    let encoding = match accept_encoding.and_then(|a| negotiate(&a, &self.encodings)) {
+      Some(encoding) => encoding,
+      None => return response,
+    };
+    let bytes = match std::mem::take(&mut response.body).into_bytes() {
+      Ok(bytes) => bytes,
+      Err(e) => {
+        eprintln!("Failed to read response body: {}", e);
+        let status = StatusCode::InternalServerError;
+        return Response::text(status, status.reason());
+      }
+    };
+    let compressed = encoding.encode(&bytes);
+    if compressed.len() >= bytes.len() {
+      response.body = Body::from(bytes);
+      return response;
+    }
+    response.body = Body::from(compressed);
+    response
+      .headers
+      .insert("Content-Encoding", encoding.as_str());
+    response.headers.remove("Content-Length");
+    // Range请求针对的是未压缩的内容, 压缩后的响应不再声明支持
+    response.headers.remove("Accept-Ranges");
+    if let Some(etag) = response.headers.get("ETag") {
+      if !etag.starts_with("W/") {
+        let weak = format!("W/{}", etag);
+        response.headers.insert("ETag", weak);
+      }
+    }
+    response
+  }
+}
+
+fn add_vary(headers: &mut HeaderMap, name: &str) {
+  if !headers.has_token("Vary", name) && !headers.has_token("Vary", "*") {
+    headers.append("Vary", name);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs;
+
+  use super::*;
+  use crate::handler::Handler;
+  use crate::middleware::Chain;
+  use crate::static_files::StaticFiles;
+
+  #[test]
+  fn negotiate_q_values() {
+    let both = [Encoding::Gzip, Encoding::Deflate];
+    assert_eq!(Some(Encoding::Gzip), negotiate("deflate, gzip", &both));
+    assert_eq!(Some(Encoding::Gzip), negotiate("x-gzip", &both));
+    assert_eq!(
+      Some(Encoding::Deflate),
+      negotiate("gzip;q=0.8, deflate;q=0.9", &both)
+    );
+    assert_eq!(
+      Some(Encoding::Deflate),
+      negotiate("*;q=0.5, gzip;q=0", &both)
+    );
+    assert_eq!(Some(Encoding::Gzip), negotiate("*", &both));
+    assert_eq!(None, negotiate("br, identity", &both));
+    assert_eq!(None, negotiate("gzip;q=0", &both));
+    assert_eq!(None, negotiate("", &both));
+    assert_eq!(Some(Encoding::Gzip), negotiate("gzip;q=2, gzip", &both));
+    assert_eq!(
+      Some(Encoding::Deflate),
+      negotiate("gzip, deflate", &[Encoding::Deflate])
+    );
+  }
+
+  fn request(accept_encoding: &str) -> Request {
+    let mut request = Request::new("GET", "/");
+    request.headers.insert("Accept-Encoding", accept_encoding);
+    request
+  }
+
+  #[test]
+  fn compress_responses() {
+    let page = "<p>hello</p>".repeat(200);
+    let chain = Chain::new(move |request: &mut Request| match request.query() {
+      Some("png") => Response::ok()
+        .with_header("Content-Type", "image/png")
+        .with_body(page.clone()),
+      Some("small") => Response::html(StatusCode::Ok, "<p>hi</p>"),
+      _ => Response::html(StatusCode::Ok, page.clone()).with_header("ETag", "\"v1\""),
+    })
+    .with(Compression::new());
+
+    let response = chain.call(&mut request("gzip;q=0.5, deflate"));
+    assert_eq!(Some("deflate"), response.headers.get("Content-Encoding"));
+    assert_eq!(Some("Accept-Encoding"), response.headers.get("Vary"));
+    assert_eq!(Some("W/\"v1\""), response.headers.get("ETag"));
+    assert!(response.body.len().unwrap() < 2400);
+
+    let response = chain.call(&mut request("identity"));
+    assert_eq!(None, response.headers.get("Content-Encoding"));
+    assert_eq!(Some("Accept-Encoding"), response.headers.get("Vary"));
+    assert_eq!(Some("\"v1\""), response.headers.get("ETag"));
+
+    for uncompressed in ["/?png", "/?small"] {
+      let mut request = request("gzip");
+      request.target = uncompressed.to_string();
+      let response = chain.call(&mut request);
+      assert_eq!(None, response.headers.get("Content-Encoding"));
+      assert_eq!(None, response.headers.get("Vary"));
+    }
+  }
+
+  #[test]
+  fn compress_static_files() {
+    let dir = env::temp_dir().join(format!("web-server-compress-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let page = "<p>hello</p>".repeat(200);
+    fs::write(dir.join("index.html"), &page).unwrap();
+    let get = |compression: Compression| {
+      let chain = Chain::new(StaticFiles::new(&dir)).with(compression);
+      let mut request = request("gzip");
+      request
+        .params
+        .insert("*".to_string(), "index.html".to_string());
+      chain.call(&mut request)
+    };
+
+    let response = get(Compression::new());
+    assert_eq!(Some("gzip"), response.headers.get("Content-Encoding"));
+    assert_eq!(None, response.headers.get("Accept-Ranges"));
+    assert!(response.headers.get("ETag").unwrap().starts_with("W/"));
+    let gzip = response.body.into_bytes().unwrap();
+    assert!(gzip.len() < page.len());
+    // gzip结尾是原始数据的CRC32和长度
+    let trailer = &gzip[gzip.len() - 8..];
+    assert_eq!(deflate::crc32(page.as_bytes()).to_le_bytes(), trailer[..4]);
+    assert_eq!((page.len() as u32).to_le_bytes(), trailer[4..]);
+
+    // 超过上限的文件仍然直接发送
+    let response = get(Compression::new().max_reader_size(1024));
+    assert_eq!(None, response.headers.get("Content-Encoding"));
+    assert_eq!(Some(page.len() as u64), response.body.len());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
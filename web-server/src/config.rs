@@ -0,0 +1,318 @@
+//! 从配置文件和命令行参数读取[`ServerBuilder`]的设置
+//!
+//! 配置文件每行一个`key = value`, `#`开头的行是注释:
+//!
+//! ```text
+//! address = 0.0.0.0:8080
+//! workers = 8
+//! max_body_size = 1m
+//! read_timeout = 10s
+//! ```
+//!
+//! 命令行参数使用同样的名字, 写作`--max-body-size 1m`或`--max-body-size=1m`,
+//! `--config FILE`读取配置文件。参数按出现的顺序生效, 后面的覆盖前面的。
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::access_log::LogTarget;
+use crate::server::ServerBuilder;
+
+/// 命令行帮助
+pub const USAGE: &str = "\
+Usage: main [OPTIONS]
+
+Options:
+  --config <FILE>             read settings from FILE (key = value per line)
+  --address <ADDR>            address to listen on [default: 127.0.0.1:7878]
+  --workers <N>               number of worker threads [default: 4]
+  --backend <NAME>            threads, or epoll to park idle connections (Linux) [default: threads]
+  --event-loops <N>           number of epoll event loop threads [default: 1]
+  --backlog <N>               length of the pending connection queue
+  --shutdown-timeout <TIME>   how long to wait for requests on shutdown [default: 30s]
+  --access-log <TARGET>       write access logs to stdout, a file path, or off [default: off]
+  --access-log-format <FMT>   common, combined or json [default: combined]
+  --keep-alive <BOOL>         allow persistent connections [default: true]
+  --idle-timeout <TIME>       close idle keep-alive connections after TIME [default: 5s]
+  --header-timeout <TIME>     time allowed to receive a request head [default: 10s]
+  --body-timeout <TIME>       time allowed to receive a request body [default: 60s]
+  --read-timeout <TIME>       timeout for each read within a request [default: 30s]
+  --response-timeout <TIME>   time allowed to send a response [default: 300s]
+  --write-timeout <TIME>      timeout for each write [default: 30s]
+  --max-header-size <SIZE>    largest accepted request head [default: 8k]
+  --max-body-size <SIZE>      largest accepted request body [default: 10m]
+  --max-upgraded <N>          concurrent WebSocket and event stream connections [default: 1024]
+  --tls-address <ADDR>        also serve HTTPS on ADDR (requires the `tls` feature)
+  --tls-cert <FILE>           PEM certificate chain for HTTPS
+  --tls-key <FILE>            PEM private key for HTTPS
+  --redirect-https <BOOL>     redirect every HTTP request to HTTPS [default: false]
+  --static-listing            list directories under /static/ (demo binary only)
+  -h, --help                  print this help
+
+Send SIGHUP to reopen the access log file after rotating it.
+TIME is a number with an optional unit: ms, s (default) or m.
+SIZE is a number of bytes with an optional unit: k, m or g.
+";
+
+#[derive(Debug)]
+pub enum ConfigError {
+  Io(io::Error),
+  /// 未知的配置项, 或者值无法解析
+  Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ConfigError::Io(e) => write!(f, "failed to read config file: {}", e),
+      ConfigError::Invalid(message) => f.write_str(message),
+    }
+  }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+  fn from(e: io::Error) -> ConfigError {
+    ConfigError::Io(e)
+  }
+}
+
+impl ServerBuilder {
+  /// 按名字设置一项配置, 名字中的`-`和`_`等价
+  pub fn set(self, key: &str, value: &str) -> Result<ServerBuilder, ConfigError> {
+    let value = value.trim();
+    let invalid = || ConfigError::Invalid(format!("invalid value for {}: {:?}", key, value));
+    let builder = match key.trim().replace('-', "_").as_str() {
+      "address" => self.address(value),
+      "workers" => match value.parse() {
+        Ok(workers) if workers > 0 => self.workers(workers),
+        _ => return Err(invalid()),
+      },
+      "backend" => self.backend(value.parse().map_err(|_| invalid())?),
+      "event_loops" => match value.parse() {
+        Ok(event_loops) if event_loops > 0 => self.event_loops(event_loops),
+        _ => return Err(invalid()),
+      },
+      "backlog" => self.backlog(value.parse().map_err(|_| invalid())?),
+      "shutdown_timeout" => self.shutdown_timeout(parse_duration(value).ok_or_else(invalid)?),
+      "access_log" => self.access_log(match value {
+        "off" | "" => None,
+        "stdout" | "-" => Some(LogTarget::Stdout),
+        path => Some(LogTarget::File(PathBuf::from(path))),
+      }),
+      "access_log_format" => self.access_log_format(value.parse().map_err(|_| invalid())?),
+      "keep_alive" => self.keep_alive(parse_bool(value).ok_or_else(invalid)?),
+      // 套接字不接受为0的超时, 0也没有意义
+      "idle_timeout" => self.idle_timeout(parse_timeout(value).ok_or_else(invalid)?),
+      "header_timeout" => self.header_timeout(parse_timeout(value).ok_or_else(invalid)?),
+      "body_timeout" => self.body_timeout(parse_timeout(value).ok_or_else(invalid)?),
+      "read_timeout" => self.read_timeout(parse_timeout(value).ok_or_else(invalid)?),
+      "response_timeout" => self.response_timeout(parse_timeout(value).ok_or_else(invalid)?),
+      "write_timeout" => self.write_timeout(parse_timeout(value).ok_or_else(invalid)?),
+      "max_header_size" => self.max_header_size(parse_size(value).ok_or_else(invalid)?),
+      "max_body_size" => self.max_body_size(parse_size(value).ok_or_else(invalid)?),
+      "max_upgraded" => self.max_upgraded(value.parse().map_err(|_| invalid())?),
+      "tls_address" => self.tls_address(value),
+      "tls_cert" => self.tls_cert(value),
+      "tls_key" => self.tls_key(value),
+      "redirect_https" => self.redirect_https(parse_bool(value).ok_or_else(invalid)?),
+      _ => return Err(ConfigError::Invalid(format!("unknown setting: {}", key))),
+    };
+    Ok(builder)
+  }
+
+  /// 读取配置文件
+  pub fn load_file<P: AsRef<Path>>(self, path: P) -> Result<ServerBuilder, ConfigError> {
+    let contents = fs::read_to_string(&path)?;
+    let mut builder = self;
+    for (number, line) in contents.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let (key, value) = line.split_once('=').ok_or_else(|| {
+        ConfigError::Invalid(format!(
+          "{}:{}: expected `key = value`",
+          path.as_ref().display(),
+          number + 1
+        ))
+      })?;
+      builder = builder.set(key, value).map_err(|e| match e {
+        ConfigError::Invalid(message) => ConfigError::Invalid(format!(
+          "{}:{}: {}",
+          path.as_ref().display(),
+          number + 1,
+          message
+        )),
+        e => e,
+      })?;
+    }
+    Ok(builder)
+  }
+
+  /// 应用命令行参数, 不包括程序名
+  pub fn apply_args<I>(self, args: I) -> Result<ServerBuilder, ConfigError>
+  where
+    I: IntoIterator<Item = String>,
+  {
+    let mut builder = self;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+      let option = match arg.strip_prefix("--") {
+        Some(option) if !option.is_empty() => option,
+        _ => {
+          return Err(ConfigError::Invalid(format!(
+            "unexpected argument: {}",
+            arg
+          )))
+        }
+      };
+      let (key, value) = match option.split_once('=') {
+        Some((key, value)) => (key.to_string(), value.to_string()),
+        None => match args.next() {
+          Some(value) => (option.to_string(), value),
+          None => {
+            return Err(ConfigError::Invalid(format!(
+              "--{} is missing its value",
+              option
+            )))
+          }
+        },
+      };
+      builder = match key.as_str() {
+        "config" => builder.load_file(value)?,
+        key => builder.set(key, &value)?,
+      };
+    }
+    Ok(builder)
+  }
+}
+
+/// 解析`500ms`、`30s`、`2m`这样的时长, 没有单位时按秒计算
+fn parse_duration(s: &str) -> Option<Duration> {
+  let (number, unit) = split_unit(s);
+  let number: u64 = number.parse().ok()?;
+  match unit {
+    "ms" => Some(Duration::from_millis(number)),
+    "" | "s" => Some(Duration::from_secs(number)),
+    "m" => Some(Duration::from_secs(number.checked_mul(60)?)),
+    _ => None,
+  }
+}
+
+/// 与`parse_duration`相同, 但不能为0
+fn parse_timeout(s: &str) -> Option<Duration> {
+  parse_duration(s).filter(|d| !d.is_zero())
+}
+
+/// 解析`512`、`8k`、`10m`这样的字节数, 单位按1024进位
+fn parse_size(s: &str) -> Option<usize> {
+  let (number, unit) = split_unit(s);
+  let number: usize = number.parse().ok()?;
+  let shift = match unit.to_ascii_lowercase().as_str() {
+    "" | "b" => 0,
+    "k" | "kb" => 10,
+    "m" | "mb" => 20,
+    "g" | "gb" => 30,
+    _ => return None,
+  };
+  number.checked_mul(1 << shift)
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+  match s.to_ascii_lowercase().as_str() {
+    "true" | "on" | "yes" | "1" => Some(true),
+    "false" | "off" | "no" | "0" => Some(false),
+    _ => None,
+  }
+}
+
+fn split_unit(s: &str) -> (&str, &str) {
+  let s = s.trim();
+  let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+  (&s[..digits], s[digits..].trim())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+
+  use super::*;
+  use crate::server::Backend;
+
+  fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+  }
+
+  #[test]
+  fn parse_values() {
+    assert_eq!(Some(Duration::from_millis(500)), parse_duration("500ms"));
+    assert_eq!(Some(Duration::from_secs(30)), parse_duration("30"));
+    assert_eq!(Some(Duration::from_secs(120)), parse_duration("2m"));
+    assert_eq!(None, parse_duration("1h"));
+    assert_eq!(Some(8192), parse_size("8k"));
+    assert_eq!(Some(10 * 1024 * 1024), parse_size("10M"));
+    assert_eq!(None, parse_size("-1"));
+    assert_eq!(Some(false), parse_bool("off"));
+  }
+
+  #[test]
+  fn file_then_flags() {
+    let path = env::temp_dir().join(format!("web-server-config-{}", std::process::id()));
+    fs::write(
+      &path,
+      "# deployment\naddress = 0.0.0.0:8080\nworkers = 8\n\nmax_body_size = 1m\n",
+    )
+    .unwrap();
+
+    let builder = ServerBuilder::default()
+      .apply_args(args(&[
+        "--config",
+        path.to_str().unwrap(),
+        "--workers=2",
+        "--read-timeout",
+        "10s",
+        "--backend=epoll",
+      ]))
+      .unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!("0.0.0.0:8080", builder.address);
+    assert_eq!(2, builder.workers);
+    assert_eq!(Backend::Epoll, builder.backend);
+    assert_eq!(1024 * 1024, builder.connection.max_body_size);
+    assert_eq!(Duration::from_secs(10), builder.connection.read_timeout);
+  }
+
+  #[test]
+  fn reject_invalid() {
+    let error = |a: &[&str]| {
+      ServerBuilder::default()
+        .apply_args(args(a))
+        .unwrap_err()
+        .to_string()
+    };
+    assert_eq!("unknown setting: port", error(&["--port", "80"]));
+    assert_eq!("invalid value for workers: \"0\"", error(&["--workers=0"]));
+    assert_eq!("--backlog is missing its value", error(&["--backlog"]));
+    for key in [
+      "read-timeout",
+      "write-timeout",
+      "idle-timeout",
+      "header-timeout",
+      "body-timeout",
+      "response-timeout",
+    ] {
+      assert_eq!(
+        format!("invalid value for {}: \"0ms\"", key),
+        error(&[&format!("--{}=0ms", key)])
+      );
+    }
+    assert_eq!("unexpected argument: 8080", error(&["8080"]));
+  }
+}
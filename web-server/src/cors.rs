@@ -0,0 +1,255 @@
+//! 跨域资源共享(CORS)中间件
+
+use std::time::Duration;
+
+use crate::middleware::Middleware;
+use crate::middleware::Next;
+use crate::request::Request;
+use crate::response::Response;
+use crate::response::StatusCode;
+
+/// 处理CORS预检请求, 并给跨域请求的响应加上`Access-Control-*`首部
+///
+/// 默认允许任意来源的GET、HEAD、POST请求:
+///
+/// ```
+/// use std::time::Duration;
+/// use web_server::cors::Cors;
+///
+/// let cors = Cors::new()
+///   .allow_origin("https://example.com")
+///   .allow_methods(&["GET", "PUT", "DELETE"])
+///   .allow_headers(&["Content-Type"])
+///   .max_age(Duration::from_secs(600));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cors {
+  /// 为空时允许任意来源
+  origins: Vec<String>,
+  methods: Vec<String>,
+  /// 为空时允许预检请求中列出的任意首部
+  headers: Vec<String>,
+  expose_headers: Vec<String>,
+  max_age: Option<Duration>,
+  credentials: bool,
+}
+
+impl Cors {
+  pub fn new() -> Cors {
+    Cors {
+      origins: Vec::new(),
+      methods: vec!["GET".to_string(), "HEAD".to_string(), "POST".to_string()],
+      headers: Vec::new(),
+      expose_headers: Vec::new(),
+      max_age: None,
+      credentials: false,
+    }
+  }
+
+  /// 添加一个允许的来源, 例如`https://example.com`, 添加之后只允许列出的来源
+  pub fn allow_origin(mut self, origin: &str) -> Cors {
+    self.origins.push(origin.to_string());
+    self
+  }
+
+  pub fn allow_methods(mut self, methods: &[&str]) -> Cors {
+    self.methods = methods.iter().map(|m| m.to_ascii_uppercase()).collect();
+    self
+  }
+
+  pub fn allow_headers(mut self, headers: &[&str]) -> Cors {
+    self.headers = headers.iter().map(|h| h.to_string()).collect();
+    self
+  }
+
+  /// 允许浏览器脚本读取的响应首部
+  pub fn expose_headers(mut self, headers: &[&str]) -> Cors {
+    self.expose_headers = headers.iter().map(|h| h.to_string()).collect();
+    self
+  }
+
+  /// 浏览器可以缓存预检结果的时间
+  pub fn max_age(mut self, max_age: Duration) -> Cors {
+    self.max_age = Some(max_age);
+    self
+  }
+
+  /// 是否允许携带cookie等凭据, 允许时不会返回`*`而是回显请求的来源
+  pub fn allow_credentials(mut self, credentials: bool) -> Cors {
+    self.credentials = credentials;
+    self
+  }
+
+  fn origin_allowed(&self, origin: &str) -> bool {
+    self.origins.is_empty() || self.origins.iter().any(|o| o == origin)
+  }
+
+  // 只有允许任意来源且不带凭据时才能用`*`, 否则回显来源并声明随Origin变化
+  fn add_origin(&self, response: &mut Response, origin: &str) {
+    if self.origins.is_empty() && !self.credentials {
+      response.headers.insert("Access-Control-Allow-Origin", "*");
+    } else {
+      response
+        .headers
+        .insert("Access-Control-Allow-Origin", origin);
+      response.headers.append("Vary", "Origin");
+    }
+    if self.credentials {
+      response
+        .headers
+        .insert("Access-Control-Allow-Credentials", "true");
+    }
+  }
+
+  fn preflight(&self, request: &Request, origin: &str) -> Response {
+    let method = request
+      .header("Access-Control-Request-Method")
+      .unwrap_or_default();
+    if !self.methods.iter().any(|m| m == method) {
+      return Response::new(StatusCode::Forbidden);
+    }
+    let mut response = Response::new(StatusCode::NoContent);
+    self.add_origin(&mut response, origin);
+    response
+      .headers
+      .insert("Access-Control-Allow-Methods", self.methods.join(", "));
+    let headers = if self.headers.is_empty() {
+      request
+        .header("Access-Control-Request-Headers")
+        .map(|h| h.to_string())
+    } else {
+      Some(self.headers.join(", "))
+    };
+    if let Some(headers) = headers {
+      response
+        .headers
+        .insert("Access-Control-Allow-Headers", headers);
+    }
+    if let Some(max_age) = self.max_age {
+      response
+        .headers
+        .insert("Access-Control-Max-Age", max_age.as_secs().to_string());
+    }
+    response
+  }
+}
+
+impl Default for Cors {
+  fn default() -> Cors {
+    Cors::new()
+  }
+}
+
+impl Middleware for Cors {
+  fn handle(&self, request: &mut Request, next: Next) -> Response {
+    // 同源请求不带Origin
+    let origin = match request.header("Origin") {
+      Some(origin) => origin.to_string(),
+      None => return next.run(request),
+    };
+    let preflight =
+      request.method == "OPTIONS" && request.headers.contains("Access-Control-Request-Method");
+    if !self.origin_allowed(&origin) {
+      // 不加任何CORS首部, 由浏览器拒绝
+      if preflight {
+        return Response::new(StatusCode::Forbidden);
+      }
+      return next.run(request);
+    }
+    if preflight {
+      return self.preflight(request, &origin);
+    }
+
+    let mut response = next.run(request);
+    self.add_origin(&mut response, &origin);
+    if !self.expose_headers.is_empty() {
+      response.headers.insert(
+        "Access-Control-Expose-Headers",
+        self.expose_headers.join(", "),
+      );
+    }
+    response
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::handler::Handler;
+  use crate::middleware::Chain;
+
+  fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+    let mut request = Request::new(method, "/api");
+    for (name, value) in headers {
+      request.headers.insert(name, *value);
+    }
+    request
+  }
+
+  #[test]
+  fn simple_requests() {
+    let chain = Chain::new(|_: &mut Request| Response::ok()).with(Cors::new());
+    let response = chain.call(&mut request("GET", &[("Origin", "https://a.test")]));
+    assert_eq!(
+      Some("*"),
+      response.headers.get("Access-Control-Allow-Origin")
+    );
+    let response = chain.call(&mut request("GET", &[]));
+    assert_eq!(None, response.headers.get("Access-Control-Allow-Origin"));
+
+    let chain = Chain::new(|_: &mut Request| Response::ok()).with(
+      Cors::new()
+        .allow_origin("https://a.test")
+        .allow_credentials(true),
+    );
+    let response = chain.call(&mut request("GET", &[("Origin", "https://a.test")]));
+    assert_eq!(
+      Some("https://a.test"),
+      response.headers.get("Access-Control-Allow-Origin")
+    );
+    assert_eq!(Some("Origin"), response.headers.get("Vary"));
+    assert_eq!(
+      Some("true"),
+      response.headers.get("Access-Control-Allow-Credentials")
+    );
+    let response = chain.call(&mut request("GET", &[("Origin", "https://b.test")]));
+    assert_eq!(StatusCode::Ok, response.status);
+    assert_eq!(None, response.headers.get("Access-Control-Allow-Origin"));
+  }
+
+  #[test]
+  fn preflight() {
+    let chain = Chain::new(|_: &mut Request| -> Response { panic!("handler called") }).with(
+      Cors::new()
+        .allow_methods(&["GET", "PUT"])
+        .max_age(Duration::from_secs(600)),
+    );
+    let response = chain.call(&mut request(
+      "OPTIONS",
+      &[
+        ("Origin", "https://a.test"),
+        ("Access-Control-Request-Method", "PUT"),
+        ("Access-Control-Request-Headers", "content-type"),
+      ],
+    ));
+    assert_eq!(StatusCode::NoContent, response.status);
+    assert_eq!(
+      Some("GET, PUT"),
+      response.headers.get("Access-Control-Allow-Methods")
+    );
+    assert_eq!(
+      Some("content-type"),
+      response.headers.get("Access-Control-Allow-Headers")
+    );
+    assert_eq!(Some("600"), response.headers.get("Access-Control-Max-Age"));
+
+    let response = chain.call(&mut request(
+      "OPTIONS",
+      &[
+        ("Origin", "https://a.test"),
+        ("Access-Control-Request-Method", "DELETE"),
+      ],
+    ));
+    assert_eq!(StatusCode::Forbidden, response.status);
+  }
+}
@@ -0,0 +1,162 @@
+//! HTTP日期(IMF-fixdate)的格式化与解析, 例如`Sun, 06 Nov 1994 08:49:37 GMT`,
+//! 以及访问日志使用的几种时间格式, 统一使用UTC
+
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+  "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// 格式化为IMF-fixdate, 早于1970年的时间按1970年处理
+pub fn format(time: SystemTime) -> String {
+  let t = Civil::from(time);
+  format!(
+    "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+    DAYS[t.weekday],
+    t.day,
+    MONTHS[(t.month - 1) as usize],
+    t.year,
+    t.hour,
+    t.minute,
+    t.second
+  )
+}
+
+/// Common Log Format中的时间, 例如`06/Nov/1994:08:49:37 +0000`
+pub fn format_clf(time: SystemTime) -> String {
+  let t = Civil::from(time);
+  format!(
+    "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+    t.day,
+    MONTHS[(t.month - 1) as usize],
+    t.year,
+    t.hour,
+    t.minute,
+    t.second
+  )
+}
+
+/// RFC 3339格式的时间, 精确到毫秒, 例如`1994-11-06T08:49:37.000Z`
+pub fn format_rfc3339(time: SystemTime) -> String {
+  let t = Civil::from(time);
+  format!(
+    "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+    t.year, t.month, t.day, t.hour, t.minute, t.second, t.millis
+  )
+}
+
+// UTC下的年月日时分秒
+struct Civil {
+  year: i64,
+  month: u32,
+  day: u32,
+  weekday: usize,
+  hour: u64,
+  minute: u64,
+  second: u64,
+  millis: u32,
+}
+
+impl From<SystemTime> for Civil {
+  fn from(time: SystemTime) -> Civil {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    let (year, month, day) = civil_from_days(days);
+    Civil {
+      year,
+      month,
+      day,
+      weekday: (days % 7) as usize,
+      hour: rem / 3600,
+      minute: rem % 3600 / 60,
+      second: rem % 60,
+      millis: since_epoch.subsec_millis(),
+    }
+  }
+}
+
+/// 解析IMF-fixdate, 格式不对时返回None
+pub fn parse(s: &str) -> Option<SystemTime> {
+  let parts: Vec<&str> = s.split(' ').collect();
+  if parts.len() != 6 || !parts[0].ends_with(',') || parts[5] != "GMT" {
+    return None;
+  }
+  let day: u32 = parts[1].parse().ok()?;
+  let month = MONTHS.iter().position(|m| *m == parts[2])? as u32 + 1;
+  let year: i64 = parts[3].parse().ok()?;
+  let time: Vec<u64> = parts[4]
+    .split(':')
+    .map(|p| p.parse().ok())
+    .collect::<Option<_>>()?;
+  if time.len() != 3 || time[0] > 23 || time[1] > 59 || time[2] > 60 {
+    return None;
+  }
+  // 四位数的年份, 更大的年份会让下面的计算溢出
+  if !(1..=31).contains(&day) || !(1970..=9999).contains(&year) {
+    return None;
+  }
+  let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+  let secs = days
+    .checked_mul(86400)?
+    .checked_add(time[0] * 3600 + time[1] * 60 + time[2])?;
+  UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+// 以下两个函数来自Howard Hinnant的公历日期算法
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z.rem_euclid(146097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+  (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year.div_euclid(400);
+  let yoe = year.rem_euclid(400);
+  let month = month as i64;
+  let doy = (153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + day as i64 - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn round_trip() {
+    let time = UNIX_EPOCH + Duration::from_secs(784111777);
+    assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", format(time));
+    assert_eq!(Some(time), parse("Sun, 06 Nov 1994 08:49:37 GMT"));
+
+    let leap = UNIX_EPOCH + Duration::from_secs(1709208000);
+    assert_eq!("Thu, 29 Feb 2024 12:00:00 GMT", format(leap));
+    assert_eq!(Some(leap), parse(&format(leap)));
+
+    assert_eq!(None, parse("Sunday, 06-Nov-94 08:49:37 GMT"));
+    assert_eq!(None, parse("Sun, 06 Nov 1994 25:49:37 GMT"));
+    assert_eq!(None, parse("Sun, 06 Nov 500000000000 08:49:37 GMT"));
+    assert_eq!(None, parse("Sun, 06 Nov 9223372036854775807 08:49:37 GMT"));
+    assert_eq!(None, parse("Sun, 06 Nov 10000 08:49:37 GMT"));
+    assert!(parse("Fri, 31 Dec 9999 23:59:59 GMT").is_some());
+  }
+
+  #[test]
+  fn log_formats() {
+    let time = UNIX_EPOCH + Duration::from_millis(784111777042);
+    assert_eq!("06/Nov/1994:08:49:37 +0000", format_clf(time));
+    assert_eq!("1994-11-06T08:49:37.042Z", format_rfc3339(time));
+  }
+}
@@ -0,0 +1,366 @@
+//! DEFLATE压缩(RFC 1951)以及gzip(RFC 1952)、zlib(RFC 1950)封装
+//!
+//! 只实现了压缩: LZ77使用哈希链查找匹配, 编码使用固定Huffman表,
+//! 压缩后反而变大时退回不压缩的存储块。压缩率不如zlib, 但对HTML/JSON这类文本已经足够。
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+// 每个位置最多比较多少个候选, 越大压缩率越高也越慢
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [u16; 29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+  163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+  0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049,
+  3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+  0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// 原始DEFLATE数据流
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+  let compressed = compress_fixed(data);
+  // 存储块每块最多65535字节, 每块有5字节的开销
+  let stored_size = data.len() + 5 * (data.len() / 65535 + 1);
+  if compressed.len() < stored_size {
+    compressed
+  } else {
+    stored(data)
+  }
+}
+
+/// gzip格式, 用于`Content-Encoding: gzip`
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+  // 没有文件名和修改时间, 操作系统未知
+  let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+  out.extend_from_slice(&deflate(data));
+  out.extend_from_slice(&crc32(data).to_le_bytes());
+  out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+  out
+}
+
+/// zlib格式, HTTP中的`Content-Encoding: deflate`指的是它而不是原始DEFLATE
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+  // 32K窗口, 校验位使(CMF * 256 + FLG)能被31整除
+  let mut out = vec![0x78, 0x01];
+  out.extend_from_slice(&deflate(data));
+  out.extend_from_slice(&adler32(data).to_be_bytes());
+  out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for &b in data {
+    crc = CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+  }
+  !crc
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+  let mut table = [0u32; 256];
+  let mut i = 0;
+  while i < 256 {
+    let mut c = i as u32;
+    let mut k = 0;
+    while k < 8 {
+      c = if c & 1 != 0 {
+        0xedb88320 ^ (c >> 1)
+      } else {
+        c >> 1
+      };
+      k += 1;
+    }
+    table[i] = c;
+    i += 1;
+  }
+  table
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+  const MOD: u32 = 65521;
+  let (mut a, mut b) = (1u32, 0u32);
+  // 5552是保证b不溢出u32的最大块长度
+  for chunk in data.chunks(5552) {
+    for &byte in chunk {
+      a += byte as u32;
+      b += a;
+    }
+    a %= MOD;
+    b %= MOD;
+  }
+  (b << 16) | a
+}
+
+fn stored(data: &[u8]) -> Vec<u8> {
+  let mut out = Vec::with_capacity(data.len() + 5 * (data.len() / 65535 + 1));
+  let mut chunks = data.chunks(65535).peekable();
+  if chunks.peek().is_none() {
+    return vec![1, 0, 0, 0xff, 0xff];
+  }
+  while let Some(chunk) = chunks.next() {
+    // BFINAL和BTYPE=00, 之后对齐到字节
+    out.push(chunks.peek().is_none() as u8);
+    let len = chunk.len() as u16;
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(&(!len).to_le_bytes());
+    out.extend_from_slice(chunk);
+  }
+  out
+}
+
+fn compress_fixed(data: &[u8]) -> Vec<u8> {
+  let mut w = BitWriter::new();
+  // BFINAL=1, BTYPE=01(固定Huffman)
+  w.write(1, 1);
+  w.write(1, 2);
+
+  let mut head = vec![usize::MAX; 1 << HASH_BITS];
+  let mut prev = vec![usize::MAX; WINDOW_SIZE];
+  let insert = |head: &mut Vec<usize>, prev: &mut Vec<usize>, i: usize| {
+    if i + MIN_MATCH <= data.len() {
+      let h = hash(&data[i..]);
+      prev[i % WINDOW_SIZE] = head[h];
+      head[h] = i;
+    }
+  };
+
+  let mut i = 0;
+  while i < data.len() {
+    let (length, distance) = longest_match(data, i, &head, &prev);
+    if length >= MIN_MATCH {
+      write_match(&mut w, length, distance);
+      for j in i..i + length {
+        insert(&mut head, &mut prev, j);
+      }
+      i += length;
+    } else {
+      write_symbol(&mut w, data[i] as u16);
+      insert(&mut head, &mut prev, i);
+      i += 1;
+    }
+  }
+  write_symbol(&mut w, 256);
+  w.finish()
+}
+
+fn hash(bytes: &[u8]) -> usize {
+  let v = ((bytes[0] as u32) << 16) | ((bytes[1] as u32) << 8) | bytes[2] as u32;
+  (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+// 沿着哈希链查找最长匹配, 返回(长度, 距离), 没有匹配时长度为0
+fn longest_match(data: &[u8], i: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+  if i + MIN_MATCH > data.len() {
+    return (0, 0);
+  }
+  let max = MAX_MATCH.min(data.len() - i);
+  let mut best = (0, 0);
+  let mut candidate = head[hash(&data[i..])];
+  for _ in 0..MAX_CHAIN {
+    if candidate == usize::MAX || i - candidate > WINDOW_SIZE {
+      break;
+    }
+    let length = data[candidate..]
+      .iter()
+      .zip(&data[i..i + max])
+      .take_while(|(a, b)| a == b)
+      .count();
+    if length > best.0 {
+      best = (length, i - candidate);
+      if length == max {
+        break;
+      }
+    }
+    candidate = prev[candidate % WINDOW_SIZE];
+  }
+  best
+}
+
+fn write_match(w: &mut BitWriter, length: usize, distance: usize) {
+  let code = LENGTH_BASE
+    .iter()
+    .rposition(|&b| b as usize <= length)
+    .unwrap();
+  write_symbol(w, 257 + code as u16);
+  w.write(
+    (length - LENGTH_BASE[code] as usize) as u32,
+    LENGTH_EXTRA[code] as u32,
+  );
+
+  let code = DISTANCE_BASE
+    .iter()
+    .rposition(|&b| b as usize <= distance)
+    .unwrap();
+  // 固定Huffman表中距离码都是5位
+  w.write_code(code as u32, 5);
+  w.write(
+    (distance - DISTANCE_BASE[code] as usize) as u32,
+    DISTANCE_EXTRA[code] as u32,
+  );
+}
+
+// 固定Huffman表中字面量/长度符号的编码
+fn write_symbol(w: &mut BitWriter, symbol: u16) {
+  let symbol = symbol as u32;
+  match symbol {
+    0..=143 => w.write_code(0x30 + symbol, 8),
+    144..=255 => w.write_code(0x190 + symbol - 144, 9),
+    256..=279 => w.write_code(symbol - 256, 7),
+    _ => w.write_code(0xc0 + symbol - 280, 8),
+  }
+}
+
+// DEFLATE从每个字节的最低位开始写; Huffman编码从最高位开始写, 其他数值从最低位开始写
+struct BitWriter {
+  out: Vec<u8>,
+  bits: u64,
+  count: u32,
+}
+
+impl BitWriter {
+  fn new() -> BitWriter {
+    BitWriter {
+      out: Vec::new(),
+      bits: 0,
+      count: 0,
+    }
+  }
+
+  fn write(&mut self, value: u32, count: u32) {
+    self.bits |= (value as u64) << self.count;
+    self.count += count;
+    while self.count >= 8 {
+      self.out.push(self.bits as u8);
+      self.bits >>= 8;
+      self.count -= 8;
+    }
+  }
+
+  fn write_code(&mut self, code: u32, length: u32) {
+    self.write(code.reverse_bits() >> (32 - length), length);
+  }
+
+  fn finish(mut self) -> Vec<u8> {
+    if self.count > 0 {
+      self.out.push(self.bits as u8);
+    }
+    self.out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+  }
+
+  impl<'a> BitReader<'a> {
+    fn bits(&mut self, n: u32) -> u32 {
+      let mut v = 0;
+      for i in 0..n {
+        v |= (((self.data[self.pos / 8] >> (self.pos % 8)) & 1) as u32) << i;
+        self.pos += 1;
+      }
+      v
+    }
+
+    // Huffman编码从最高位开始
+    fn code(&mut self, n: u32) -> u32 {
+      (0..n).fold(0, |code, _| (code << 1) | self.bits(1))
+    }
+  }
+
+  // 只支持存储块和固定Huffman块的解压, 用来验证压缩结果
+  fn inflate(data: &[u8]) -> Vec<u8> {
+    let mut r = BitReader { data, pos: 0 };
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+      let last = r.bits(1) == 1;
+      match r.bits(2) {
+        0 => {
+          let start = r.pos.div_ceil(8);
+          let len = u16::from_le_bytes([data[start], data[start + 1]]) as usize;
+          out.extend_from_slice(&data[start + 4..start + 4 + len]);
+          r.pos = (start + 4 + len) * 8;
+        }
+        1 => loop {
+          let code = r.code(7);
+          let symbol = if code <= 0b0010111 {
+            code + 256
+          } else {
+            let code = (code << 1) | r.bits(1);
+            match code {
+              0x30..=0xbf => code - 0x30,
+              0xc0..=0xc7 => code - 0xc0 + 280,
+              _ => ((code << 1) | r.bits(1)) - 0x190 + 144,
+            }
+          };
+          match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => break,
+            _ => {
+              let i = (symbol - 257) as usize;
+              let length = LENGTH_BASE[i] as usize + r.bits(LENGTH_EXTRA[i] as u32) as usize;
+              let i = r.code(5) as usize;
+              let distance = DISTANCE_BASE[i] as usize + r.bits(DISTANCE_EXTRA[i] as u32) as usize;
+              for _ in 0..length {
+                out.push(out[out.len() - distance]);
+              }
+            }
+          }
+        },
+        _ => panic!("unsupported block type"),
+      }
+      if last {
+        return out;
+      }
+    }
+  }
+
+  #[test]
+  fn round_trip() {
+    let text = "<li><a href=\"/items/1\">item</a></li>\n".repeat(200);
+    let mut noise = Vec::new();
+    let mut x = 12345u32;
+    for _ in 0..70000 {
+      x = x.wrapping_mul(1103515245).wrapping_add(12345);
+      noise.push((x >> 16) as u8);
+    }
+    for data in [b"".to_vec(), b"a".to_vec(), text.into_bytes(), noise] {
+      let compressed = deflate(&data);
+      assert_eq!(data, inflate(&compressed));
+      assert!(compressed.len() <= data.len() + 5 * (data.len() / 65535 + 1));
+    }
+    let repeated = vec![b'x'; 10000];
+    assert!(deflate(&repeated).len() < 100);
+  }
+
+  #[test]
+  fn checksums_and_framing() {
+    assert_eq!(0xcbf43926, crc32(b"123456789"));
+    assert_eq!(0x11e60398, adler32(b"Wikipedia"));
+
+    let data = b"hello hello hello";
+    let gz = gzip(data);
+    assert_eq!([0x1f, 0x8b, 8], gz[..3]);
+    assert_eq!(crc32(data).to_le_bytes(), gz[gz.len() - 8..gz.len() - 4]);
+    assert_eq!(data.to_vec(), inflate(&gz[10..gz.len() - 8]));
+
+    let z = zlib(data);
+    assert_eq!(0, (z[0] as u32 * 256 + z[1] as u32) % 31);
+    assert_eq!(data.to_vec(), inflate(&z[2..z.len() - 4]));
+  }
+}
@@ -0,0 +1,180 @@
+//! Linux epoll和eventfd的简单封装, 只提供事件循环需要的部分
+//!
+//! 注册的文件描述符都是水平触发、只关心可读(包括对端关闭), 每个描述符带一个`u64`标识。
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
+use std::os::raw::c_int;
+use std::os::raw::c_uint;
+use std::time::Duration;
+
+const EPOLL_CLOEXEC: c_int = 0o2000000;
+const EPOLL_CTL_ADD: c_int = 1;
+const EPOLL_CTL_DEL: c_int = 2;
+const EPOLLIN: u32 = 0x001;
+const EPOLLRDHUP: u32 = 0x2000;
+const EFD_CLOEXEC: c_int = 0o2000000;
+const EFD_NONBLOCK: c_int = 0o4000;
+
+// 一次`wait`最多取回的事件数, 剩下的留给下一次
+const MAX_EVENTS: usize = 256;
+
+// 与内核的`struct epoll_event`一致, x86上是紧凑布局
+#[repr(C)]
+#[cfg_attr(any(target_arch = "x86", target_arch = "x86_64"), repr(packed))]
+#[derive(Clone, Copy)]
+struct EpollEvent {
+  events: u32,
+  data: u64,
+}
+
+extern "C" {
+  fn epoll_create1(flags: c_int) -> c_int;
+  fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut EpollEvent) -> c_int;
+  fn epoll_wait(epfd: c_int, events: *mut EpollEvent, maxevents: c_int, timeout: c_int) -> c_int;
+  fn eventfd(initval: c_uint, flags: c_int) -> c_int;
+}
+
+fn check(result: c_int) -> io::Result<c_int> {
+  if result < 0 {
+    Err(io::Error::last_os_error())
+  } else {
+    Ok(result)
+  }
+}
+
+pub struct Epoll {
+  fd: OwnedFd,
+}
+
+impl Epoll {
+  pub fn new() -> io::Result<Epoll> {
+    let fd = check(unsafe { epoll_create1(EPOLL_CLOEXEC) })?;
+    Ok(Epoll {
+      fd: unsafe { OwnedFd::from_raw_fd(fd) },
+    })
+  }
+
+  /// 开始关注`fd`的可读事件, 事件发生时返回`token`
+  pub fn add(&self, fd: RawFd, token: u64) -> io::Result<()> {
+    let mut event = EpollEvent {
+      events: EPOLLIN | EPOLLRDHUP,
+      data: token,
+    };
+    check(unsafe { epoll_ctl(self.fd.as_raw_fd(), EPOLL_CTL_ADD, fd, &mut event) })?;
+    Ok(())
+  }
+
+  /// 不再关注`fd`
+  ///
+  /// 描述符被dup过时, 关闭其中一个并不会把它从epoll中移除, 所以关闭之前总是要先调用这个方法。
+  pub fn delete(&self, fd: RawFd) -> io::Result<()> {
+    let mut event = EpollEvent { events: 0, data: 0 };
+    check(unsafe { epoll_ctl(self.fd.as_raw_fd(), EPOLL_CTL_DEL, fd, &mut event) })?;
+    Ok(())
+  }
+
+  /// 等待事件, 把就绪的token放进`tokens`; 超时或被信号打断时`tokens`为空
+  pub fn wait(&self, tokens: &mut Vec<u64>, timeout: Option<Duration>) -> io::Result<()> {
+    tokens.clear();
+    let timeout = match timeout {
+      // 向上取整, 避免还没到期就反复醒来
+      Some(timeout) => timeout
+        .as_nanos()
+        .div_ceil(1_000_000)
+        .min(c_int::MAX as u128) as c_int,
+      None => -1,
+    };
+    let mut events = [EpollEvent { events: 0, data: 0 }; MAX_EVENTS];
+    let n = unsafe {
+      epoll_wait(
+        self.fd.as_raw_fd(),
+        events.as_mut_ptr(),
+        MAX_EVENTS as c_int,
+        timeout,
+      )
+    };
+    let n = match check(n) {
+      Ok(n) => n as usize,
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+      Err(e) => return Err(e),
+    };
+    tokens.extend(events[..n].iter().map(|event| event.data));
+    Ok(())
+  }
+}
+
+/// 用eventfd从其他线程唤醒阻塞在[`Epoll::wait`]中的线程
+pub struct Waker {
+  file: File,
+}
+
+impl Waker {
+  pub fn new() -> io::Result<Waker> {
+    let fd = check(unsafe { eventfd(0, EFD_CLOEXEC | EFD_NONBLOCK) })?;
+    Ok(Waker {
+      file: File::from(unsafe { OwnedFd::from_raw_fd(fd) }),
+    })
+  }
+
+  pub fn wake(&self) {
+    let _ = (&self.file).write(&1u64.to_ne_bytes());
+  }
+
+  /// 清除唤醒状态, 否则水平触发的epoll会一直返回这个事件
+  pub fn reset(&self) {
+    let mut buf = [0; 8];
+    let _ = (&self.file).read(&mut buf);
+  }
+}
+
+impl AsRawFd for Waker {
+  fn as_raw_fd(&self) -> RawFd {
+    self.file.as_raw_fd()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::TcpListener;
+  use std::net::TcpStream;
+
+  use super::*;
+
+  #[test]
+  fn readable_and_wake() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let waker = Waker::new().unwrap();
+    let epoll = Epoll::new().unwrap();
+    epoll.add(server.as_raw_fd(), 1).unwrap();
+    epoll.add(waker.as_raw_fd(), 2).unwrap();
+
+    let mut tokens = Vec::new();
+    let timeout = Some(Duration::from_millis(10));
+    epoll.wait(&mut tokens, timeout).unwrap();
+    assert!(tokens.is_empty());
+
+    client.write_all(b"x").unwrap();
+    waker.wake();
+    epoll.wait(&mut tokens, timeout).unwrap();
+    tokens.sort();
+    assert_eq!(vec![1, 2], tokens);
+
+    // 水平触发: 数据没有读走之前一直就绪, 唤醒状态清除之后不再返回
+    waker.reset();
+    epoll.wait(&mut tokens, timeout).unwrap();
+    assert_eq!(vec![1], tokens);
+
+    epoll.delete(server.as_raw_fd()).unwrap();
+    epoll.wait(&mut tokens, timeout).unwrap();
+    assert!(tokens.is_empty());
+  }
+}
@@ -0,0 +1,464 @@
+//! [`Backend::Epoll`](crate::server::Backend::Epoll)的事件循环
+//!
+//! 监听socket和所有等待请求的连接都是非阻塞的, 注册在事件循环的epoll中。连接可读时事件循环
+//! 把数据读进连接自己的缓冲区, 收齐请求行和首部之后才把连接交给线程池; worker处理完请求、
+//! 连接再次空闲时, 通过channel把连接交还给原来的事件循环, 并用eventfd唤醒它。
+//!
+//! 空闲超时和首部超时由事件循环定期检查, 请求体的读取仍然在worker中进行。
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::os::fd::AsRawFd;
+use std::os::fd::RawFd;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::epoll::Epoll;
+use crate::epoll::Waker;
+use crate::handler::Handler;
+use crate::response::StatusCode;
+use crate::server;
+use crate::server::ConnectionConfig;
+use crate::server::ConnectionGuard;
+use crate::server::Prefixed;
+use crate::server::State;
+use crate::ThreadPool;
+
+// 其余的token是连接的文件描述符
+const LISTENER: u64 = u64::MAX;
+const WAKER: u64 = u64::MAX - 1;
+
+/// 一组事件循环, 第一个负责accept, 新连接轮流分给各个事件循环
+pub(crate) struct EventLoops {
+  loops: Vec<EventLoop>,
+}
+
+impl EventLoops {
+  pub(crate) fn new(count: usize, listener: &TcpListener) -> io::Result<EventLoops> {
+    let loops = (0..count)
+      .map(|_| EventLoop::new())
+      .collect::<io::Result<Vec<_>>>()?;
+    listener.set_nonblocking(true)?;
+    loops[0].epoll.add(listener.as_raw_fd(), LISTENER)?;
+    Ok(EventLoops { loops })
+  }
+
+  /// 运行所有事件循环, 直到服务器开始关闭
+  pub(crate) fn run(
+    self,
+    listener: &TcpListener,
+    handler: &Arc<dyn Handler>,
+    config: &Arc<ConnectionConfig>,
+    state: &Arc<State>,
+    pool: &ThreadPool,
+  ) {
+    let shared = Shared {
+      handler,
+      config,
+      state,
+      pool,
+      remotes: self.loops.iter().map(EventLoop::remote).collect(),
+    };
+    thread::scope(|scope| {
+      for (i, mut event_loop) in self.loops.into_iter().enumerate() {
+        let listener = (i == 0).then_some(listener);
+        let shared = &shared;
+        scope.spawn(move || {
+          event_loop.run(listener, shared);
+          // 只有负责accept的事件循环会被关闭时的连接唤醒, 由它通知其他事件循环
+          for remote in &shared.remotes {
+            remote.waker.wake();
+          }
+        });
+      }
+    });
+  }
+}
+
+// 所有事件循环共用的东西
+struct Shared<'a> {
+  handler: &'a Arc<dyn Handler>,
+  config: &'a Arc<ConnectionConfig>,
+  state: &'a Arc<State>,
+  pool: &'a ThreadPool,
+  remotes: Vec<Remote>,
+}
+
+// 事件循环持有的连接: 空闲的keep-alive连接, 或者还没有收齐首部的请求
+struct Waiting {
+  stream: Prefixed<TcpStream>,
+  guard: ConnectionGuard,
+  // 没有收到数据时是开始空闲的时间, 之后是收到请求第一个字节的时间
+  since: Instant,
+}
+
+// 从其他线程把连接交给事件循环
+#[derive(Clone)]
+struct Remote {
+  sender: mpsc::Sender<Waiting>,
+  waker: Arc<Waker>,
+}
+
+impl Remote {
+  // 事件循环已经退出时连接会被直接丢弃, 也就是关闭
+  fn send(&self, waiting: Waiting) {
+    if self.sender.send(waiting).is_ok() {
+      self.waker.wake();
+    }
+  }
+}
+
+struct EventLoop {
+  epoll: Epoll,
+  waker: Arc<Waker>,
+  sender: mpsc::Sender<Waiting>,
+  receiver: mpsc::Receiver<Waiting>,
+  connections: HashMap<u64, Waiting>,
+}
+
+impl EventLoop {
+  fn new() -> io::Result<EventLoop> {
+    let epoll = Epoll::new()?;
+    let waker = Arc::new(Waker::new()?);
+    epoll.add(waker.as_raw_fd(), WAKER)?;
+    let (sender, receiver) = mpsc::channel();
+    Ok(EventLoop {
+      epoll,
+      waker,
+      sender,
+      receiver,
+      connections: HashMap::new(),
+    })
+  }
+
+  fn remote(&self) -> Remote {
+    Remote {
+      sender: self.sender.clone(),
+      waker: Arc::clone(&self.waker),
+    }
+  }
+
+  fn run(&mut self, listener: Option<&TcpListener>, shared: &Shared) {
+    let tick = sweep_interval(shared.config);
+    let mut last_sweep = Instant::now();
+    let mut tokens = Vec::new();
+    let mut next = 0;
+    while !shared.state.shutting_down() {
+      if let Err(e) = self.epoll.wait(&mut tokens, Some(tick)) {
+        eprintln!("Failed to wait for events: {}", e);
+        break;
+      }
+      for &token in &tokens {
+        match (token, listener) {
+          (LISTENER, Some(listener)) => self.accept(listener, &mut next, shared),
+          (WAKER, _) => self.receive(shared),
+          _ => self.read(token, shared),
+        }
+      }
+      if last_sweep.elapsed() >= tick {
+        self.sweep(shared.config);
+        last_sweep = Instant::now();
+      }
+    }
+    // 事件循环中的连接都在等待请求, 关闭时直接断开
+    for (token, waiting) in self.connections.drain() {
+      let _ = self.epoll.delete(token as RawFd);
+      drop(waiting);
+    }
+  }
+
+  fn accept(&mut self, listener: &TcpListener, next: &mut usize, shared: &Shared) {
+    loop {
+      let stream = match listener.accept() {
+        Ok((stream, _)) => stream,
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(e) => {
+          eprintln!("Failed to accept connection: {}", e);
+          return;
+        }
+      };
+      let guard = match shared.state.register(&stream) {
+        Ok(guard) => guard,
+        Err(e) => {
+          eprintln!("Failed to register connection: {}", e);
+          continue;
+        }
+      };
+      let waiting = Waiting {
+        stream: Prefixed::new(stream),
+        guard,
+        since: Instant::now(),
+      };
+      *next = (*next + 1) % shared.remotes.len();
+      match *next {
+        0 => self.wait_for_request(waiting, shared),
+        i => shared.remotes[i].send(waiting),
+      }
+    }
+  }
+
+  fn receive(&mut self, shared: &Shared) {
+    self.waker.reset();
+    while let Ok(waiting) = self.receiver.try_recv() {
+      self.wait_for_request(waiting, shared);
+    }
+  }
+
+  // 缓冲区里已经有完整的首部(流水线请求)时直接交给线程池, 否则等待数据
+  fn wait_for_request(&mut self, mut waiting: Waiting, shared: &Shared) {
+    let pos = waiting.stream.pos;
+    waiting.stream.buffered.drain(..pos);
+    waiting.stream.pos = 0;
+    if head_complete(waiting.stream.unread(), shared.config) {
+      self.dispatch(waiting, shared);
+      return;
+    }
+    let stream = &waiting.stream.stream;
+    let fd = stream.as_raw_fd();
+    let registered = stream
+      .set_nonblocking(true)
+      .and_then(|_| self.epoll.add(fd, fd as u64));
+    if let Err(e) = registered {
+      eprintln!("Failed to register connection: {}", e);
+      return;
+    }
+    self.connections.insert(fd as u64, waiting);
+  }
+
+  // 读出所有可读的数据, 首部收齐后交给线程池, 对端关闭时断开
+  fn read(&mut self, token: u64, shared: &Shared) {
+    let waiting = match self.connections.get_mut(&token) {
+      Some(waiting) => waiting,
+      None => return,
+    };
+    let started = !waiting.stream.buffered.is_empty();
+    let mut chunk = [0; 4096];
+    let mut closed = false;
+    loop {
+      match waiting.stream.stream.read(&mut chunk) {
+        Ok(0) => {
+          closed = true;
+          break;
+        }
+        Ok(n) => {
+          waiting.stream.buffered.extend_from_slice(&chunk[..n]);
+          if waiting.stream.buffered.len() > shared.config.max_header_size {
+            break;
+          }
+        }
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(_) => {
+          closed = true;
+          break;
+        }
+      }
+    }
+    if !started && !waiting.stream.buffered.is_empty() {
+      waiting.since = Instant::now();
+    }
+    // 客户端可能发送完请求之后就关闭了写方向, 这样的请求仍然要处理
+    let ready = head_complete(waiting.stream.unread(), shared.config);
+    if ready || closed {
+      let waiting = self.remove(token);
+      if let (true, Some(waiting)) = (ready, waiting) {
+        self.dispatch(waiting, shared);
+      }
+    }
+  }
+
+  fn dispatch(&self, waiting: Waiting, shared: &Shared) {
+    let Waiting { stream, guard, .. } = waiting;
+    if let Err(e) = stream.stream.set_nonblocking(false) {
+      eprintln!("Failed to set connection to blocking: {}", e);
+      return;
+    }
+    let handler = Arc::clone(shared.handler);
+    let config = Arc::clone(shared.config);
+    let remote = self.remote();
+    shared.pool.execute(move || {
+      if let Some((stream, guard)) = server::serve(stream, &*handler, &config, Some(guard), true) {
+        remote.send(Waiting {
+          stream,
+          guard,
+          since: Instant::now(),
+        });
+      }
+    });
+  }
+
+  // 关闭空闲超时的连接; 首部没有在`header_timeout`内收齐的先返回408, 并记录到访问日志
+  fn sweep(&mut self, config: &ConnectionConfig) {
+    let expired: Vec<u64> = self
+      .connections
+      .iter()
+      .filter(|(_, waiting)| {
+        let timeout = match waiting.stream.buffered.is_empty() {
+          true => config.idle_timeout,
+          false => config.header_timeout,
+        };
+        waiting.since.elapsed() >= timeout
+      })
+      .map(|(token, _)| *token)
+      .collect();
+    for token in expired {
+      if let Some(mut waiting) = self.remove(token) {
+        if !waiting.stream.buffered.is_empty() {
+          let status = StatusCode::RequestTimeout;
+          let stream = &mut waiting.stream.stream;
+          let peer_addr = stream.peer_addr().ok();
+          server::reject(stream, status, status.reason(), config, peer_addr);
+        }
+      }
+    }
+  }
+
+  fn remove(&mut self, token: u64) -> Option<Waiting> {
+    let waiting = self.connections.remove(&token)?;
+    let _ = self.epoll.delete(token as RawFd);
+    Some(waiting)
+  }
+}
+
+// 首部过大时也交给worker, 由解析器返回431
+fn head_complete(buffered: &[u8], config: &ConnectionConfig) -> bool {
+  buffered.len() > config.max_header_size || buffered.windows(4).any(|w| w == b"\r\n\r\n")
+}
+
+// 超时检查的间隔, 超时时间越短检查得越频繁
+fn sweep_interval(config: &ConnectionConfig) -> Duration {
+  let shortest = config.idle_timeout.min(config.header_timeout);
+  (shortest / 4).clamp(Duration::from_millis(10), Duration::from_secs(1))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+  use std::net::TcpStream;
+
+  use std::env;
+  use std::fs;
+
+  use super::*;
+  use crate::access_log::LogFormat;
+  use crate::access_log::LogTarget;
+  use crate::request::Request;
+  use crate::response::Response;
+  use crate::server::Backend;
+  use crate::server::Server;
+
+  fn get(stream: &mut TcpStream, path: &str) -> String {
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).unwrap();
+    read_response(stream)
+  }
+
+  // 按Content-Length读出一个完整的响应
+  fn read_response(stream: &mut TcpStream) -> String {
+    let mut out = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+      let n = stream.read(&mut buf).unwrap();
+      out.extend_from_slice(&buf[..n]);
+      let text = String::from_utf8_lossy(&out).into_owned();
+      if n == 0 {
+        return text;
+      }
+      if let Some((head, body)) = text.split_once("\r\n\r\n") {
+        let length = head
+          .lines()
+          .find_map(|line| line.strip_prefix("Content-Length: "))
+          .and_then(|length| length.parse().ok())
+          .unwrap_or(0);
+        if body.len() >= length {
+          return text;
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn idle_connections_do_not_hold_workers() {
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .workers(1)
+      .backend(Backend::Epoll)
+      .event_loops(2)
+      .idle_timeout(Duration::from_millis(300))
+      .build(|request: &mut Request| Response::text(StatusCode::Ok, request.path().to_string()))
+      .unwrap();
+    let addr = server.local_addr();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    // 只有一个worker, 但所有keep-alive连接都能继续发送请求
+    let mut clients: Vec<TcpStream> = (0..50).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    for (i, client) in clients.iter_mut().enumerate() {
+      assert!(get(client, &format!("/{}", i)).ends_with(&format!("/{}", i)));
+    }
+    for (i, client) in clients.iter_mut().enumerate().rev() {
+      assert!(get(client, &format!("/again/{}", i)).ends_with(&format!("/again/{}", i)));
+    }
+
+    // 首部分几次到达, 以及流水线请求
+    let client = &mut clients[0];
+    client.write_all(b"GET /split HTTP/1.1\r\nHo").unwrap();
+    thread::sleep(Duration::from_millis(20));
+    client.write_all(b"st: localhost\r\n\r\n").unwrap();
+    assert!(read_response(client).ends_with("/split"));
+    client
+      .write_all(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\nConnection: close\r\n\r\n")
+      .unwrap();
+    let mut out = String::new();
+    client.read_to_string(&mut out).unwrap();
+    assert!(out.contains("\r\n\r\n/a") && out.ends_with("/b"));
+
+    // 空闲超时之后事件循环会关闭连接
+    thread::sleep(Duration::from_millis(600));
+    assert_eq!(0, clients[1].read(&mut [0; 16]).unwrap());
+
+    handle.shutdown();
+    running.join().unwrap();
+  }
+
+  #[test]
+  fn slow_head_gets_request_timeout() {
+    let log = env::temp_dir().join(format!("web-server-epoll-{}.log", std::process::id()));
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .workers(1)
+      .backend(Backend::Epoll)
+      .header_timeout(Duration::from_millis(100))
+      .access_log(Some(LogTarget::File(log.clone())))
+      .access_log_format(LogFormat::Common)
+      .build(|_: &mut Request| Response::text(StatusCode::Ok, "ok"))
+      .unwrap();
+    let addr = server.local_addr();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    let mut slow = TcpStream::connect(addr).unwrap();
+    slow.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    // 慢客户端只占用事件循环, 不影响其他请求
+    let mut other = TcpStream::connect(addr).unwrap();
+    assert!(get(&mut other, "/").ends_with("ok"));
+    let mut out = String::new();
+    slow.read_to_string(&mut out).unwrap();
+    assert!(out.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+
+    handle.shutdown();
+    running.join().unwrap();
+    // 返回408的连接也记录到访问日志
+    let lines = fs::read_to_string(&log).unwrap();
+    fs::remove_file(&log).unwrap();
+    assert!(lines.contains("\"GET / HTTP/1.1\" 200 "));
+    assert!(lines.contains("\"- - -\" 408 "));
+  }
+}
@@ -0,0 +1,19 @@
+use crate::request::Request;
+use crate::response::Response;
+
+/// 根据请求生成响应
+///
+/// 签名为`Fn(&mut Request) -> Response`的闭包自动实现了这个trait,
+/// 传入闭包时需要标注参数类型, 例如`|request: &mut Request| Response::ok()`。
+pub trait Handler: Send + Sync {
+  fn call(&self, request: &mut Request) -> Response;
+}
+
+impl<F> Handler for F
+where
+  F: Fn(&mut Request) -> Response + Send + Sync,
+{
+  fn call(&self, request: &mut Request) -> Response {
+    self(request)
+  }
+}
@@ -0,0 +1,86 @@
+/// HTTP首部集合
+///
+/// 保留首部的插入顺序和原始大小写, 查找时不区分大小写, 同名首部可以出现多次。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderMap {
+  entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+  pub fn new() -> HeaderMap {
+    HeaderMap::default()
+  }
+
+  /// 返回第一个同名首部的值
+  pub fn get(&self, name: &str) -> Option<&str> {
+    self
+      .entries
+      .iter()
+      .find(|(k, _)| k.eq_ignore_ascii_case(name))
+      .map(|(_, v)| v.as_str())
+  }
+
+  /// 返回所有同名首部的值
+  pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    self
+      .entries
+      .iter()
+      .filter(move |(k, _)| k.eq_ignore_ascii_case(name))
+      .map(|(_, v)| v.as_str())
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.get(name).is_some()
+  }
+
+  /// 设置首部, 替换掉所有已有的同名首部
+  pub fn insert(&mut self, name: &str, value: impl Into<String>) {
+    self.remove(name);
+    self.entries.push((name.to_string(), value.into()));
+  }
+
+  /// 追加首部, 保留已有的同名首部
+  pub fn append(&mut self, name: &str, value: impl Into<String>) {
+    self.entries.push((name.to_string(), value.into()));
+  }
+
+  pub fn remove(&mut self, name: &str) {
+    self.entries.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+    self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  /// 判断逗号分隔的首部值中是否包含某个token, 例如`Connection: keep-alive, Upgrade`
+  pub fn has_token(&self, name: &str, token: &str) -> bool {
+    self
+      .get_all(name)
+      .flat_map(|v| v.split(','))
+      .any(|t| t.trim().eq_ignore_ascii_case(token))
+  }
+}
+
+/// 首部的值中除了水平制表符之外不能有控制字符, 否则可能被用来拆分响应
+pub fn is_valid_value(value: &str) -> bool {
+  !value.chars().any(|c| c.is_control() && c != '\t')
+}
+
+/// 去掉值中不允许出现的控制字符
+pub(crate) fn sanitize_value(value: String) -> String {
+  if is_valid_value(&value) {
+    return value;
+  }
+  value
+    .chars()
+    .filter(|c| !c.is_control() || *c == '\t')
+    .collect()
+}
@@ -1,8 +1,46 @@
+use std::panic;
+use std::panic::AssertUnwindSafe;
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
 
+pub mod access_log;
+pub mod base64;
+pub mod basic_auth;
+pub mod body;
+pub mod client;
+pub mod compression;
+pub mod config;
+pub mod cors;
+pub mod date;
+pub mod deflate;
+#[cfg(target_os = "linux")]
+pub mod epoll;
+#[cfg(target_os = "linux")]
+mod event_loop;
+pub mod handler;
+pub mod header;
+pub mod middleware;
+pub mod mime;
+pub mod parser;
+pub mod proxy;
+pub mod range;
+pub mod rate_limit;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod sha1;
+#[cfg(unix)]
+pub mod signal;
+pub mod sse;
+pub mod static_files;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod url;
+pub mod websocket;
+
 pub struct ThreadPool {
   workers: Vec<Worker>,
   sender: mpsc::Sender<Message>,
@@ -50,16 +88,16 @@ impl ThreadPool {
 
 impl Drop for ThreadPool {
   fn drop(&mut self) {
-    println!("Sending terminate message to all workers.");
+    eprintln!("Sending terminate message to all workers.");
 
     for _ in &mut self.workers {
       self.sender.send(Message::Terminate).unwrap();
     }
 
-    println!("Shutting down all workers.");
+    eprintln!("Shutting down all workers.");
 
     for worker in &mut self.workers {
-      println!("Shutting down worker {}", worker.id);
+      eprintln!("Shutting down worker {}", worker.id);
       // 为Option值调用take方法会将Some变 体的值移出并在原来的位置留下None变体。
       if let Some(thread) = worker.thread.take() {
         thread.join().unwrap();
@@ -84,11 +122,14 @@ impl Worker {
         let message = receiver.lock().unwrap().recv().unwrap();
         match message {
           Message::NewJob(job) => {
-            println!("Worker {} got a job; executing.", id);
-            job();
+            // 任务panic时线程继续接收下一个任务, 否则线程池会越来越小,
+            // 最后execute和drop中的unwrap都会失败
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+              eprintln!("Worker {} job panicked.", id);
+            }
           }
           Message::Terminate => {
-            println!("Worker {} was told to terminate.", id);
+            eprintln!("Worker {} was told to terminate.", id);
             break;
           }
         }
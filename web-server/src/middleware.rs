@@ -0,0 +1,286 @@
+//! 中间件
+//!
+//! 中间件包在handler外面, 可以在调用handler之前检查或修改请求、直接返回响应
+//! 而不再往下调用, 也可以在handler返回之后修改响应。多个中间件用[`Chain`]
+//! 组合, 先添加的在最外层:
+//!
+//! ```
+//! use web_server::middleware::{Chain, Logger, RequestId};
+//! use web_server::request::Request;
+//! use web_server::response::Response;
+//!
+//! let handler = Chain::new(|_: &mut Request| Response::ok())
+//!   .with(Logger::new())
+//!   .with(RequestId::new());
+//! ```
+//!
+//! 内置的中间件还有[`Cors`](crate::cors::Cors)、[`BasicAuth`](crate::basic_auth::BasicAuth)、
+//! [`Compression`](crate::compression::Compression)和[`RateLimit`](crate::rate_limit::RateLimit)。
+
+use std::process;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use crate::handler::Handler;
+use crate::request::Request;
+use crate::response::Response;
+
+/// 包在handler外面的一层处理
+///
+/// 调用`next.run(request)`继续处理请求, 不调用时直接用返回的响应短路。
+/// 签名为`Fn(&mut Request, Next) -> Response`的闭包自动实现了这个trait。
+pub trait Middleware: Send + Sync {
+  fn handle(&self, request: &mut Request, next: Next) -> Response;
+}
+
+impl<F> Middleware for F
+where
+  F: Fn(&mut Request, Next) -> Response + Send + Sync,
+{
+  fn handle(&self, request: &mut Request, next: Next) -> Response {
+    self(request, next)
+  }
+}
+
+/// 链中剩下的中间件和最终的handler
+pub struct Next<'a> {
+  middleware: &'a [Box<dyn Middleware>],
+  handler: &'a dyn Handler,
+}
+
+impl<'a> Next<'a> {
+  /// 把请求交给下一个中间件, 已经是最后一个时调用handler
+  pub fn run(self, request: &mut Request) -> Response {
+    match self.middleware.split_first() {
+      Some((first, rest)) => first.handle(
+        request,
+        Next {
+          middleware: rest,
+          handler: self.handler,
+        },
+      ),
+      None => self.handler.call(request),
+    }
+  }
+}
+
+/// 按顺序组合中间件和handler, 本身也是一个handler
+///
+/// 可以作为整个服务器的handler, 也可以只注册到某个路由上。
+pub struct Chain<H> {
+  handler: H,
+  middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl<H: Handler> Chain<H> {
+  pub fn new(handler: H) -> Chain<H> {
+    Chain {
+      handler,
+      middleware: Vec::new(),
+    }
+  }
+
+  /// 添加一个中间件, 它在之前添加的中间件之后、handler之前运行
+  pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Chain<H> {
+    self.middleware.push(Box::new(middleware));
+    self
+  }
+}
+
+impl<H: Handler> Handler for Chain<H> {
+  fn call(&self, request: &mut Request) -> Response {
+    Next {
+      middleware: &self.middleware,
+      handler: &self.handler,
+    }
+    .run(request)
+  }
+}
+
+/// 在handler之前运行的钩子, 返回`Some`时不再调用后面的中间件和handler
+pub fn before<F>(f: F) -> impl Middleware
+where
+  F: Fn(&mut Request) -> Option<Response> + Send + Sync,
+{
+  move |request: &mut Request, next: Next| match f(request) {
+    Some(response) => response,
+    None => next.run(request),
+  }
+}
+
+/// 在handler之后运行的钩子, 可以修改响应
+pub fn after<F>(f: F) -> impl Middleware
+where
+  F: Fn(&Request, &mut Response) + Send + Sync,
+{
+  move |request: &mut Request, next: Next| {
+    let mut response = next.run(request);
+    f(request, &mut response);
+    response
+  }
+}
+
+/// 给每个请求分配一个ID, 写入请求和响应的`X-Request-Id`首部
+///
+/// 客户端或上游代理已经带了合法的`X-Request-Id`时沿用它, 方便串联日志。
+pub struct RequestId {
+  prefix: String,
+  counter: AtomicU64,
+}
+
+impl RequestId {
+  pub const HEADER: &'static str = "X-Request-Id";
+
+  pub fn new() -> RequestId {
+    let nanos = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_nanos() as u64)
+      .unwrap_or(0);
+    RequestId {
+      prefix: format!("{:08x}", (nanos ^ ((process::id() as u64) << 32)) as u32),
+      counter: AtomicU64::new(0),
+    }
+  }
+
+  fn generate(&self) -> String {
+    let n = self.counter.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{:08x}", self.prefix, n)
+  }
+}
+
+impl Default for RequestId {
+  fn default() -> RequestId {
+    RequestId::new()
+  }
+}
+
+impl Middleware for RequestId {
+  fn handle(&self, request: &mut Request, next: Next) -> Response {
+    let id = match request.header(RequestId::HEADER) {
+      Some(id) if valid_id(id) => id.to_string(),
+      _ => self.generate(),
+    };
+    request.headers.insert(RequestId::HEADER, id.clone());
+    let mut response = next.run(request);
+    response.headers.insert(RequestId::HEADER, id);
+    response
+  }
+}
+
+// 只接受不太长的可见ASCII字符, 避免把任意内容原样写进响应和日志
+fn valid_id(id: &str) -> bool {
+  !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// 处理完每个请求后打印一行方法、路径、状态码和耗时
+///
+/// 放在[`RequestId`]外面时会同时打印请求ID。
+#[derive(Debug, Default)]
+pub struct Logger;
+
+impl Logger {
+  pub fn new() -> Logger {
+    Logger
+  }
+}
+
+impl Middleware for Logger {
+  fn handle(&self, request: &mut Request, next: Next) -> Response {
+    let start = Instant::now();
+    let response = next.run(request);
+    let id = match request.header(RequestId::HEADER) {
+      Some(id) => format!(" [{}]", id),
+      None => String::new(),
+    };
+    println!(
+      "{} {} -> {} ({:.1?}){}",
+      request.method,
+      request.target,
+      response.status.code(),
+      start.elapsed(),
+      id
+    );
+    response
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::response::StatusCode;
+
+  fn trace(name: &'static str) -> impl Middleware {
+    move |request: &mut Request, next: Next| {
+      request.headers.append("X-Trace", format!("{}>", name));
+      let response = next.run(request);
+      response.with_header(name, "seen")
+    }
+  }
+
+  fn echo_trace(request: &mut Request) -> Response {
+    let trace: Vec<&str> = request.headers.get_all("X-Trace").collect();
+    Response::text(StatusCode::Ok, trace.concat())
+  }
+
+  #[test]
+  fn runs_in_order() {
+    let chain = Chain::new(echo_trace)
+      .with(trace("a"))
+      .with(trace("b"))
+      .with(after(|_: &Request, response: &mut Response| {
+        response.headers.insert("X-After", "1");
+      }));
+
+    let response = chain.call(&mut Request::new("GET", "/"));
+    assert_eq!(Some(&b"a>b>"[..]), response.body.as_bytes());
+    assert_eq!(Some("seen"), response.headers.get("a"));
+    assert_eq!(Some("seen"), response.headers.get("b"));
+    assert_eq!(Some("1"), response.headers.get("X-After"));
+  }
+
+  #[test]
+  fn before_short_circuits() {
+    let chain = Chain::new(echo_trace)
+      .with(before(|request: &mut Request| {
+        if request.path() == "/admin" {
+          Some(Response::new(StatusCode::Forbidden))
+        } else {
+          None
+        }
+      }))
+      .with(trace("a"));
+
+    let response = chain.call(&mut Request::new("GET", "/admin"));
+    assert_eq!(StatusCode::Forbidden, response.status);
+    assert_eq!(None, response.headers.get("a"));
+    let response = chain.call(&mut Request::new("GET", "/"));
+    assert_eq!(Some(&b"a>"[..]), response.body.as_bytes());
+  }
+
+  #[test]
+  fn request_id() {
+    let chain = Chain::new(|request: &mut Request| {
+      Response::text(StatusCode::Ok, request.header("x-request-id").unwrap())
+    })
+    .with(RequestId::new());
+
+    let first = chain.call(&mut Request::new("GET", "/"));
+    let second = chain.call(&mut Request::new("GET", "/"));
+    let id = first.headers.get("X-Request-Id").unwrap();
+    assert_eq!(Some(id.as_bytes()), first.body.as_bytes());
+    assert_ne!(id, second.headers.get("X-Request-Id").unwrap());
+
+    let mut request = Request::new("GET", "/");
+    request.headers.insert("X-Request-Id", "upstream-42");
+    let response = chain.call(&mut request);
+    assert_eq!(Some("upstream-42"), response.headers.get("X-Request-Id"));
+
+    let mut request = Request::new("GET", "/");
+    request.headers.insert("X-Request-Id", "bad id");
+    let response = chain.call(&mut request);
+    assert_ne!(Some("bad id"), response.headers.get("X-Request-Id"));
+  }
+}
@@ -0,0 +1,81 @@
+use std::path::Path;
+
+/// 根据文件扩展名推断MIME类型, 未知类型返回`application/octet-stream`
+///
+/// 文本类型会带上`charset=utf-8`。
+pub fn from_path(path: &Path) -> &'static str {
+  let ext = path
+    .extension()
+    .and_then(|e| e.to_str())
+    .map(|e| e.to_ascii_lowercase());
+  match ext.as_deref() {
+    Some("html") | Some("htm") => "text/html; charset=utf-8",
+    Some("css") => "text/css; charset=utf-8",
+    Some("js") | Some("mjs") => "text/javascript; charset=utf-8",
+    Some("json") => "application/json",
+    Some("txt") => "text/plain; charset=utf-8",
+    Some("md") => "text/markdown; charset=utf-8",
+    Some("csv") => "text/csv; charset=utf-8",
+    Some("xml") => "application/xml",
+    Some("svg") => "image/svg+xml",
+    Some("png") => "image/png",
+    Some("jpg") | Some("jpeg") => "image/jpeg",
+    Some("gif") => "image/gif",
+    Some("webp") => "image/webp",
+    Some("ico") => "image/x-icon",
+    Some("woff") => "font/woff",
+    Some("woff2") => "font/woff2",
+    Some("ttf") => "font/ttf",
+    Some("wasm") => "application/wasm",
+    Some("pdf") => "application/pdf",
+    Some("zip") => "application/zip",
+    Some("gz") => "application/gzip",
+    Some("tar") => "application/x-tar",
+    Some("mp3") => "audio/mpeg",
+    Some("mp4") => "video/mp4",
+    Some("webm") => "video/webm",
+    _ => "application/octet-stream",
+  }
+}
+
+/// 判断某个`Content-Type`的内容值不值得压缩
+///
+/// 文本、JSON、XML、JavaScript、SVG等是可压缩的; 图片、音视频、字体和压缩包
+/// 本身已经压缩过, 再压缩只会浪费CPU。
+pub fn is_compressible(content_type: &str) -> bool {
+  let essence = content_type
+    .split(';')
+    .next()
+    .unwrap_or("")
+    .trim()
+    .to_ascii_lowercase();
+  let (kind, subtype) = match essence.split_once('/') {
+    Some(parts) => parts,
+    None => return false,
+  };
+  if kind == "text" || subtype.ends_with("+json") || subtype.ends_with("+xml") {
+    return true;
+  }
+  kind == "application"
+    && matches!(
+      subtype,
+      "json" | "javascript" | "xml" | "wasm" | "x-www-form-urlencoded" | "x-tar"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn compressible_types() {
+    assert!(is_compressible("text/html; charset=utf-8"));
+    assert!(is_compressible("application/json"));
+    assert!(is_compressible("image/svg+xml"));
+    assert!(is_compressible("application/problem+json"));
+    assert!(!is_compressible("image/png"));
+    assert!(!is_compressible("application/gzip"));
+    assert!(!is_compressible("application/octet-stream"));
+    assert!(!is_compressible("nonsense"));
+  }
+}
@@ -0,0 +1,649 @@
+//! HTTP/1.1请求解析
+//!
+//! `parse`是一个纯函数, 每次都从缓冲区开头尝试解析一个完整的请求,
+//! 数据不够时返回`Status::Partial`; `RequestReader`负责从流中不断读取数据并调用它,
+//! 因此一个请求可以分多次到达, 也可以超过单次读取的大小。`RequestReader`会记住
+//! chunked请求体已经解码到哪里, 不会每次都从头解码。
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::header;
+use crate::header::HeaderMap;
+use crate::request::Request;
+use crate::request::Version;
+
+/// 请求解析失败的原因
+#[derive(Debug)]
+pub enum ParseError {
+  /// 请求格式错误, 应当返回400
+  Malformed(&'static str),
+  /// 读取过程中的IO错误
+  Io(io::Error),
+  /// 请求还没读完连接就被关闭了
+  UnexpectedEof,
+  /// 请求行和首部超过了`Limits::max_header_size`, 应当返回431
+  HeaderTooLarge,
+  /// 请求体超过了`Limits::max_body_size`, 应当返回413
+  BodyTooLarge,
+  /// 请求已经开始但没有按时收完, 应当返回408
+  Timeout,
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ParseError::Malformed(reason) => write!(f, "malformed request: {}", reason),
+      ParseError::Io(e) => write!(f, "io error: {}", e),
+      ParseError::UnexpectedEof => write!(f, "connection closed in the middle of a request"),
+      ParseError::HeaderTooLarge => write!(f, "request head is too large"),
+      ParseError::BodyTooLarge => write!(f, "request body is too large"),
+      ParseError::Timeout => write!(f, "timed out reading the request"),
+    }
+  }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+  fn from(e: io::Error) -> ParseError {
+    ParseError::Io(e)
+  }
+}
+
+/// 一次解析的结果
+#[derive(Debug, PartialEq)]
+pub enum Status {
+  /// 解析出一个完整的请求, 以及它在缓冲区中占用的字节数
+  Complete(Request, usize),
+  /// 数据还不完整, 需要继续读取
+  Partial,
+}
+
+/// 请求大小和时间的上限, 默认不限制
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+  /// 请求行和首部(包括结尾的空行)的最大字节数
+  pub max_header_size: usize,
+  /// 请求体的最大字节数, chunked请求体按解码后的长度计算
+  pub max_body_size: usize,
+  /// 从收到请求的第一个字节起, 必须在这段时间内收完请求行和首部;
+  /// 只有`RequestReader`会检查, 在每次读取之后进行, 因此还需要让每次读取的超时不超过剩余的时间
+  pub header_timeout: Option<Duration>,
+  /// 首部收完之后, 必须在这段时间内收完请求体; 检查方式与`header_timeout`相同
+  pub body_timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+  fn default() -> Limits {
+    Limits {
+      max_header_size: usize::MAX,
+      max_body_size: usize::MAX,
+      header_timeout: None,
+      body_timeout: None,
+    }
+  }
+}
+
+/// 从缓冲区开头解析一个请求
+pub fn parse(buf: &[u8]) -> Result<Status, ParseError> {
+  parse_with_limits(buf, &Limits::default())
+}
+
+/// 与`parse`相同, 但请求超过`limits`时返回错误, 不会等数据全部到达
+pub fn parse_with_limits(buf: &[u8], limits: &Limits) -> Result<Status, ParseError> {
+  parse_resume(buf, limits, &mut Chunked::default())
+}
+
+// `progress`保存上一次解析同一个缓冲区时chunked请求体的解码进度
+fn parse_resume(buf: &[u8], limits: &Limits, progress: &mut Chunked) -> Result<Status, ParseError> {
+  let head_end = match find(buf, b"\r\n\r\n") {
+    Some(i) if i + 4 <= limits.max_header_size => i,
+    None if buf.len() < limits.max_header_size => return Ok(Status::Partial),
+    _ => return Err(ParseError::HeaderTooLarge),
+  };
+  let head = match std::str::from_utf8(&buf[..head_end]) {
+    Ok(head) => head,
+    Err(_) => return Err(ParseError::Malformed("request head is not valid UTF-8")),
+  };
+  let mut lines = head.split("\r\n");
+
+  let (method, target, version) = parse_request_line(lines.next().unwrap_or(""))?;
+  let mut headers = HeaderMap::new();
+  for line in lines {
+    let (name, value) = parse_header(line)?;
+    headers.append(&name, value);
+  }
+
+  let mut request = Request {
+    method,
+    target,
+    version,
+    headers,
+    body: Vec::new(),
+    params: HashMap::new(),
+    peer_addr: None,
+    secure: false,
+  };
+  let body_start = head_end + 4;
+
+  // 所有的Transfer-Encoding合起来只能是一个chunked
+  let codings: Vec<&str> = request
+    .headers
+    .get_all("Transfer-Encoding")
+    .flat_map(|value| value.split(','))
+    .map(str::trim)
+    .collect();
+  let chunked = match codings[..] {
+    [] => false,
+    [coding] if coding.eq_ignore_ascii_case("chunked") => true,
+    _ => return Err(ParseError::Malformed("unsupported transfer encoding")),
+  };
+  if chunked {
+    if request.header("Content-Length").is_some() {
+      return Err(ParseError::Malformed(
+        "both Content-Length and Transfer-Encoding are present",
+      ));
+    }
+    return Ok(
+      match progress.decode(&buf[body_start..], limits.max_body_size)? {
+        Some(used) => {
+          request.body = std::mem::take(&mut progress.body);
+          Status::Complete(request, body_start + used)
+        }
+        None => Status::Partial,
+      },
+    );
+  }
+
+  let length = content_length(&request.headers)?.unwrap_or(0);
+  if length > limits.max_body_size {
+    return Err(ParseError::BodyTooLarge);
+  }
+  if buf.len() - body_start < length {
+    return Ok(Status::Partial);
+  }
+  request.body = buf[body_start..body_start + length].to_vec();
+  Ok(Status::Complete(request, body_start + length))
+}
+
+fn parse_request_line(line: &str) -> Result<(String, String, Version), ParseError> {
+  let mut parts = line.split(' ');
+  let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+    (Some(m), Some(t), Some(v), None) => (m, t, v),
+    _ => return Err(ParseError::Malformed("invalid request line")),
+  };
+  if method.is_empty() || !method.bytes().all(is_token) {
+    return Err(ParseError::Malformed("invalid method"));
+  }
+  if target.is_empty() || target.bytes().any(|b| b.is_ascii_control()) {
+    return Err(ParseError::Malformed("invalid request target"));
+  }
+  let version = match version {
+    "HTTP/1.1" => Version::Http11,
+    "HTTP/1.0" => Version::Http10,
+    _ => return Err(ParseError::Malformed("unsupported HTTP version")),
+  };
+  Ok((method.to_string(), target.to_string(), version))
+}
+
+pub(crate) fn parse_header(line: &str) -> Result<(String, String), ParseError> {
+  let colon = match line.find(':') {
+    Some(i) => i,
+    None => return Err(ParseError::Malformed("header without colon")),
+  };
+  let name = &line[..colon];
+  if name.is_empty() || !name.bytes().all(is_token) {
+    return Err(ParseError::Malformed("invalid header name"));
+  }
+  let value = line[colon + 1..].trim_matches(|c| c == ' ' || c == '\t');
+  if !header::is_valid_value(value) {
+    return Err(ParseError::Malformed("invalid header value"));
+  }
+  Ok((name.to_string(), value.to_string()))
+}
+
+/// 解析`Content-Length`, 有多个时必须相同; 没有时返回None
+pub(crate) fn content_length(headers: &HeaderMap) -> Result<Option<usize>, ParseError> {
+  let mut length = None;
+  for value in headers.get_all("Content-Length") {
+    let value = match value.parse::<usize>() {
+      Ok(v) if value.bytes().all(|b| b.is_ascii_digit()) => v,
+      _ => return Err(ParseError::Malformed("invalid Content-Length")),
+    };
+    if length.is_some_and(|l| l != value) {
+      return Err(ParseError::Malformed("conflicting Content-Length headers"));
+    }
+    length = Some(value);
+  }
+  Ok(length)
+}
+
+/// chunked请求体的解码进度
+///
+/// 数据不完整时记下已经解码的部分, 下次从断开的地方继续,
+/// 避免一点点到达的大请求体每次都从头解码。
+#[derive(Debug, Default)]
+struct Chunked {
+  body: Vec<u8>,
+  // 下一个要解析的位置, 相对于请求体开头
+  pos: usize,
+  // 最后一个chunk已经收到, 正在跳过trailer
+  trailer: bool,
+}
+
+impl Chunked {
+  /// 继续解码, 完整时返回请求体占用的字节数, 数据不完整时返回None
+  fn decode(&mut self, buf: &[u8], max_size: usize) -> Result<Option<usize>, ParseError> {
+    while !self.trailer {
+      let line_end = match find(&buf[self.pos..], b"\r\n") {
+        Some(i) => self.pos + i,
+        None => return Ok(None),
+      };
+      let line = std::str::from_utf8(&buf[self.pos..line_end])
+        .map_err(|_| ParseError::Malformed("invalid chunk size"))?;
+      // 忽略chunk扩展
+      let size = line.split(';').next().unwrap_or("").trim();
+      let size = usize::from_str_radix(size, 16)
+        .map_err(|_| ParseError::Malformed("invalid chunk size"))?;
+      let start = line_end + 2;
+
+      if size == 0 {
+        self.pos = start;
+        self.trailer = true;
+        break;
+      }
+      if size > max_size - self.body.len() {
+        return Err(ParseError::BodyTooLarge);
+      }
+      let end = match start.checked_add(size).and_then(|n| n.checked_add(2)) {
+        Some(end) => end,
+        None => return Err(ParseError::BodyTooLarge),
+      };
+      if buf.len() < end {
+        return Ok(None);
+      }
+      if &buf[end - 2..end] != b"\r\n" {
+        return Err(ParseError::Malformed("chunk is not terminated by CRLF"));
+      }
+      self.body.extend_from_slice(&buf[start..end - 2]);
+      self.pos = end;
+    }
+
+    // 跳过trailer首部, 直到空行
+    loop {
+      let line_end = match find(&buf[self.pos..], b"\r\n") {
+        Some(i) => self.pos + i,
+        None => return Ok(None),
+      };
+      let empty = line_end == self.pos;
+      self.pos = line_end + 2;
+      if empty {
+        return Ok(Some(self.pos));
+      }
+    }
+  }
+}
+
+pub(crate) fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+  matches!(
+    e.kind(),
+    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+  )
+}
+
+// RFC 7230中token允许的字符
+fn is_token(b: u8) -> bool {
+  b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// 从流中读取请求
+///
+/// 缓冲区中多读到的数据会留给下一次`read_request`。
+pub struct RequestReader<R> {
+  inner: R,
+  buf: Vec<u8>,
+  limits: Limits,
+  // 当前请求收到第一个字节的时间, 首部收完之后为None
+  head_started: Option<Instant>,
+  // 当前请求收完首部的时间, 请求体收完之后为None
+  body_started: Option<Instant>,
+  chunked: Chunked,
+  // 请求开始之后每次读取之前调用, 参数是首部或请求体剩余的时间, 没有限制时为None
+  before_read: Option<BeforeRead<R>>,
+}
+
+type BeforeRead<R> = fn(&mut R, Option<Duration>) -> io::Result<()>;
+
+impl<R: Read> RequestReader<R> {
+  pub fn new(inner: R) -> RequestReader<R> {
+    RequestReader::with_limits(inner, Limits::default())
+  }
+
+  pub fn with_limits(inner: R, limits: Limits) -> RequestReader<R> {
+    RequestReader {
+      inner,
+      buf: Vec::new(),
+      limits,
+      head_started: None,
+      body_started: None,
+      chunked: Chunked::default(),
+      before_read: None,
+    }
+  }
+
+  /// 设置读取之前的回调, 用来把底层流的读超时缩短到首部或请求体剩余的时间
+  pub(crate) fn before_read(mut self, f: BeforeRead<R>) -> RequestReader<R> {
+    self.before_read = Some(f);
+    self
+  }
+
+  /// 读取下一个请求, 连接在请求开始之前被正常关闭时返回`Ok(None)`
+  ///
+  /// 请求开始之后的读超时会变成`ParseError::Timeout`, 开始之前的仍然是`ParseError::Io`。
+  pub fn read_request(&mut self) -> Result<Option<Request>, ParseError> {
+    let mut chunk = [0; 4096];
+    loop {
+      if !self.buf.is_empty() {
+        let status = parse_resume(&self.buf, &self.limits, &mut self.chunked)?;
+        if let Status::Complete(request, used) = status {
+          self.buf.drain(..used);
+          self.chunked = Chunked::default();
+          self.body_started = None;
+          // 剩下的是下一个流水线请求的开头
+          self.head_started = if self.buf.is_empty() {
+            None
+          } else {
+            Some(Instant::now())
+          };
+          return Ok(Some(request));
+        }
+        self.check_timeout()?;
+        if let Some(before_read) = self.before_read {
+          let remaining = self.remaining();
+          before_read(&mut self.inner, remaining)?;
+        }
+      }
+
+      let n = match self.inner.read(&mut chunk) {
+        Ok(n) => n,
+        Err(ref e) if is_timeout(e) && !self.buf.is_empty() => return Err(ParseError::Timeout),
+        Err(e) => return Err(e.into()),
+      };
+      if n == 0 {
+        return if self.buf.is_empty() {
+          Ok(None)
+        } else {
+          Err(ParseError::UnexpectedEof)
+        };
+      }
+      if self.buf.is_empty() {
+        self.head_started = Some(Instant::now());
+      }
+      self.buf.extend_from_slice(&chunk[..n]);
+    }
+  }
+
+  // 首部收完之后从头开始计算请求体的时间
+  fn check_timeout(&mut self) -> Result<(), ParseError> {
+    if self.head_started.is_some() && find(&self.buf, b"\r\n\r\n").is_some() {
+      self.head_started = None;
+      self.body_started = Some(Instant::now());
+    }
+    match self.remaining() {
+      Some(remaining) if remaining.is_zero() => Err(ParseError::Timeout),
+      _ => Ok(()),
+    }
+  }
+
+  fn remaining(&self) -> Option<Duration> {
+    let (started, timeout) = match (self.head_started, self.body_started) {
+      (Some(started), _) => (started, self.limits.header_timeout?),
+      (None, Some(started)) => (started, self.limits.body_timeout?),
+      (None, None) => return None,
+    };
+    Some(timeout.saturating_sub(started.elapsed()))
+  }
+
+  /// 已经读到但还没有解析的字节数
+  pub fn buffered(&self) -> usize {
+    self.buf.len()
+  }
+
+  pub fn get_ref(&self) -> &R {
+    &self.inner
+  }
+
+  pub fn get_mut(&mut self) -> &mut R {
+    &mut self.inner
+  }
+
+  /// 取回底层的流和已经读入但还没有解析的字节, 用于协议升级之后接管连接
+  pub fn into_parts(self) -> (R, Vec<u8>) {
+    (self.inner, self.buf)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_head_and_body() {
+    let raw = b"POST /users?id=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhelloGET";
+    match parse(raw).unwrap() {
+      Status::Complete(request, used) => {
+        assert_eq!("POST", request.method);
+        assert_eq!("/users", request.path());
+        assert_eq!(Some("id=1"), request.query());
+        assert_eq!(Version::Http11, request.version);
+        assert_eq!(Some("localhost"), request.header("host"));
+        assert_eq!(b"hello".to_vec(), request.body);
+        assert_eq!(raw.len() - 3, used);
+      }
+      Status::Partial => panic!("request should be complete"),
+    }
+  }
+
+  #[test]
+  fn parse_chunked_body() {
+    let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n";
+    for end in 0..raw.len() {
+      assert_eq!(Status::Partial, parse(&raw[..end]).unwrap());
+    }
+    match parse(raw).unwrap() {
+      Status::Complete(request, used) => {
+        assert_eq!(b"hello world".to_vec(), request.body);
+        assert_eq!(raw.len(), used);
+      }
+      Status::Partial => panic!("request should be complete"),
+    }
+  }
+
+  #[test]
+  fn reject_malformed() {
+    let cases: &[&[u8]] = &[
+      b"GET /\r\n\r\n",
+      b"GET / HTTP/2.0\r\n\r\n",
+      b"GET / HTTP/1.1\r\nno colon\r\n\r\n",
+      b"POST / HTTP/1.1\r\nContent-Length: abc\r\n\r\n",
+      b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n",
+      b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+      b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n\r\n",
+      b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n",
+      b"GET / HTTP/1.1\r\nHost: a\rb\r\n\r\n",
+      b"GET / HTTP/1.1\r\nHost: a\x00b\r\n\r\n",
+      b"GET / HTTP/1.1\r\nX: \x1b[31m\r\n\r\n",
+    ];
+    for raw in cases {
+      assert!(matches!(parse(raw), Err(ParseError::Malformed(_))));
+    }
+  }
+
+  #[test]
+  fn enforce_limits() {
+    let limits = Limits {
+      max_header_size: 64,
+      max_body_size: 4,
+      ..Limits::default()
+    };
+    let head_too_large = |raw: &[u8]| {
+      matches!(
+        parse_with_limits(raw, &limits),
+        Err(ParseError::HeaderTooLarge)
+      )
+    };
+    let body_too_large = |raw: &[u8]| {
+      matches!(
+        parse_with_limits(raw, &limits),
+        Err(ParseError::BodyTooLarge)
+      )
+    };
+    let long = "x".repeat(64);
+    // 首部还没收完就已经超过上限
+    assert!(head_too_large(format!("GET /{}", long).as_bytes()));
+    assert!(head_too_large(
+      format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", long).as_bytes()
+    ));
+    assert!(body_too_large(
+      b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n"
+    ));
+    assert!(body_too_large(
+      b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\n"
+    ));
+    assert!(matches!(
+      parse_with_limits(b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nabcd", &limits),
+      Ok(Status::Complete(..))
+    ));
+  }
+
+  #[test]
+  fn huge_chunk_size() {
+    let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\nabc";
+    assert!(matches!(parse(raw), Err(ParseError::BodyTooLarge)));
+  }
+
+  // 每次只返回一个字节的Read, 模拟请求被拆分到多次读取中
+  struct OneByte<'a>(&'a [u8]);
+
+  impl<'a> Read for OneByte<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      match self.0.split_first() {
+        Some((b, rest)) if !buf.is_empty() => {
+          buf[0] = *b;
+          self.0 = rest;
+          Ok(1)
+        }
+        _ => Ok(0),
+      }
+    }
+  }
+
+  #[test]
+  fn read_split_and_pipelined_requests() {
+    let body = "x".repeat(2000);
+    let raw = format!(
+      "PUT /big HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}GET / HTTP/1.0\r\n\r\n",
+      body.len(),
+      body
+    );
+    let mut reader = RequestReader::new(OneByte(raw.as_bytes()));
+
+    let first = reader.read_request().unwrap().unwrap();
+    assert_eq!(body.as_bytes(), &first.body[..]);
+    let second = reader.read_request().unwrap().unwrap();
+    assert_eq!(Version::Http10, second.version);
+    assert!(reader.read_request().unwrap().is_none());
+  }
+
+  #[test]
+  fn read_split_chunked_requests() {
+    let mut raw = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    for i in 0..200 {
+      raw.extend_from_slice(format!("1\r\n{}\r\n", i % 10).as_bytes());
+    }
+    raw.extend_from_slice(b"0\r\n\r\n");
+    raw.extend_from_slice(
+      b"POST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n",
+    );
+    let mut reader = RequestReader::new(OneByte(&raw));
+
+    let first = reader.read_request().unwrap().unwrap();
+    let expected: Vec<u8> = (0..200).map(|i| b'0' + (i % 10) as u8).collect();
+    assert_eq!(expected, first.body);
+    // 解码进度不会带到下一个请求
+    let second = reader.read_request().unwrap().unwrap();
+    assert_eq!("/b", second.path());
+    assert_eq!(b"ok".to_vec(), second.body);
+    assert!(reader.read_request().unwrap().is_none());
+  }
+
+  // 每次读取前先等待一会儿, 模拟慢慢发送首部的客户端
+  struct Slow<'a>(OneByte<'a>, Duration);
+
+  impl<'a> Read for Slow<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      std::thread::sleep(self.1);
+      self.0.read(buf)
+    }
+  }
+
+  #[test]
+  fn header_timeout() {
+    let limits = Limits {
+      header_timeout: Some(Duration::from_millis(50)),
+      ..Limits::default()
+    };
+    let raw = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+    let mut reader =
+      RequestReader::with_limits(Slow(OneByte(raw), Duration::from_millis(10)), limits);
+    assert!(matches!(reader.read_request(), Err(ParseError::Timeout)));
+
+    // 首部按时收完之后, 请求体不受这个限制
+    let body = "x".repeat(20);
+    let head = b"POST / HTTP/1.1\r\nContent-Length: 20\r\n\r\n";
+    let slow_body = Slow(OneByte(body.as_bytes()), Duration::from_millis(5));
+    let limits = Limits {
+      header_timeout: Some(Duration::from_millis(50)),
+      ..Limits::default()
+    };
+    let mut reader = RequestReader::with_limits(Read::chain(&head[..], slow_body), limits);
+    let request = reader.read_request().unwrap().unwrap();
+    assert_eq!(body.as_bytes(), &request.body[..]);
+  }
+
+  #[test]
+  fn body_timeout() {
+    let limits = Limits {
+      header_timeout: Some(Duration::from_millis(50)),
+      body_timeout: Some(Duration::from_millis(50)),
+      ..Limits::default()
+    };
+    // 请求体一点一点地到达, 总时间超过了期限
+    let body = "x".repeat(20);
+    let head = b"POST / HTTP/1.1\r\nContent-Length: 20\r\n\r\n";
+    let slow_body = Slow(OneByte(body.as_bytes()), Duration::from_millis(10));
+    let mut reader = RequestReader::with_limits(Read::chain(&head[..], slow_body), limits);
+    assert!(matches!(reader.read_request(), Err(ParseError::Timeout)));
+
+    // 按时收完的请求体不受影响, 下一个请求重新计时
+    let limits = Limits {
+      header_timeout: Some(Duration::from_secs(1)),
+      body_timeout: Some(Duration::from_millis(50)),
+      ..Limits::default()
+    };
+    let raw = b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\nhiGET / HTTP/1.1\r\n\r\n";
+    let slow = Slow(OneByte(raw), Duration::from_millis(1));
+    let mut reader = RequestReader::with_limits(slow, limits);
+    assert_eq!(b"hi", &reader.read_request().unwrap().unwrap().body[..]);
+    assert_eq!("GET", reader.read_request().unwrap().unwrap().method);
+  }
+}
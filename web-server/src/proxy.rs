@@ -0,0 +1,468 @@
+//! 反向代理
+//!
+//! [`Proxy`]把请求转发给一组上游服务器中的一个, 支持轮询和最少连接两种负载均衡方式。
+//! 转发时去掉逐跳首部, 加上`X-Forwarded-For`、`X-Forwarded-Host`和`X-Forwarded-Proto`,
+//! `X-Forwarded-Proto`只在请求来自配置的可信代理时沿用原来的值;
+//! 上游失败时幂等请求换一个上游重试。配置了健康检查时, 后台线程定期请求每个上游,
+//! 失败的上游暂时不再分配请求, 检查恢复正常后重新加入。
+//!
+//! 请求体和响应体都会完整读入内存, 不支持WebSocket等协议升级。
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use web_server::proxy::Balance;
+//! use web_server::proxy::Proxy;
+//! use web_server::server::Server;
+//!
+//! let proxy = Proxy::new(&["127.0.0.1:8081", "127.0.0.1:8082"])
+//!   .balance(Balance::LeastConnections)
+//!   .health_check("/health", Duration::from_secs(5));
+//! Server::bind("0.0.0.0:8080", proxy).unwrap().run().unwrap();
+//! ```
+
+use std::net::IpAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::client::Client;
+use crate::client::ClientError;
+use crate::handler::Handler;
+use crate::header::HeaderMap;
+use crate::request::Request;
+use crate::response::Response;
+use crate::response::StatusCode;
+
+// 只对一跳连接有意义的首部, 不能转发; `Connection`中列出的首部也是
+const HOP_BY_HOP: [&str; 9] = [
+  "Connection",
+  "Keep-Alive",
+  "Proxy-Connection",
+  "Proxy-Authenticate",
+  "Proxy-Authorization",
+  "TE",
+  "Trailer",
+  "Transfer-Encoding",
+  "Upgrade",
+];
+
+/// 负载均衡方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+  /// 依次分配给每个上游
+  RoundRobin,
+  /// 分配给正在处理的请求最少的上游, 一样多时轮流
+  LeastConnections,
+}
+
+struct Upstream {
+  addr: String,
+  // 正在转发的请求数
+  active: AtomicUsize,
+  healthy: AtomicBool,
+}
+
+impl Upstream {
+  fn set_healthy(&self, healthy: bool) {
+    if self.healthy.swap(healthy, Ordering::SeqCst) != healthy {
+      let state = if healthy { "up" } else { "down" };
+      eprintln!("Upstream {} is {}", self.addr, state);
+    }
+  }
+}
+
+/// 把请求转发给上游服务器的handler
+pub struct Proxy {
+  upstreams: Arc<Vec<Upstream>>,
+  balance: Balance,
+  retries: usize,
+  client: Client,
+  next: AtomicUsize,
+  trusted_proxies: Vec<IpAddr>,
+  // 健康检查线程在这个Sender被丢弃时退出
+  health_check: Option<mpsc::Sender<()>>,
+}
+
+impl Proxy {
+  /// 上游服务器的地址, 例如`127.0.0.1:8081`
+  ///
+  /// # Panics
+  ///
+  /// `upstreams`为空时panic.
+  pub fn new(upstreams: &[&str]) -> Proxy {
+    assert!(!upstreams.is_empty());
+    let upstreams = upstreams
+      .iter()
+      .map(|addr| Upstream {
+        addr: addr.to_string(),
+        active: AtomicUsize::new(0),
+        healthy: AtomicBool::new(true),
+      })
+      .collect();
+    Proxy {
+      upstreams: Arc::new(upstreams),
+      balance: Balance::RoundRobin,
+      retries: 1,
+      client: Client::new(),
+      next: AtomicUsize::new(0),
+      trusted_proxies: Vec::new(),
+      health_check: None,
+    }
+  }
+
+  /// 负载均衡方式, 默认轮询
+  pub fn balance(mut self, balance: Balance) -> Proxy {
+    self.balance = balance;
+    self
+  }
+
+  /// 失败后最多换几个上游重试, 默认1次
+  ///
+  /// 幂等请求在任何失败后都会重试; 其他请求只在连接被拒绝时重试, 因为这时请求肯定没有发出去。
+  pub fn retries(mut self, retries: usize) -> Proxy {
+    self.retries = retries;
+    self
+  }
+
+  /// 前面的代理, 只有从这些地址来的请求才沿用已有的`X-Forwarded-Proto`, 默认没有
+  pub fn trusted_proxies(mut self, proxies: &[IpAddr]) -> Proxy {
+    self.trusted_proxies = proxies.to_vec();
+    self
+  }
+
+  /// 用于转发请求的客户端, 可以设置超时和连接池大小
+  pub fn client(mut self, client: Client) -> Proxy {
+    self.client = client;
+    self
+  }
+
+  /// 每隔`interval`对每个上游发送`GET path`, 返回2xx或3xx之外的结果时暂停使用这个上游
+  ///
+  /// 启用后转发时连接失败的上游也会被立即暂停, 直到下一次检查成功。
+  pub fn health_check(mut self, path: &str, interval: Duration) -> Proxy {
+    let (stop, stopped) = mpsc::channel();
+    let upstreams = Arc::clone(&self.upstreams);
+    let path = path.to_string();
+    let spawned = thread::Builder::new()
+      .name("health-check".to_string())
+      .spawn(move || check_health(&upstreams, &path, interval, stopped));
+    match spawned {
+      Ok(_) => self.health_check = Some(stop),
+      Err(e) => eprintln!("Failed to spawn health check thread: {}", e),
+    }
+    self
+  }
+
+  // 选出一个健康并且这次请求还没有试过的上游
+  fn pick(&self, tried: &[usize]) -> Option<usize> {
+    let n = self.upstreams.len();
+    let start = self.next.fetch_add(1, Ordering::Relaxed);
+    let mut candidates = (0..n)
+      .map(|i| (start + i) % n)
+      .filter(|i| !tried.contains(i) && self.upstreams[*i].healthy.load(Ordering::SeqCst));
+    match self.balance {
+      Balance::RoundRobin => candidates.next(),
+      Balance::LeastConnections => {
+        candidates.min_by_key(|&i| self.upstreams[i].active.load(Ordering::SeqCst))
+      }
+    }
+  }
+
+  fn forward(&self, upstream: &Upstream, request: &Request) -> Result<Response, ClientError> {
+    upstream.active.fetch_add(1, Ordering::SeqCst);
+    let result = self.client.send(&upstream.addr, request.clone());
+    upstream.active.fetch_sub(1, Ordering::SeqCst);
+    let mut response = result?;
+    remove_hop_by_hop(&mut response.headers);
+    Ok(response)
+  }
+}
+
+impl Handler for Proxy {
+  fn call(&self, request: &mut Request) -> Response {
+    let forwarded = forwarded_request(request, &self.trusted_proxies);
+    let idempotent = matches!(
+      request.method.as_str(),
+      "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS" | "TRACE"
+    );
+    let mut tried = Vec::new();
+    let mut last_error = None;
+    while tried.len() <= self.retries {
+      let i = match self.pick(&tried) {
+        Some(i) => i,
+        None => break,
+      };
+      tried.push(i);
+      let upstream = &self.upstreams[i];
+      let e = match self.forward(upstream, &forwarded) {
+        Ok(response) => return response,
+        Err(e) => e,
+      };
+      eprintln!("Failed to proxy to {}: {}", upstream.addr, e);
+      let refused = is_refused(&e);
+      if refused && self.health_check.is_some() {
+        upstream.set_healthy(false);
+      }
+      last_error = Some(e);
+      if !idempotent && !refused {
+        break;
+      }
+    }
+    match last_error {
+      None => Response::text(StatusCode::ServiceUnavailable, "no healthy upstream"),
+      Some(ClientError::Timeout) => {
+        Response::text(StatusCode::GatewayTimeout, "upstream timed out")
+      }
+      Some(_) => Response::text(StatusCode::BadGateway, "upstream failed"),
+    }
+  }
+}
+
+// 发给上游的请求: 去掉逐跳首部, 加上X-Forwarded-*, Host保持不变
+fn forwarded_request(request: &Request, trusted_proxies: &[IpAddr]) -> Request {
+  let mut forwarded = Request::new(&request.method, &request.target);
+  forwarded.headers = request.headers.clone();
+  forwarded.body = request.body.clone();
+  remove_hop_by_hop(&mut forwarded.headers);
+
+  if let Some(peer_addr) = request.peer_addr {
+    let client = peer_addr.ip().to_string();
+    let chain = match request.header("X-Forwarded-For") {
+      Some(chain) => format!("{}, {}", chain, client),
+      None => client,
+    };
+    forwarded.headers.insert("X-Forwarded-For", chain);
+  }
+  if let Some(host) = request.header("Host") {
+    forwarded.headers.insert("X-Forwarded-Host", host);
+  }
+  // 客户端自己发来的值不可信, 只沿用可信代理设置的值
+  let trusted = request
+    .peer_addr
+    .is_some_and(|addr| trusted_proxies.contains(&addr.ip()));
+  let proto = match request.header("X-Forwarded-Proto") {
+    Some(proto) if trusted => proto.to_string(),
+    _ => request.scheme().to_string(),
+  };
+  forwarded.headers.insert("X-Forwarded-Proto", proto);
+  forwarded
+}
+
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+  let listed: Vec<String> = headers
+    .get_all("Connection")
+    .flat_map(|value| value.split(','))
+    .map(|name| name.trim().to_string())
+    .filter(|name| !name.is_empty())
+    .collect();
+  for name in HOP_BY_HOP
+    .iter()
+    .copied()
+    .chain(listed.iter().map(String::as_str))
+  {
+    headers.remove(name);
+  }
+}
+
+// 连接被拒绝时请求还没有发出, 任何请求都可以安全地重试
+fn is_refused(e: &ClientError) -> bool {
+  matches!(e, ClientError::Io(e) if e.kind() == std::io::ErrorKind::ConnectionRefused)
+}
+
+fn check_health(
+  upstreams: &[Upstream],
+  path: &str,
+  interval: Duration,
+  stopped: mpsc::Receiver<()>,
+) {
+  let client = Client::new()
+    .connect_timeout(interval)
+    .read_timeout(interval)
+    .write_timeout(interval)
+    .max_idle_per_host(0);
+  loop {
+    for upstream in upstreams {
+      let healthy = client
+        .send(&upstream.addr, Request::new("GET", path))
+        .is_ok_and(|response| (200..400).contains(&response.status.code()));
+      upstream.set_healthy(healthy);
+    }
+    if stopped.recv_timeout(interval) != Err(mpsc::RecvTimeoutError::Timeout) {
+      return;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::TcpListener;
+
+  use super::*;
+  use crate::server::Server;
+  use crate::server::ServerHandle;
+
+  fn upstream(name: &'static str) -> (String, ServerHandle) {
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .build(move |request: &mut Request| {
+        let header = |name: &str| request.header(name).unwrap_or("-").to_string();
+        let body = format!(
+          "{} {} host={} xff={} xfh={} xfp={} secret={}",
+          name,
+          request.target,
+          header("Host"),
+          header("X-Forwarded-For"),
+          header("X-Forwarded-Host"),
+          header("X-Forwarded-Proto"),
+          header("X-Secret"),
+        );
+        Response::text(StatusCode::Ok, body).with_header("Keep-Alive", "timeout=5")
+      })
+      .unwrap();
+    let addr = server.local_addr().to_string();
+    let handle = server.handle();
+    thread::spawn(move || server.run().unwrap());
+    (addr, handle)
+  }
+
+  // 连接会被拒绝的地址
+  fn dead() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+  }
+
+  // 接受连接之后什么也不返回就关闭
+  fn broken() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        drop(stream);
+      }
+    });
+    addr
+  }
+
+  fn get(proxy: &Proxy, target: &str) -> Response {
+    let mut request = Request::new("GET", target);
+    request.headers.insert("Host", "example.com");
+    request.peer_addr = Some("192.0.2.7:1234".parse().unwrap());
+    proxy.call(&mut request)
+  }
+
+  fn body(response: Response) -> String {
+    String::from_utf8(response.body.into_bytes().unwrap()).unwrap()
+  }
+
+  #[test]
+  fn forward_round_robin() {
+    let (a, a_handle) = upstream("a");
+    let (b, b_handle) = upstream("b");
+    let proxy = Proxy::new(&[&a, &b]);
+
+    let mut request = Request::new("GET", "/echo?x=1");
+    request.headers.insert("Host", "example.com");
+    request.headers.insert("Connection", "keep-alive, X-Secret");
+    request.headers.insert("X-Secret", "1");
+    request.headers.insert("X-Forwarded-For", "10.0.0.1");
+    request.peer_addr = Some("192.0.2.7:1234".parse().unwrap());
+    let response = proxy.call(&mut request);
+    assert_eq!(StatusCode::Ok, response.status);
+    assert_eq!(None, response.headers.get("Keep-Alive"));
+    assert_eq!(
+      "a /echo?x=1 host=example.com xff=10.0.0.1, 192.0.2.7 xfh=example.com xfp=http secret=-",
+      body(response)
+    );
+
+    let names: Vec<String> = (0..4)
+      .map(|_| body(get(&proxy, "/"))[..1].to_string())
+      .collect();
+    assert_eq!(vec!["b", "a", "b", "a"], names);
+
+    a_handle.shutdown();
+    b_handle.shutdown();
+  }
+
+  #[test]
+  fn forwarded_proto() {
+    let trusted: IpAddr = "10.0.0.1".parse().unwrap();
+    let proto = |peer: &str, secure: bool, header: Option<&str>| {
+      let mut request = Request::new("GET", "/");
+      request.peer_addr = Some(peer.parse().unwrap());
+      request.secure = secure;
+      if let Some(header) = header {
+        request.headers.insert("X-Forwarded-Proto", header);
+      }
+      let forwarded = forwarded_request(&request, &[trusted]);
+      forwarded.header("X-Forwarded-Proto").unwrap().to_string()
+    };
+    assert_eq!("http", proto("192.0.2.7:1234", false, None));
+    assert_eq!("https", proto("192.0.2.7:1234", true, None));
+    // 客户端伪造的值被覆盖
+    assert_eq!("http", proto("192.0.2.7:1234", false, Some("https")));
+    assert_eq!("https", proto("192.0.2.7:1234", true, Some("http")));
+    // 可信代理设置的值原样保留
+    assert_eq!("https", proto("10.0.0.1:1234", false, Some("https")));
+    assert_eq!("http", proto("10.0.0.1:1234", false, None));
+  }
+
+  #[test]
+  fn least_connections() {
+    let proxy = Proxy::new(&["a:1", "b:1", "c:1"]).balance(Balance::LeastConnections);
+    proxy.upstreams[0].active.store(2, Ordering::SeqCst);
+    proxy.upstreams[1].active.store(1, Ordering::SeqCst);
+    proxy.upstreams[2].active.store(1, Ordering::SeqCst);
+    // 一样多时从轮询的位置开始找
+    let picks: Vec<Option<usize>> = (0..3).map(|_| proxy.pick(&[])).collect();
+    assert_eq!(vec![Some(1), Some(1), Some(2)], picks);
+    assert_eq!(Some(0), proxy.pick(&[1, 2]));
+    proxy.upstreams[0].healthy.store(false, Ordering::SeqCst);
+    assert_eq!(None, proxy.pick(&[1, 2]));
+  }
+
+  #[test]
+  fn retries() {
+    let (ok, handle) = upstream("ok");
+    // 幂等请求换一个上游重试, 其他请求只在连接被拒绝时重试
+    let proxy = Proxy::new(&[&broken(), &ok]);
+    assert_eq!(StatusCode::Ok, get(&proxy, "/").status);
+    let mut post = Request::new("POST", "/");
+    assert_eq!(StatusCode::BadGateway, proxy.call(&mut post).status);
+
+    let proxy = Proxy::new(&[&dead(), &ok]);
+    assert_eq!(StatusCode::Ok, proxy.call(&mut post).status);
+    let proxy = Proxy::new(&[&dead(), &dead()]).retries(0);
+    assert_eq!(StatusCode::BadGateway, get(&proxy, "/").status);
+
+    handle.shutdown();
+  }
+
+  #[test]
+  fn health_checks() {
+    let (ok, handle) = upstream("ok");
+    let proxy =
+      Proxy::new(&[&dead(), &ok, &broken()]).health_check("/health", Duration::from_millis(50));
+    thread::sleep(Duration::from_millis(300));
+    let healthy: Vec<bool> = proxy
+      .upstreams
+      .iter()
+      .map(|u| u.healthy.load(Ordering::SeqCst))
+      .collect();
+    assert_eq!(vec![false, true, false], healthy);
+    let proxy = proxy.retries(0);
+    for _ in 0..3 {
+      assert!(body(get(&proxy, "/")).starts_with("ok"));
+    }
+
+    // 所有上游都被暂停时返回503
+    handle.shutdown();
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(StatusCode::ServiceUnavailable, get(&proxy, "/").status);
+  }
+}
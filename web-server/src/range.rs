@@ -0,0 +1,164 @@
+//! `Range: bytes=...`请求首部的解析
+
+/// 单个字节区间, 两端都包含在内
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+  pub start: u64,
+  pub end: u64,
+}
+
+impl ByteRange {
+  /// 区间包含的字节数, 至少为1
+  pub fn size(&self) -> u64 {
+    self.end - self.start + 1
+  }
+
+  /// `Content-Range`首部的值, 例如`bytes 0-99/1000`
+  pub fn content_range(&self, total: u64) -> String {
+    format!("bytes {}-{}/{}", self.start, self.end, total)
+  }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ranges {
+  Satisfiable(Vec<ByteRange>),
+  /// 语法正确但没有一个区间落在资源范围内, 应该返回416
+  Unsatisfiable,
+}
+
+// 区间过多时很可能是恶意请求, 直接忽略Range返回完整内容
+const MAX_RANGES: usize = 16;
+
+/// 按资源长度`len`解析`Range`首部
+///
+/// 支持`start-end`、`start-`和`-suffix`三种写法。语法错误、不是`bytes`单位
+/// 或者区间数量过多时返回None, 此时应当忽略这个首部。
+///
+/// # Examples
+///
+/// ```
+/// use web_server::range::{parse, ByteRange, Ranges};
+///
+/// assert_eq!(
+///   Some(Ranges::Satisfiable(vec![ByteRange { start: 90, end: 99 }])),
+///   parse("bytes=-10", 100)
+/// );
+/// assert_eq!(Some(Ranges::Unsatisfiable), parse("bytes=100-", 100));
+/// assert_eq!(None, parse("lines=1-2", 100));
+/// ```
+pub fn parse(header: &str, len: u64) -> Option<Ranges> {
+  let (unit, specs) = header.trim().split_once('=')?;
+  if !unit.trim().eq_ignore_ascii_case("bytes") {
+    return None;
+  }
+
+  let mut ranges = Vec::new();
+  let mut count = 0;
+  for spec in specs.split(',') {
+    let spec = spec.trim();
+    if spec.is_empty() {
+      continue;
+    }
+    count += 1;
+    if count > MAX_RANGES {
+      return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let range = if first.is_empty() {
+      // 最后suffix个字节
+      let suffix = parse_number(last)?;
+      if suffix == 0 || len == 0 {
+        continue;
+      }
+      ByteRange {
+        start: len.saturating_sub(suffix),
+        end: len - 1,
+      }
+    } else {
+      let start = parse_number(first)?;
+      let end = match last {
+        "" => u64::MAX,
+        last => parse_number(last)?,
+      };
+      if end < start {
+        return None;
+      }
+      if start >= len {
+        continue;
+      }
+      ByteRange {
+        start,
+        end: end.min(len - 1),
+      }
+    };
+    ranges.push(range);
+  }
+
+  if count == 0 {
+    return None;
+  }
+  if ranges.is_empty() {
+    return Some(Ranges::Unsatisfiable);
+  }
+  Some(Ranges::Satisfiable(ranges))
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+  if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+    return None;
+  }
+  s.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn range(start: u64, end: u64) -> ByteRange {
+    ByteRange { start, end }
+  }
+
+  #[test]
+  fn parse_specs() {
+    assert_eq!(
+      Some(Ranges::Satisfiable(vec![
+        range(0, 9),
+        range(50, 99),
+        range(95, 99)
+      ])),
+      parse("bytes=0-9, 50-, -5", 100)
+    );
+    assert_eq!(
+      Some(Ranges::Satisfiable(vec![range(90, 99)])),
+      parse("bytes=90-1000", 100)
+    );
+    assert_eq!(
+      Some(Ranges::Satisfiable(vec![range(0, 99)])),
+      parse("bytes=-500", 100)
+    );
+    // 落在范围外的区间被丢弃
+    assert_eq!(
+      Some(Ranges::Satisfiable(vec![range(0, 0)])),
+      parse("bytes=200-300,0-0", 100)
+    );
+    assert_eq!(Some(Ranges::Unsatisfiable), parse("bytes=200-300", 100));
+    assert_eq!(Some(Ranges::Unsatisfiable), parse("bytes=-0", 100));
+  }
+
+  #[test]
+  fn ignore_invalid() {
+    for header in &[
+      "bytes",
+      "bytes=",
+      "bytes=5",
+      "bytes=9-1",
+      "bytes=a-b",
+      "bytes=+1-2",
+      "items=0-1",
+    ] {
+      assert_eq!(None, parse(header, 100), "{}", header);
+    }
+    let many = format!("bytes={}", vec!["0-1"; 17].join(","));
+    assert_eq!(None, parse(&many, 100));
+  }
+}
@@ -0,0 +1,308 @@
+//! 按客户端限流的中间件(令牌桶)
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::middleware::Middleware;
+use crate::middleware::Next;
+use crate::request::Request;
+use crate::response::Response;
+use crate::response::StatusCode;
+
+// 分片数, 不同客户端的请求大多落在不同的锁上
+const SHARDS: usize = 16;
+
+struct Bucket {
+  tokens: f64,
+  updated: Instant,
+}
+
+struct Shard {
+  buckets: HashMap<String, Bucket>,
+  last_sweep: Instant,
+}
+
+/// 每个客户端一个令牌桶, 令牌用完时返回429和`Retry-After`
+///
+/// 默认按对端IP区分客户端。在代理后面时可以改用某个首部, 但只有对端是配置的可信代理时
+/// 才使用: 从右往左跳过可信代理的地址, 取第一个其他的值。更左边的值是客户端自己发来的,
+/// 可以随意伪造。首部不存在或者全是可信代理时仍然按对端IP, 没有对端地址的请求不限流。
+///
+/// ```
+/// use std::time::Duration;
+/// use web_server::rate_limit::RateLimit;
+///
+/// // 每秒10个请求, 最多攒20个
+/// let limit = RateLimit::new(10.0, 20)
+///   .key_header("X-Forwarded-For")
+///   .trusted_proxies(&["10.0.0.1".parse().unwrap()])
+///   .idle_timeout(Duration::from_secs(300));
+/// ```
+pub struct RateLimit {
+  rate: f64,
+  burst: f64,
+  header: Option<String>,
+  trusted_proxies: Vec<IpAddr>,
+  idle_timeout: Duration,
+  shards: Vec<Mutex<Shard>>,
+}
+
+impl RateLimit {
+  /// 每秒补充`rate`个令牌, 桶里最多`burst`个
+  ///
+  /// # Panics
+  ///
+  /// `rate`不是正数, 或者小到补满一个桶的时间无法用`Duration`表示时panic.
+  pub fn new(rate: f64, burst: u32) -> RateLimit {
+    let burst = burst.max(1) as f64;
+    assert!(rate > 0.0, "rate must be positive");
+    assert!(
+      Duration::try_from_secs_f64(burst / rate).is_ok(),
+      "rate is too small"
+    );
+    let now = Instant::now();
+    RateLimit {
+      rate,
+      burst,
+      header: None,
+      trusted_proxies: Vec::new(),
+      idle_timeout: Duration::from_secs(60),
+      shards: (0..SHARDS)
+        .map(|_| {
+          Mutex::new(Shard {
+            buckets: HashMap::new(),
+            last_sweep: now,
+          })
+        })
+        .collect(),
+    }
+  }
+
+  /// 用这个首部的值区分客户端, 需要同时设置`trusted_proxies`
+  pub fn key_header(mut self, name: &str) -> RateLimit {
+    self.header = Some(name.to_string());
+    self
+  }
+
+  /// 前面的代理, 只有从这些地址来的请求才使用`key_header`, 默认没有
+  pub fn trusted_proxies(mut self, proxies: &[IpAddr]) -> RateLimit {
+    self.trusted_proxies = proxies.to_vec();
+    self
+  }
+
+  /// 超过这段时间没有请求的桶会被清理, 默认60秒
+  ///
+  /// 不会短于把桶补满需要的时间, 清理掉的桶和新建的桶没有区别。
+  pub fn idle_timeout(mut self, timeout: Duration) -> RateLimit {
+    self.idle_timeout = timeout;
+    self
+  }
+
+  fn key(&self, request: &Request) -> Option<String> {
+    let peer = request.peer_addr?.ip();
+    let header = match &self.header {
+      Some(name) if self.is_trusted(peer) => request.header(name),
+      _ => None,
+    };
+    let client = header
+      .into_iter()
+      .flat_map(|value| value.rsplit(','))
+      .map(str::trim)
+      .filter(|value| !value.is_empty())
+      .find(|value| !value.parse().is_ok_and(|ip| self.is_trusted(ip)));
+    Some(client.map_or_else(|| peer.to_string(), str::to_string))
+  }
+
+  fn is_trusted(&self, ip: IpAddr) -> bool {
+    self.trusted_proxies.contains(&ip)
+  }
+
+  fn shard(&self, key: &str) -> &Mutex<Shard> {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    &self.shards[hasher.finish() as usize % SHARDS]
+  }
+
+  /// 取一个令牌, 没有时返回还要等多久
+  fn acquire(&self, key: &str, now: Instant) -> Result<(), Duration> {
+    let idle = self
+      .idle_timeout
+      .max(Duration::from_secs_f64(self.burst / self.rate));
+    let mut shard = self.shard(key).lock().unwrap();
+    if now.saturating_duration_since(shard.last_sweep) >= idle {
+      shard
+        .buckets
+        .retain(|_, bucket| now.saturating_duration_since(bucket.updated) < idle);
+      shard.last_sweep = now;
+    }
+
+    let bucket = shard.buckets.entry(key.to_string()).or_insert(Bucket {
+      tokens: self.burst,
+      updated: now,
+    });
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+    bucket.updated = now;
+    if bucket.tokens >= 1.0 {
+      bucket.tokens -= 1.0;
+      Ok(())
+    } else {
+      Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+    }
+  }
+
+  #[cfg(test)]
+  fn buckets(&self) -> usize {
+    self
+      .shards
+      .iter()
+      .map(|shard| shard.lock().unwrap().buckets.len())
+      .sum()
+  }
+}
+
+impl Middleware for RateLimit {
+  fn handle(&self, request: &mut Request, next: Next) -> Response {
+    let key = match self.key(request) {
+      Some(key) => key,
+      None => return next.run(request),
+    };
+    match self.acquire(&key, Instant::now()) {
+      Ok(()) => next.run(request),
+      Err(wait) => {
+        // 向上取整到秒, 至少1秒
+        let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        Response::text(
+          StatusCode::TooManyRequests,
+          StatusCode::TooManyRequests.reason(),
+        )
+        .with_header("Retry-After", seconds.max(1).to_string())
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::handler::Handler;
+  use crate::middleware::Chain;
+
+  #[test]
+  fn token_bucket() {
+    let limit = RateLimit::new(2.0, 3);
+    let start = Instant::now();
+    for _ in 0..3 {
+      assert_eq!(Ok(()), limit.acquire("a", start));
+    }
+    assert_eq!(Err(Duration::from_millis(500)), limit.acquire("a", start));
+    // 别的客户端不受影响
+    assert_eq!(Ok(()), limit.acquire("b", start));
+
+    let later = start + Duration::from_millis(500);
+    assert_eq!(Ok(()), limit.acquire("a", later));
+    assert!(limit.acquire("a", later).is_err());
+    // 补满之后不会超过burst
+    let much_later = start + Duration::from_secs(60);
+    for _ in 0..3 {
+      assert_eq!(Ok(()), limit.acquire("a", much_later));
+    }
+    assert!(limit.acquire("a", much_later).is_err());
+  }
+
+  #[test]
+  fn evict_idle_buckets() {
+    let limit = RateLimit::new(1.0, 1).idle_timeout(Duration::from_secs(10));
+    let start = Instant::now();
+    for i in 0..100 {
+      limit.acquire(&i.to_string(), start).unwrap();
+    }
+    assert_eq!(100, limit.buckets());
+
+    let later = start + Duration::from_secs(10);
+    for i in 0..SHARDS * 10 {
+      let _ = limit.acquire(&format!("new-{}", i), later);
+    }
+    assert_eq!(SHARDS * 10, limit.buckets());
+  }
+
+  #[test]
+  fn reject_tiny_rate() {
+    assert!(std::panic::catch_unwind(|| RateLimit::new(0.0, 1)).is_err());
+    assert!(std::panic::catch_unwind(|| RateLimit::new(f64::MIN_POSITIVE, 10)).is_err());
+    assert!(std::panic::catch_unwind(|| RateLimit::new(f64::NAN, 10)).is_err());
+    RateLimit::new(1e-6, 1000);
+  }
+
+  #[test]
+  fn limit_by_peer_or_header() {
+    let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+    let chain = Chain::new(|_: &mut Request| Response::ok()).with(
+      RateLimit::new(0.1, 1)
+        .key_header("X-Forwarded-For")
+        .trusted_proxies(&[proxy]),
+    );
+    let request = |peer: &str, forwarded: Option<&str>| {
+      let mut request = Request::new("GET", "/");
+      request.peer_addr = Some(peer.parse().unwrap());
+      if let Some(value) = forwarded {
+        request.headers.insert("X-Forwarded-For", value);
+      }
+      request
+    };
+
+    assert_eq!(
+      StatusCode::Ok,
+      chain.call(&mut request("10.0.0.1:1000", None)).status
+    );
+    // 同一个IP的不同端口算同一个客户端
+    let response = chain.call(&mut request("10.0.0.1:1001", None));
+    assert_eq!(StatusCode::TooManyRequests, response.status);
+    assert_eq!(Some("10"), response.headers.get("Retry-After"));
+
+    // 可信代理转发来的请求按代理加上的客户端地址, 跳过代理自己
+    let forwarded = Some("192.168.0.1, 10.0.0.1");
+    assert_eq!(
+      StatusCode::Ok,
+      chain.call(&mut request("10.0.0.1:1002", forwarded)).status
+    );
+    let forwarded = Some("192.168.0.1");
+    let response = chain.call(&mut request("10.0.0.1:1003", forwarded));
+    assert_eq!(StatusCode::TooManyRequests, response.status);
+
+    // 客户端每次换一个伪造的地址, 仍然按代理看到的地址限流
+    for fake in ["1.1.1.1", "2.2.2.2", "3.3.3.3"] {
+      let forwarded = format!("{}, 192.168.0.2", fake);
+      let status = chain
+        .call(&mut request("10.0.0.1:1004", Some(&forwarded)))
+        .status;
+      assert_eq!(
+        fake == "1.1.1.1",
+        status == StatusCode::Ok,
+        "{} got {:?}",
+        fake,
+        status
+      );
+    }
+    // 不是可信代理时忽略首部
+    let response = chain.call(&mut request("10.0.0.2:1000", Some("4.4.4.4")));
+    assert_eq!(StatusCode::Ok, response.status);
+    let response = chain.call(&mut request("10.0.0.2:1001", Some("5.5.5.5")));
+    assert_eq!(StatusCode::TooManyRequests, response.status);
+
+    // 不知道是谁的请求不限流
+    let chain = Chain::new(|_: &mut Request| Response::ok()).with(RateLimit::new(0.1, 1));
+    for _ in 0..3 {
+      assert_eq!(
+        StatusCode::Ok,
+        chain.call(&mut Request::new("GET", "/")).status
+      );
+    }
+  }
+}
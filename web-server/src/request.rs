@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::net::SocketAddr;
+
+use crate::header::HeaderMap;
+
+/// HTTP协议版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+  Http10,
+  Http11,
+}
+
+impl fmt::Display for Version {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Version::Http10 => write!(f, "HTTP/1.0"),
+      Version::Http11 => write!(f, "HTTP/1.1"),
+    }
+  }
+}
+
+/// 解析完成的HTTP请求
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+  pub method: String,
+  /// 请求行中的目标, 例如`/users/1?page=2`
+  pub target: String,
+  pub version: Version,
+  pub headers: HeaderMap,
+  /// 已经按Content-Length或chunked解码后的请求体
+  pub body: Vec<u8>,
+  /// 路由匹配到的路径参数
+  pub params: HashMap<String, String>,
+  /// 客户端地址, 不是从网络连接读到的请求为None
+  pub peer_addr: Option<SocketAddr>,
+  /// 是否通过TLS连接收到
+  pub secure: bool,
+}
+
+impl Request {
+  /// 创建一个没有首部和请求体的HTTP/1.1请求
+  pub fn new(method: &str, target: &str) -> Request {
+    Request {
+      method: method.to_string(),
+      target: target.to_string(),
+      version: Version::Http11,
+      headers: HeaderMap::new(),
+      body: Vec::new(),
+      params: HashMap::new(),
+      peer_addr: None,
+      secure: false,
+    }
+  }
+
+  /// 客户端连接使用的协议, `https`或`http`
+  pub fn scheme(&self) -> &'static str {
+    match self.secure {
+      true => "https",
+      false => "http",
+    }
+  }
+
+  /// 不区分大小写地查找首部, 有多个同名首部时返回第一个
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self.headers.get(name)
+  }
+
+  /// 去掉查询字符串后的路径
+  pub fn path(&self) -> &str {
+    match self.target.find('?') {
+      Some(i) => &self.target[..i],
+      None => &self.target,
+    }
+  }
+
+  /// `?`之后的查询字符串
+  pub fn query(&self) -> Option<&str> {
+    self.target.find('?').map(|i| &self.target[i + 1..])
+  }
+
+  /// 序列化后写入`w`, 用于客户端发送请求
+  ///
+  /// `body`是解码后的字节: 首部中有`Transfer-Encoding: chunked`时按chunked编码写出,
+  /// 否则在没有`Content-Length`时补上。
+  pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    let chunked = self.headers.has_token("Transfer-Encoding", "chunked");
+    let mut head = format!("{} {} {}\r\n", self.method, self.target, self.version);
+    for (name, value) in self.headers.iter() {
+      head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    // POST等请求即使没有请求体也要说明长度, 否则有的服务器会返回411
+    let needs_length =
+      !self.body.is_empty() || matches!(self.method.as_str(), "POST" | "PUT" | "PATCH");
+    if !chunked && needs_length && !self.headers.contains("Content-Length") {
+      head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+    }
+    head.push_str("\r\n");
+    w.write_all(head.as_bytes())?;
+    if chunked {
+      if !self.body.is_empty() {
+        write!(w, "{:x}\r\n", self.body.len())?;
+        w.write_all(&self.body)?;
+        w.write_all(b"\r\n")?;
+      }
+      w.write_all(b"0\r\n\r\n")?;
+    } else {
+      w.write_all(&self.body)?;
+    }
+    w.flush()
+  }
+}
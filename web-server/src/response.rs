@@ -0,0 +1,382 @@
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use crate::body::Body;
+use crate::header;
+use crate::header::HeaderMap;
+use crate::server::Transport;
+
+/// HTTP状态码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusCode {
+  SwitchingProtocols,
+  Ok,
+  Created,
+  NoContent,
+  PartialContent,
+  MovedPermanently,
+  Found,
+  NotModified,
+  PermanentRedirect,
+  BadRequest,
+  Unauthorized,
+  Forbidden,
+  NotFound,
+  MethodNotAllowed,
+  RequestTimeout,
+  PayloadTooLarge,
+  RangeNotSatisfiable,
+  UpgradeRequired,
+  TooManyRequests,
+  RequestHeaderFieldsTooLarge,
+  InternalServerError,
+  NotImplemented,
+  BadGateway,
+  ServiceUnavailable,
+  GatewayTimeout,
+  /// 上面没有列出的状态码, 例如客户端从上游收到的响应; 原因短语为空
+  Other(u16),
+}
+
+impl StatusCode {
+  pub fn code(&self) -> u16 {
+    match self {
+      StatusCode::SwitchingProtocols => 101,
+      StatusCode::Ok => 200,
+      StatusCode::Created => 201,
+      StatusCode::NoContent => 204,
+      StatusCode::PartialContent => 206,
+      StatusCode::MovedPermanently => 301,
+      StatusCode::Found => 302,
+      StatusCode::NotModified => 304,
+      StatusCode::PermanentRedirect => 308,
+      StatusCode::BadRequest => 400,
+      StatusCode::Unauthorized => 401,
+      StatusCode::Forbidden => 403,
+      StatusCode::NotFound => 404,
+      StatusCode::MethodNotAllowed => 405,
+      StatusCode::RequestTimeout => 408,
+      StatusCode::PayloadTooLarge => 413,
+      StatusCode::RangeNotSatisfiable => 416,
+      StatusCode::UpgradeRequired => 426,
+      StatusCode::TooManyRequests => 429,
+      StatusCode::RequestHeaderFieldsTooLarge => 431,
+      StatusCode::InternalServerError => 500,
+      StatusCode::NotImplemented => 501,
+      StatusCode::BadGateway => 502,
+      StatusCode::ServiceUnavailable => 503,
+      StatusCode::GatewayTimeout => 504,
+      StatusCode::Other(code) => *code,
+    }
+  }
+
+  pub fn reason(&self) -> &'static str {
+    match self {
+      StatusCode::SwitchingProtocols => "Switching Protocols",
+      StatusCode::Ok => "OK",
+      StatusCode::Created => "Created",
+      StatusCode::NoContent => "No Content",
+      StatusCode::PartialContent => "Partial Content",
+      StatusCode::MovedPermanently => "Moved Permanently",
+      StatusCode::Found => "Found",
+      StatusCode::NotModified => "Not Modified",
+      StatusCode::PermanentRedirect => "Permanent Redirect",
+      StatusCode::BadRequest => "Bad Request",
+      StatusCode::Unauthorized => "Unauthorized",
+      StatusCode::Forbidden => "Forbidden",
+      StatusCode::NotFound => "Not Found",
+      StatusCode::MethodNotAllowed => "Method Not Allowed",
+      StatusCode::RequestTimeout => "Request Timeout",
+      StatusCode::PayloadTooLarge => "Payload Too Large",
+      StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+      StatusCode::UpgradeRequired => "Upgrade Required",
+      StatusCode::TooManyRequests => "Too Many Requests",
+      StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+      StatusCode::InternalServerError => "Internal Server Error",
+      StatusCode::NotImplemented => "Not Implemented",
+      StatusCode::BadGateway => "Bad Gateway",
+      StatusCode::ServiceUnavailable => "Service Unavailable",
+      StatusCode::GatewayTimeout => "Gateway Timeout",
+      StatusCode::Other(_) => "",
+    }
+  }
+
+  /// 按数字查找状态码, 没有列出的返回`Other`
+  ///
+  /// ```
+  /// use web_server::response::StatusCode;
+  ///
+  /// assert_eq!(StatusCode::NotFound, StatusCode::from_code(404));
+  /// assert_eq!(StatusCode::Other(418), StatusCode::from_code(418));
+  /// ```
+  pub fn from_code(code: u16) -> StatusCode {
+    KNOWN
+      .iter()
+      .copied()
+      .find(|status| status.code() == code)
+      .unwrap_or(StatusCode::Other(code))
+  }
+
+  // 1xx、204和304响应不能带有响应体
+  pub(crate) fn allows_body(&self) -> bool {
+    self.code() >= 200 && !matches!(self, StatusCode::NoContent | StatusCode::NotModified)
+  }
+}
+
+const KNOWN: [StatusCode; 25] = [
+  StatusCode::SwitchingProtocols,
+  StatusCode::Ok,
+  StatusCode::Created,
+  StatusCode::NoContent,
+  StatusCode::PartialContent,
+  StatusCode::MovedPermanently,
+  StatusCode::Found,
+  StatusCode::NotModified,
+  StatusCode::PermanentRedirect,
+  StatusCode::BadRequest,
+  StatusCode::Unauthorized,
+  StatusCode::Forbidden,
+  StatusCode::NotFound,
+  StatusCode::MethodNotAllowed,
+  StatusCode::RequestTimeout,
+  StatusCode::PayloadTooLarge,
+  StatusCode::RangeNotSatisfiable,
+  StatusCode::UpgradeRequired,
+  StatusCode::TooManyRequests,
+  StatusCode::RequestHeaderFieldsTooLarge,
+  StatusCode::InternalServerError,
+  StatusCode::NotImplemented,
+  StatusCode::BadGateway,
+  StatusCode::ServiceUnavailable,
+  StatusCode::GatewayTimeout,
+];
+
+/// HTTP响应
+///
+/// # Examples
+///
+/// ```
+/// use web_server::response::Response;
+/// use web_server::response::StatusCode;
+///
+/// let response = Response::new(StatusCode::Created)
+///   .with_header("Location", "/users/1")
+///   .with_body("created");
+/// assert_eq!(Some("/users/1"), response.headers.get("location"));
+/// ```
+#[derive(Debug)]
+pub struct Response {
+  pub status: StatusCode,
+  pub headers: HeaderMap,
+  pub body: Body,
+  /// 响应首部发送之后接管连接的回调
+  pub upgrade: Option<Upgrade>,
+}
+
+/// 协议升级之后接管连接, 见[`Response::with_upgrade`]
+pub struct Upgrade(Box<dyn FnOnce(Box<dyn Transport + Send>) + Send>);
+
+impl Upgrade {
+  pub(crate) fn call(self, stream: Box<dyn Transport + Send>) {
+    (self.0)(stream)
+  }
+}
+
+impl fmt::Debug for Upgrade {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("Upgrade")
+  }
+}
+
+impl Response {
+  pub fn new(status: StatusCode) -> Response {
+    Response {
+      status,
+      headers: HeaderMap::new(),
+      body: Body::empty(),
+      upgrade: None,
+    }
+  }
+
+  pub fn ok() -> Response {
+    Response::new(StatusCode::Ok)
+  }
+
+  pub fn not_found() -> Response {
+    Response::new(StatusCode::NotFound)
+  }
+
+  /// `text/plain`响应
+  pub fn text(status: StatusCode, body: impl Into<String>) -> Response {
+    Response::new(status)
+      .with_header("Content-Type", "text/plain; charset=utf-8")
+      .with_body(body.into())
+  }
+
+  /// `text/html`响应
+  pub fn html(status: StatusCode, body: impl Into<String>) -> Response {
+    Response::new(status)
+      .with_header("Content-Type", "text/html; charset=utf-8")
+      .with_body(body.into())
+  }
+
+  /// 重定向到`location`
+  pub fn redirect(status: StatusCode, location: &str) -> Response {
+    Response::new(status).with_header("Location", location)
+  }
+
+  /// 设置首部, 值中的CR、LF等控制字符会被去掉
+  pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Response {
+    self
+      .headers
+      .insert(name, header::sanitize_value(value.into()));
+    self
+  }
+
+  pub fn with_body(mut self, body: impl Into<Body>) -> Response {
+    self.body = body.into();
+    self
+  }
+
+  /// 从`reader`流式读取响应体, `length`未知时使用chunked编码发送
+  pub fn with_reader<R: Read + Send + 'static>(
+    mut self,
+    reader: R,
+    length: Option<u64>,
+  ) -> Response {
+    self.body = Body::reader(reader, length);
+    self
+  }
+
+  /// 响应首部写出之后把连接交给`f`, 连接不再用于之后的请求
+  ///
+  /// 状态码为101时用于WebSocket等协议; 其他状态码时由`f`写出响应体, 例如长时间
+  /// 推送的事件流, 这时不会写出`body`, 连接会带上`Connection: close`。
+  /// HEAD请求不会交出连接。
+  ///
+  /// `f`在单独的线程中运行, 不占用处理普通请求的worker。连接上已经收到的
+  /// 后续字节不会丢失, 可以直接从传入的流中读到。传入的流保留请求的读写超时;
+  /// 同时交出的连接超过`max_upgraded`时不会调用`f`, 而是返回503。
+  pub fn with_upgrade<F>(mut self, f: F) -> Response
+  where
+    F: FnOnce(Box<dyn Transport + Send>) + Send + 'static,
+  {
+    self.upgrade = Some(Upgrade(Box::new(f)));
+    self
+  }
+
+  /// 序列化为HTTP/1.1响应写入`w`
+  ///
+  /// 没有设置`Content-Length`和`Transfer-Encoding`时按响应体自动补上:
+  /// 长度已知时使用`Content-Length`, 否则使用chunked编码。返回发送的响应体字节数。
+  pub fn write_to<W: Write>(&mut self, w: &mut W) -> io::Result<u64> {
+    self.write_head(w)?;
+    let mut written = 0;
+    if self.status.allows_body() {
+      let chunked = self.headers.has_token("Transfer-Encoding", "chunked");
+      written = self.body.write_to(w, chunked)?;
+    }
+    w.flush()?;
+    Ok(written)
+  }
+
+  /// 只写出状态行和首部, 用于HEAD请求, 首部与`write_to`完全相同
+  pub fn write_head<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+    if self.status.allows_body()
+      && !self.headers.contains("Content-Length")
+      && !self.headers.contains("Transfer-Encoding")
+    {
+      match self.body.len() {
+        Some(length) => self.headers.insert("Content-Length", length.to_string()),
+        None => self.headers.insert("Transfer-Encoding", "chunked"),
+      }
+    }
+
+    let mut head = format!(
+      "HTTP/1.1 {} {}\r\n",
+      self.status.code(),
+      self.status.reason()
+    );
+    // 直接修改`headers`时没有经过检查, 写出之前再检查一次
+    for (name, value) in self.headers.iter() {
+      if name.is_empty() || name.chars().any(|c| c.is_control() || c == ':' || c == ' ') {
+        continue;
+      }
+      let value = header::sanitize_value(value.to_string());
+      head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    w.write_all(head.as_bytes())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn serialize() {
+    let mut out = Vec::new();
+    Response::text(StatusCode::NotFound, "missing")
+      .write_to(&mut out)
+      .unwrap();
+    assert_eq!(
+      "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 7\r\n\r\nmissing",
+      String::from_utf8(out).unwrap()
+    );
+
+    let mut out = Vec::new();
+    Response::new(StatusCode::NoContent)
+      .with_body("ignored")
+      .write_to(&mut out)
+      .unwrap();
+    assert_eq!(
+      "HTTP/1.1 204 No Content\r\n\r\n",
+      String::from_utf8(out).unwrap()
+    );
+  }
+
+  #[test]
+  fn no_header_injection() {
+    let mut response =
+      Response::new(StatusCode::NoContent).with_header("Location", "https://a/\r\nSet-Cookie: x=1");
+    assert_eq!(
+      Some("https://a/Set-Cookie: x=1"),
+      response.headers.get("Location")
+    );
+    response.headers.insert("X-Direct", "a\nb");
+    response.headers.insert("Bad\r\nName", "c");
+    let mut out = Vec::new();
+    response.write_to(&mut out).unwrap();
+    assert_eq!(
+      "HTTP/1.1 204 No Content\r\nLocation: https://a/Set-Cookie: x=1\r\nX-Direct: ab\r\n\r\n",
+      String::from_utf8(out).unwrap()
+    );
+  }
+
+  #[test]
+  fn stream_chunked() {
+    let mut out = Vec::new();
+    Response::ok()
+      .with_reader(&b"hello world"[..], None)
+      .write_to(&mut out)
+      .unwrap();
+    assert_eq!(
+      "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nb\r\nhello world\r\n0\r\n\r\n",
+      String::from_utf8(out).unwrap()
+    );
+
+    // 长度已知的流只读取`length`个字节
+    let mut out = Vec::new();
+    Response::ok()
+      .with_reader(&b"hello world"[..], Some(5))
+      .write_to(&mut out)
+      .unwrap();
+    assert_eq!(
+      "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello",
+      String::from_utf8(out).unwrap()
+    );
+  }
+}
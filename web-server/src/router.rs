@@ -0,0 +1,251 @@
+//! 按方法和路径分发请求
+//!
+//! 路径模式按`/`分段, 每段可以是:
+//! - 字面量: `users`
+//! - 路径参数: `:id`, 匹配任意一段
+//! - 通配符: `*`或`*name`, 只能出现在最后, 匹配剩余的所有段(可以为空)
+//!
+//! 匹配到的参数保存在`Request::params`中, 通配符未命名时的键为`*`。
+//! 路由按注册顺序匹配, 先注册的优先。HEAD请求会交给匹配的GET路由处理。
+
+use std::collections::HashMap;
+
+use crate::handler::Handler;
+use crate::request::Request;
+use crate::response::Response;
+use crate::response::StatusCode;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+  Static(String),
+  Param(String),
+  Wildcard(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Pattern {
+  segments: Vec<Segment>,
+}
+
+impl Pattern {
+  /// # Panics
+  ///
+  /// 通配符不在最后一段时会触发panic.
+  fn parse(pattern: &str) -> Pattern {
+    let parts: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let segments = parts
+      .iter()
+      .enumerate()
+      .map(|(i, part)| {
+        if let Some(name) = part.strip_prefix(':') {
+          Segment::Param(name.to_string())
+        } else if let Some(name) = part.strip_prefix('*') {
+          assert!(i == parts.len() - 1, "wildcard must be the last segment");
+          let name = if name.is_empty() { "*" } else { name };
+          Segment::Wildcard(name.to_string())
+        } else {
+          Segment::Static(part.to_string())
+        }
+      })
+      .collect();
+    Pattern { segments }
+  }
+
+  fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+    let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let mut params = HashMap::new();
+
+    for (i, segment) in self.segments.iter().enumerate() {
+      match segment {
+        Segment::Wildcard(name) => {
+          params.insert(name.clone(), parts[i..].join("/"));
+          return Some(params);
+        }
+        Segment::Static(s) => {
+          if parts.get(i) != Some(&s.as_str()) {
+            return None;
+          }
+        }
+        Segment::Param(name) => {
+          params.insert(name.clone(), parts.get(i)?.to_string());
+        }
+      }
+    }
+
+    if parts.len() == self.segments.len() {
+      Some(params)
+    } else {
+      None
+    }
+  }
+}
+
+struct Route {
+  method: String,
+  pattern: Pattern,
+  handler: Box<dyn Handler>,
+}
+
+/// 路由表
+///
+/// `Router`本身也实现了`Handler`, 可以直接交给`server::handle_connection`。
+///
+/// # Examples
+///
+/// ```
+/// use web_server::request::Request;
+/// use web_server::response::Response;
+/// use web_server::response::StatusCode;
+/// use web_server::router::Router;
+///
+/// let mut router = Router::new();
+/// router.get("/users/:id", |request: &mut Request| {
+///   Response::text(StatusCode::Ok, format!("user {}", request.params["id"]))
+/// });
+/// ```
+#[derive(Default)]
+pub struct Router {
+  routes: Vec<Route>,
+  not_found: Option<Box<dyn Handler>>,
+}
+
+impl Router {
+  pub fn new() -> Router {
+    Router::default()
+  }
+
+  /// 注册一个处理指定方法和路径模式的handler
+  pub fn route<H>(&mut self, method: &str, pattern: &str, handler: H) -> &mut Router
+  where
+    H: Handler + 'static,
+  {
+    self.routes.push(Route {
+      method: method.to_string(),
+      pattern: Pattern::parse(pattern),
+      handler: Box::new(handler),
+    });
+    self
+  }
+
+  pub fn get<H: Handler + 'static>(&mut self, pattern: &str, handler: H) -> &mut Router {
+    self.route("GET", pattern, handler)
+  }
+
+  pub fn post<H: Handler + 'static>(&mut self, pattern: &str, handler: H) -> &mut Router {
+    self.route("POST", pattern, handler)
+  }
+
+  pub fn put<H: Handler + 'static>(&mut self, pattern: &str, handler: H) -> &mut Router {
+    self.route("PUT", pattern, handler)
+  }
+
+  pub fn delete<H: Handler + 'static>(&mut self, pattern: &str, handler: H) -> &mut Router {
+    self.route("DELETE", pattern, handler)
+  }
+
+  /// 没有任何路由匹配路径时调用的handler, 未设置时返回空的404
+  pub fn not_found<H: Handler + 'static>(&mut self, handler: H) -> &mut Router {
+    self.not_found = Some(Box::new(handler));
+    self
+  }
+}
+
+impl Handler for Router {
+  /// 查找匹配的路由并调用它的handler
+  ///
+  /// 路径匹配但方法不匹配时返回405, 并在`Allow`首部中列出允许的方法。
+  fn call(&self, request: &mut Request) -> Response {
+    let mut allowed: Vec<&str> = Vec::new();
+
+    for route in &self.routes {
+      let params = match route.pattern.matches(request.path()) {
+        Some(params) => params,
+        None => continue,
+      };
+      // 没有单独注册HEAD时由GET路由处理, 响应体在写出时丢弃
+      let head_as_get = request.method == "HEAD" && route.method == "GET";
+      if route.method != request.method && !head_as_get {
+        if !allowed.contains(&route.method.as_str()) {
+          allowed.push(&route.method);
+        }
+        continue;
+      }
+      request.params = params;
+      return route.handler.call(request);
+    }
+
+    if !allowed.is_empty() {
+      return Response::new(StatusCode::MethodNotAllowed).with_header("Allow", allowed.join(", "));
+    }
+    match &self.not_found {
+      Some(handler) => handler.call(request),
+      None => Response::not_found(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+      .iter()
+      .map(|(k, v)| (k.to_string(), v.to_string()))
+      .collect()
+  }
+
+  #[test]
+  fn match_params() {
+    let pattern = Pattern::parse("/users/:id/posts/:post");
+    assert_eq!(
+      Some(params(&[("id", "7"), ("post", "hello")])),
+      pattern.matches("/users/7/posts/hello")
+    );
+    assert_eq!(None, pattern.matches("/users/7/posts"));
+    assert_eq!(None, pattern.matches("/users/7/posts/hello/extra"));
+    assert_eq!(Some(params(&[])), Pattern::parse("/").matches("/"));
+  }
+
+  #[test]
+  fn match_wildcards() {
+    assert_eq!(
+      Some(params(&[("*", "css/site.css")])),
+      Pattern::parse("/static/*").matches("/static/css/site.css")
+    );
+    assert_eq!(
+      Some(params(&[("path", "")])),
+      Pattern::parse("/files/*path").matches("/files")
+    );
+    assert_eq!(None, Pattern::parse("/files/*path").matches("/other"));
+  }
+
+  #[test]
+  fn dispatch_by_method() {
+    let mut router = Router::new();
+    router
+      .get("/users/:id", |request: &mut Request| {
+        Response::text(StatusCode::Ok, request.params["id"].clone())
+      })
+      .delete("/users/:id", |_: &mut Request| {
+        Response::new(StatusCode::NoContent)
+      });
+    let request = |method: &str, target: &str| router.call(&mut Request::new(method, target));
+
+    assert_eq!(
+      Some(&b"42"[..]),
+      request("GET", "/users/42?x=1").body.as_bytes()
+    );
+    assert_eq!(StatusCode::NoContent, request("DELETE", "/users/42").status);
+    let response = request("POST", "/users/42");
+    assert_eq!(StatusCode::MethodNotAllowed, response.status);
+    assert_eq!(Some("GET, DELETE"), response.headers.get("Allow"));
+    assert_eq!(StatusCode::NotFound, request("GET", "/posts").status);
+  }
+
+  #[test]
+  #[should_panic(expected = "wildcard must be the last segment")]
+  fn wildcard_in_the_middle() {
+    Pattern::parse("/a/*/b");
+  }
+}
@@ -0,0 +1,1580 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use crate::access_log::AccessLog;
+use crate::access_log::Entry;
+use crate::access_log::LogFormat;
+use crate::access_log::LogTarget;
+#[cfg(target_os = "linux")]
+use crate::event_loop::EventLoops;
+use crate::handler::Handler;
+use crate::parser::Limits;
+use crate::parser::ParseError;
+use crate::parser::RequestReader;
+use crate::request::Request;
+use crate::request::Version;
+use crate::response::Response;
+use crate::response::StatusCode;
+use crate::response::Upgrade;
+#[cfg(feature = "tls")]
+use crate::tls;
+#[cfg(feature = "tls")]
+use crate::tls::TlsStream;
+use crate::ThreadPool;
+
+/// 可以承载HTTP连接的流
+///
+/// 除了读写之外还需要能设置读写超时, 用于实现keep-alive的空闲超时和慢客户端保护。
+pub trait Transport: Read + Write {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+  fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+  /// 客户端地址, 用于访问日志
+  fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+  /// 是否是TLS连接, 记录在`Request::secure`中
+  fn is_secure(&self) -> bool {
+    false
+  }
+
+  /// 复制出同一个连接的写端, 让另一个线程在这边阻塞读取时也能写入; 默认不支持
+  fn try_clone_writer(&self) -> io::Result<Box<dyn Write + Send>> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "this transport cannot be split",
+    ))
+  }
+}
+
+impl Transport for TcpStream {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    TcpStream::set_read_timeout(self, timeout)
+  }
+
+  fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    TcpStream::set_write_timeout(self, timeout)
+  }
+
+  fn peer_addr(&self) -> io::Result<SocketAddr> {
+    TcpStream::peer_addr(self)
+  }
+
+  fn try_clone_writer(&self) -> io::Result<Box<dyn Write + Send>> {
+    Ok(Box::new(self.try_clone()?))
+  }
+}
+
+/// 连接级别的配置
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+  /// 是否允许在一个连接上处理多个请求
+  pub keep_alive: bool,
+  /// 等待下一个请求的最长时间, 超时后关闭连接
+  pub idle_timeout: Duration,
+  /// 收到请求的第一个字节之后, 必须在这段时间内收完请求行和首部, 否则返回408
+  pub header_timeout: Duration,
+  /// 收完首部之后, 必须在这段时间内收完请求体, 否则返回408
+  pub body_timeout: Duration,
+  /// 请求开始之后每次读取的超时
+  pub read_timeout: Duration,
+  /// 必须在这段时间内写完一个响应, 否则关闭连接; 交出的连接写完首部之后不再受限制
+  pub response_timeout: Duration,
+  /// 每次写入的超时
+  pub write_timeout: Duration,
+  /// 请求行和首部的最大字节数
+  pub max_header_size: usize,
+  /// 请求体的最大字节数
+  pub max_body_size: usize,
+  /// 同时交出的连接(WebSocket、事件流等)的最大数量, 达到时返回503;
+  /// 只在通过[`Server`]运行时检查
+  pub max_upgraded: usize,
+  /// 每个请求处理完之后写一条访问日志, None表示不记录
+  pub access_log: Option<Arc<AccessLog>>,
+}
+
+impl Default for ConnectionConfig {
+  fn default() -> ConnectionConfig {
+    ConnectionConfig {
+      keep_alive: true,
+      idle_timeout: Duration::from_secs(5),
+      header_timeout: Duration::from_secs(10),
+      body_timeout: Duration::from_secs(60),
+      read_timeout: Duration::from_secs(30),
+      response_timeout: Duration::from_secs(300),
+      write_timeout: Duration::from_secs(30),
+      max_header_size: 8 * 1024,
+      max_body_size: 10 * 1024 * 1024,
+      max_upgraded: 1024,
+      access_log: None,
+    }
+  }
+}
+
+impl ConnectionConfig {
+  fn limits(&self) -> Limits {
+    Limits {
+      max_header_size: self.max_header_size,
+      max_body_size: self.max_body_size,
+      header_timeout: Some(self.header_timeout),
+      body_timeout: Some(self.body_timeout),
+    }
+  }
+}
+
+// 等待请求的第一个字节时使用空闲超时, 请求开始之后换成读超时,
+// 读超时不超过首部或请求体剩余的时间; 写响应时写超时不超过响应剩余的时间
+struct Timed<S> {
+  stream: S,
+  idle_timeout: Duration,
+  read_timeout: Duration,
+  write_timeout: Duration,
+  response_timeout: Duration,
+  waiting: bool,
+  // 当前设置在流上的读超时, 相同时不再重复设置; 为0表示还没有设置过
+  timeout: Duration,
+  // 当前设置在流上的写超时
+  current_write_timeout: Duration,
+  // 正在写的响应的截止时间
+  deadline: Option<Instant>,
+}
+
+impl<S: Transport> Timed<S> {
+  fn new(stream: S, config: &ConnectionConfig) -> Timed<S> {
+    Timed {
+      stream,
+      idle_timeout: config.idle_timeout,
+      read_timeout: config.read_timeout,
+      write_timeout: config.write_timeout,
+      response_timeout: config.response_timeout,
+      waiting: false,
+      timeout: Duration::ZERO,
+      // `serve`在创建之前已经设置好了
+      current_write_timeout: config.write_timeout,
+      deadline: None,
+    }
+  }
+
+  fn wait_for_request(&mut self) -> io::Result<()> {
+    self.waiting = true;
+    self.set_timeout(self.idle_timeout)
+  }
+
+  // 作为`RequestReader::before_read`的回调
+  fn limit_to_remaining(&mut self, remaining: Option<Duration>) -> io::Result<()> {
+    if self.waiting {
+      return Ok(());
+    }
+    let timeout = match remaining {
+      // 超时不能为0, 剩余时间用完时让下一次读取尽快超时
+      Some(remaining) => self
+        .read_timeout
+        .min(remaining.max(Duration::from_millis(1))),
+      None => self.read_timeout,
+    };
+    self.set_timeout(timeout)
+  }
+
+  fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+    if self.timeout != timeout {
+      self.stream.set_read_timeout(Some(timeout))?;
+      self.timeout = timeout;
+    }
+    Ok(())
+  }
+
+  fn start_response(&mut self) {
+    self.deadline = Some(Instant::now() + self.response_timeout);
+  }
+
+  fn end_response(&mut self) -> io::Result<()> {
+    self.deadline = None;
+    self.set_write_timeout(self.write_timeout)
+  }
+
+  fn set_write_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+    if self.current_write_timeout != timeout {
+      self.stream.set_write_timeout(Some(timeout))?;
+      self.current_write_timeout = timeout;
+    }
+    Ok(())
+  }
+}
+
+impl<S: Transport> Read for Timed<S> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = self.stream.read(buf)?;
+    if n > 0 && self.waiting {
+      self.waiting = false;
+      self.set_timeout(self.read_timeout)?;
+    }
+    Ok(n)
+  }
+}
+
+impl<S: Transport> Write for Timed<S> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if let Some(deadline) = self.deadline {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      if remaining.is_zero() {
+        return Err(io::Error::new(
+          io::ErrorKind::TimedOut,
+          "response timed out",
+        ));
+      }
+      self.set_write_timeout(self.write_timeout.min(remaining))?;
+    }
+    self.stream.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.stream.flush()
+  }
+}
+
+/// 在一个连接上循环读取请求、调用handler并写回响应
+///
+/// 支持持久连接: HTTP/1.1默认保持连接, HTTP/1.0需要`Connection: keep-alive`,
+/// 任意一方发送`Connection: close`后关闭。流水线请求会按顺序依次处理。
+/// 请求格式错误时返回400并关闭连接, 不会panic。
+///
+/// 为了不让慢客户端长期占用worker, 读写都有超时: 等待请求时使用`idle_timeout`,
+/// 请求开始后首部必须在`header_timeout`内收完, 请求体必须在`body_timeout`内收完,
+/// 响应必须在`response_timeout`内写完, 每次读写不超过`read_timeout`/`write_timeout`。
+/// 请求超时返回408, 首部过大返回431, 请求体过大返回413, 之后都会关闭连接。
+pub fn handle_connection<S: Transport + Send + 'static>(
+  stream: S,
+  handler: &dyn Handler,
+  config: &ConnectionConfig,
+) {
+  serve(stream, handler, config, None, false);
+}
+
+// `guard`用于优雅关闭: 等待下一个请求时标记为空闲, 关闭时空闲连接会被立即断开,
+// 正在处理的请求写完响应后再关闭。
+//
+// `park`为true时不在这里等待下一个请求, 而是把空闲的连接连同`guard`一起返回给调用者,
+// 由事件循环等待数据到来
+pub(crate) fn serve<S: Transport + Send + 'static>(
+  stream: S,
+  handler: &dyn Handler,
+  config: &ConnectionConfig,
+  mut guard: Option<ConnectionGuard>,
+  park: bool,
+) -> Option<(S, ConnectionGuard)> {
+  if let Err(e) = stream.set_write_timeout(Some(config.write_timeout)) {
+    eprintln!("Failed to set write timeout: {}", e);
+    return None;
+  }
+  let peer_addr = stream.peer_addr().ok();
+  let secure = stream.is_secure();
+  let mut reader = RequestReader::with_limits(Timed::new(stream, config), config.limits())
+    .before_read(Timed::limit_to_remaining);
+  let mut first = true;
+
+  loop {
+    // 第一个请求可能在关闭之前就已经发出, 总是要处理
+    if let Some(guard) = &guard {
+      if !first && !guard.set_idle(true) {
+        return None;
+      }
+    }
+    // 缓冲区中还有流水线请求的一部分时, 下一个请求已经开始了
+    if reader.buffered() == 0 {
+      if park && !first {
+        if let Some(guard) = guard.take() {
+          return Some((reader.into_parts().0.stream, guard));
+        }
+      }
+      if let Err(e) = reader.get_mut().wait_for_request() {
+        eprintln!("Failed to set read timeout: {}", e);
+        return None;
+      }
+    }
+    first = false;
+    let request = reader.read_request();
+    if let Some(guard) = &guard {
+      guard.set_idle(false);
+    }
+    let started = (SystemTime::now(), Instant::now());
+    let mut request = match request {
+      Ok(Some(request)) => request,
+      // 客户端关闭了连接或者空闲超时
+      Ok(None) => return None,
+      Err(ParseError::Io(ref e)) if is_timeout(e) => return None,
+      Err(ParseError::Malformed(reason)) => {
+        eprintln!("Bad request: {}", reason);
+        reject(
+          reader.get_mut(),
+          StatusCode::BadRequest,
+          reason,
+          config,
+          peer_addr,
+        );
+        return None;
+      }
+      Err(ParseError::HeaderTooLarge) => {
+        let status = StatusCode::RequestHeaderFieldsTooLarge;
+        reject(reader.get_mut(), status, status.reason(), config, peer_addr);
+        return None;
+      }
+      Err(ParseError::BodyTooLarge) => {
+        let status = StatusCode::PayloadTooLarge;
+        reject(reader.get_mut(), status, status.reason(), config, peer_addr);
+        return None;
+      }
+      Err(ParseError::Timeout) => {
+        let status = StatusCode::RequestTimeout;
+        reject(reader.get_mut(), status, status.reason(), config, peer_addr);
+        return None;
+      }
+      Err(e) => {
+        eprintln!("Failed to read request: {}", e);
+        return None;
+      }
+    };
+
+    request.peer_addr = peer_addr;
+    request.secure = secure;
+    let mut response = handler.call(&mut request);
+    if request.version == Version::Http10
+      && response.body.len().is_none()
+      && !response.headers.contains("Content-Length")
+    {
+      // HTTP/1.0不支持chunked编码, 只能把长度未知的响应体读入内存
+      let body = std::mem::take(&mut response.body);
+      match body.into_bytes() {
+        Ok(bytes) => response.body = bytes.into(),
+        Err(e) => {
+          eprintln!("Failed to read response body: {}", e);
+          return None;
+        }
+      }
+    }
+    let mut upgrade = match request.method.as_str() {
+      "HEAD" => None,
+      _ => response.upgrade.take(),
+    };
+    // 每个交出的连接占用一个线程, 数量需要有上限
+    let mut slot = None;
+    if let (Some(_), Some(guard)) = (&upgrade, &guard) {
+      slot = guard.reserve_upgrade(config.max_upgraded);
+      if slot.is_none() {
+        upgrade = None;
+        let status = StatusCode::ServiceUnavailable;
+        response = Response::text(status, "too many upgraded connections");
+      }
+    }
+    let switching = response.status == StatusCode::SwitchingProtocols;
+    let keep_alive = config.keep_alive
+      && upgrade.is_none()
+      && !guard.as_ref().is_some_and(ConnectionGuard::shutting_down)
+      && wants_keep_alive(&request)
+      && !response.headers.has_token("Connection", "close");
+    // 101响应的Connection首部由handler设置
+    if !switching {
+      if !keep_alive {
+        response.headers.insert("Connection", "close");
+      } else if request.version == Version::Http10 {
+        response.headers.insert("Connection", "keep-alive");
+      }
+    }
+
+    let stream = reader.get_mut();
+    stream.start_response();
+    // HEAD响应的首部与GET相同, 但不发送响应体; 交出连接时响应体由接管者写出
+    let written = if request.method == "HEAD" || upgrade.is_some() {
+      response
+        .write_head(stream)
+        .and_then(|_| stream.flush())
+        .map(|_| 0)
+    } else {
+      response.write_to(stream)
+    };
+    let written = written.and_then(|n| stream.end_response().map(|_| n));
+    if let Some(access_log) = &config.access_log {
+      access_log.log(&Entry {
+        peer_addr,
+        time: started.0,
+        method: &request.method,
+        target: &request.target,
+        version: &request.version.to_string(),
+        status: response.status.code(),
+        bytes: *written.as_ref().unwrap_or(&0),
+        duration: started.1.elapsed(),
+        referer: request.header("Referer"),
+        user_agent: request.header("User-Agent"),
+      });
+    }
+    if let Err(e) = written {
+      eprintln!("Failed to write response: {}", e);
+      return None;
+    }
+    if let Some(upgrade) = upgrade {
+      hand_off(reader, upgrade, guard, slot);
+      return None;
+    }
+    if !keep_alive {
+      return None;
+    }
+  }
+}
+
+// 交出的连接(WebSocket、事件流等)可能持续很久, 放到单独的线程中运行, 不占用线程池的worker。
+// 连接标记为空闲, 关闭服务器时会断开读方向, 让接管连接的回调读到EOF后结束
+fn hand_off<S: Transport + Send + 'static>(
+  reader: RequestReader<Timed<S>>,
+  upgrade: Upgrade,
+  guard: Option<ConnectionGuard>,
+  slot: Option<UpgradeSlot>,
+) {
+  if let Some(guard) = &guard {
+    if !guard.set_idle(true) {
+      return;
+    }
+  }
+  let (timed, buffered) = reader.into_parts();
+  let stream = Prefixed {
+    stream: timed.stream,
+    buffered,
+    pos: 0,
+  };
+  let spawned = thread::Builder::new()
+    .name("upgraded".to_string())
+    .spawn(move || {
+      let _guard = guard;
+      let _slot = slot;
+      upgrade.call(Box::new(stream));
+    });
+  if let Err(e) = spawned {
+    eprintln!("Failed to spawn thread for upgraded connection: {}", e);
+  }
+}
+
+// 先读出`buffered`中已经读到的字节, 再从流中读取。
+// 用于交出连接时带上解析请求时多读的字节, 以及事件循环把收到的首部交给worker
+pub(crate) struct Prefixed<S> {
+  pub(crate) stream: S,
+  pub(crate) buffered: Vec<u8>,
+  pub(crate) pos: usize,
+}
+
+impl<S> Prefixed<S> {
+  pub(crate) fn new(stream: S) -> Prefixed<S> {
+    Prefixed {
+      stream,
+      buffered: Vec::new(),
+      pos: 0,
+    }
+  }
+
+  /// 还没有被读出的字节
+  pub(crate) fn unread(&self) -> &[u8] {
+    &self.buffered[self.pos..]
+  }
+}
+
+impl<S: Transport> Read for Prefixed<S> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.pos < self.buffered.len() {
+      let n = (&self.buffered[self.pos..]).read(buf)?;
+      self.pos += n;
+      return Ok(n);
+    }
+    self.stream.read(buf)
+  }
+}
+
+impl<S: Transport> Write for Prefixed<S> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.stream.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.stream.flush()
+  }
+}
+
+impl<S: Transport> Transport for Prefixed<S> {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.stream.set_read_timeout(timeout)
+  }
+
+  fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.stream.set_write_timeout(timeout)
+  }
+
+  fn peer_addr(&self) -> io::Result<SocketAddr> {
+    self.stream.peer_addr()
+  }
+
+  fn is_secure(&self) -> bool {
+    self.stream.is_secure()
+  }
+
+  // 已经读到的字节只属于读端
+  fn try_clone_writer(&self) -> io::Result<Box<dyn Write + Send>> {
+    self.stream.try_clone_writer()
+  }
+}
+
+/// 多线程HTTP服务器, 支持优雅关闭
+///
+/// `run`会一直接受连接, 直到通过[`ServerHandle::shutdown`]请求关闭。关闭时先停止接受
+/// 新连接并断开空闲的keep-alive连接, 然后等待正在处理的请求完成; 超过`shutdown_timeout`
+/// 仍未完成的连接会被强制断开, 最后终止线程池中的所有worker。
+///
+/// 强制断开只能让连接上的读写失败, 仍在执行的handler需要自己返回之后worker才能退出。
+///
+/// # Examples
+///
+/// ```no_run
+/// use web_server::request::Request;
+/// use web_server::response::Response;
+/// use web_server::response::StatusCode;
+/// use web_server::server::Server;
+///
+/// let server = Server::bind("127.0.0.1:7878", |_: &mut Request| {
+///   Response::text(StatusCode::Ok, "hello")
+/// })
+/// .unwrap();
+/// let handle = server.handle();
+/// std::thread::spawn(move || handle.shutdown());
+/// server.run().unwrap();
+/// ```
+pub struct Server {
+  listeners: Vec<Listener>,
+  workers: usize,
+  backend: Backend,
+  event_loops: usize,
+  config: Arc<ConnectionConfig>,
+  shutdown_timeout: Duration,
+  state: Arc<State>,
+}
+
+impl Server {
+  pub fn builder() -> ServerBuilder {
+    ServerBuilder::default()
+  }
+
+  /// 使用默认配置监听`addr`
+  pub fn bind<H: Handler + 'static>(addr: &str, handler: H) -> io::Result<Server> {
+    Server::builder().address(addr).build(handler)
+  }
+
+  pub fn local_addr(&self) -> SocketAddr {
+    self.state.local_addr
+  }
+
+  /// HTTPS监听的地址, 没有配置HTTPS时为None
+  pub fn tls_local_addr(&self) -> Option<SocketAddr> {
+    self.state.tls_addr
+  }
+
+  /// 用于在其他线程中关闭服务器的句柄
+  pub fn handle(&self) -> ServerHandle {
+    ServerHandle {
+      state: Arc::clone(&self.state),
+    }
+  }
+
+  /// 接受并处理连接, 直到服务器被关闭
+  pub fn run(self) -> io::Result<()> {
+    let pool = ThreadPool::new(self.workers);
+    #[cfg(target_os = "linux")]
+    let mut event_loops = match self.backend {
+      Backend::Epoll => Some(EventLoops::new(
+        self.event_loops,
+        &self.listeners[0].listener,
+      )?),
+      Backend::Threads => None,
+    };
+
+    // 每个监听socket在单独的线程中accept, 连接都交给同一个线程池处理
+    thread::scope(|scope| {
+      for listener in &self.listeners {
+        let pool = &pool;
+        let server = &self;
+        // 第一个是HTTP监听socket, 只有它由事件循环处理, HTTPS连接仍然每个占用一个worker
+        #[cfg(target_os = "linux")]
+        if let Some(event_loops) = event_loops.take() {
+          scope.spawn(move || {
+            event_loops.run(
+              &listener.listener,
+              &listener.handler,
+              &server.config,
+              &server.state,
+              pool,
+            )
+          });
+          continue;
+        }
+        scope.spawn(move || server.accept(listener, pool));
+      }
+    });
+
+    drop(self.listeners);
+    self.state.drain(self.shutdown_timeout);
+    // ThreadPool的drop会向每个worker发送Terminate并等待它们退出
+    drop(pool);
+    Ok(())
+  }
+
+  fn accept(&self, listener: &Listener, pool: &ThreadPool) {
+    for stream in listener.listener.incoming() {
+      if self.state.shutting_down() {
+        break;
+      }
+      let stream = match stream {
+        Ok(stream) => stream,
+        Err(e) => {
+          eprintln!("Failed to accept connection: {}", e);
+          continue;
+        }
+      };
+      let guard = match self.state.register(&stream) {
+        Ok(guard) => guard,
+        Err(e) => {
+          eprintln!("Failed to register connection: {}", e);
+          continue;
+        }
+      };
+      let handler = Arc::clone(&listener.handler);
+      let config = Arc::clone(&self.config);
+      #[cfg(feature = "tls")]
+      if let Some(tls) = &listener.tls {
+        // 握手放在worker中进行, 不阻塞accept
+        let tls = Arc::clone(tls);
+        pool.execute(
+          move || match TlsStream::accept(tls, stream, config.header_timeout) {
+            Ok(stream) => {
+              serve(stream, &*handler, &config, Some(guard), false);
+            }
+            Err(e) => eprintln!("TLS handshake failed: {}", e),
+          },
+        );
+        continue;
+      }
+      pool.execute(move || {
+        serve(stream, &*handler, &config, Some(guard), false);
+      });
+    }
+  }
+}
+
+// 一个监听socket, 以及它接受的连接使用的handler
+struct Listener {
+  listener: TcpListener,
+  handler: Arc<dyn Handler>,
+  #[cfg(feature = "tls")]
+  tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+/// 服务器处理连接的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+  /// 每个连接在整个生命周期内占用线程池中的一个worker, 包括等待下一个请求的时间,
+  /// 所以并发连接数不超过worker数量
+  Threads,
+  /// 只支持Linux: 少数几个事件循环线程用epoll等待所有空闲连接, 收齐请求首部之后
+  /// 才交给线程池处理, 处理完再交还给事件循环, 适合大量空闲的keep-alive连接。
+  ///
+  /// 请求体和响应仍然在worker中以阻塞方式读写; HTTPS连接总是使用`Threads`的方式。
+  Epoll,
+}
+
+impl FromStr for Backend {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> Result<Backend, &'static str> {
+    match s.to_ascii_lowercase().as_str() {
+      "threads" => Ok(Backend::Threads),
+      "epoll" => Ok(Backend::Epoll),
+      _ => Err("unknown backend"),
+    }
+  }
+}
+
+/// [`Server`]的配置
+///
+/// 除了在代码中设置, 也可以通过[`ServerBuilder::load_file`]和[`ServerBuilder::apply_args`]
+/// 从配置文件或命令行参数中读取, 见`config`模块。
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use web_server::request::Request;
+/// use web_server::response::Response;
+/// use web_server::response::StatusCode;
+/// use web_server::server::Server;
+///
+/// let server = Server::builder()
+///   .address("0.0.0.0:8080")
+///   .workers(8)
+///   .max_body_size(1024 * 1024)
+///   .read_timeout(Duration::from_secs(10))
+///   .build(|_: &mut Request| Response::text(StatusCode::Ok, "hello"))
+///   .unwrap();
+/// server.run().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerBuilder {
+  pub(crate) address: String,
+  pub(crate) workers: usize,
+  pub(crate) backend: Backend,
+  pub(crate) event_loops: usize,
+  pub(crate) backlog: Option<u32>,
+  pub(crate) shutdown_timeout: Duration,
+  pub(crate) access_log: Option<LogTarget>,
+  pub(crate) access_log_format: LogFormat,
+  pub(crate) connection: ConnectionConfig,
+  pub(crate) tls_address: Option<String>,
+  pub(crate) tls_cert: Option<PathBuf>,
+  pub(crate) tls_key: Option<PathBuf>,
+  pub(crate) redirect_https: bool,
+}
+
+impl Default for ServerBuilder {
+  /// 监听`127.0.0.1:7878`, 4个worker, 关闭时最多等待30秒
+  fn default() -> ServerBuilder {
+    ServerBuilder {
+      address: "127.0.0.1:7878".to_string(),
+      workers: 4,
+      backend: Backend::Threads,
+      event_loops: 1,
+      backlog: None,
+      shutdown_timeout: Duration::from_secs(30),
+      access_log: None,
+      access_log_format: LogFormat::Combined,
+      connection: ConnectionConfig::default(),
+      tls_address: None,
+      tls_cert: None,
+      tls_key: None,
+      redirect_https: false,
+    }
+  }
+}
+
+impl ServerBuilder {
+  /// 监听地址, 例如`0.0.0.0:8080`
+  pub fn address(mut self, address: &str) -> ServerBuilder {
+    self.address = address.to_string();
+    self
+  }
+
+  /// 线程池中worker的数量
+  pub fn workers(mut self, workers: usize) -> ServerBuilder {
+    self.workers = workers;
+    self
+  }
+
+  /// 处理连接的方式, 默认为[`Backend::Threads`]
+  pub fn backend(mut self, backend: Backend) -> ServerBuilder {
+    self.backend = backend;
+    self
+  }
+
+  /// 使用[`Backend::Epoll`]时事件循环线程的数量, 默认为1
+  pub fn event_loops(mut self, event_loops: usize) -> ServerBuilder {
+    self.event_loops = event_loops;
+    self
+  }
+
+  /// 等待accept的连接队列长度, 默认使用标准库的128
+  pub fn backlog(mut self, backlog: u32) -> ServerBuilder {
+    self.backlog = Some(backlog);
+    self
+  }
+
+  /// 关闭时等待进行中的请求完成的最长时间
+  pub fn shutdown_timeout(mut self, timeout: Duration) -> ServerBuilder {
+    self.shutdown_timeout = timeout;
+    self
+  }
+
+  /// 访问日志写到哪里, 默认不记录
+  pub fn access_log(mut self, target: Option<LogTarget>) -> ServerBuilder {
+    self.access_log = target;
+    self
+  }
+
+  /// 访问日志的格式, 默认为Combined Log Format
+  pub fn access_log_format(mut self, format: LogFormat) -> ServerBuilder {
+    self.access_log_format = format;
+    self
+  }
+
+  pub fn keep_alive(mut self, enabled: bool) -> ServerBuilder {
+    self.connection.keep_alive = enabled;
+    self
+  }
+
+  pub fn idle_timeout(mut self, timeout: Duration) -> ServerBuilder {
+    self.connection.idle_timeout = timeout;
+    self
+  }
+
+  pub fn header_timeout(mut self, timeout: Duration) -> ServerBuilder {
+    self.connection.header_timeout = timeout;
+    self
+  }
+
+  pub fn body_timeout(mut self, timeout: Duration) -> ServerBuilder {
+    self.connection.body_timeout = timeout;
+    self
+  }
+
+  pub fn read_timeout(mut self, timeout: Duration) -> ServerBuilder {
+    self.connection.read_timeout = timeout;
+    self
+  }
+
+  pub fn response_timeout(mut self, timeout: Duration) -> ServerBuilder {
+    self.connection.response_timeout = timeout;
+    self
+  }
+
+  pub fn write_timeout(mut self, timeout: Duration) -> ServerBuilder {
+    self.connection.write_timeout = timeout;
+    self
+  }
+
+  pub fn max_header_size(mut self, size: usize) -> ServerBuilder {
+    self.connection.max_header_size = size;
+    self
+  }
+
+  pub fn max_body_size(mut self, size: usize) -> ServerBuilder {
+    self.connection.max_body_size = size;
+    self
+  }
+
+  /// 同时交出的连接(WebSocket、事件流等)的最大数量, 默认1024
+  pub fn max_upgraded(mut self, max: usize) -> ServerBuilder {
+    self.connection.max_upgraded = max;
+    self
+  }
+
+  /// 同时在`address`上监听HTTPS, 需要启用`tls` feature并设置证书和私钥
+  pub fn tls_address(mut self, address: &str) -> ServerBuilder {
+    self.tls_address = Some(address.to_string());
+    self
+  }
+
+  /// PEM格式的证书链文件
+  pub fn tls_cert<P: AsRef<Path>>(mut self, path: P) -> ServerBuilder {
+    self.tls_cert = Some(path.as_ref().to_path_buf());
+    self
+  }
+
+  /// PEM格式的私钥文件
+  pub fn tls_key<P: AsRef<Path>>(mut self, path: P) -> ServerBuilder {
+    self.tls_key = Some(path.as_ref().to_path_buf());
+    self
+  }
+
+  /// HTTP监听端口上的所有请求都重定向到HTTPS, 需要同时设置`tls_address`
+  pub fn redirect_https(mut self, enabled: bool) -> ServerBuilder {
+    self.redirect_https = enabled;
+    self
+  }
+
+  /// 绑定地址并创建服务器
+  ///
+  /// # Panics
+  ///
+  /// worker或事件循环的数量为0时panic.
+  pub fn build<H: Handler + 'static>(mut self, handler: H) -> io::Result<Server> {
+    if self.backend == Backend::Epoll && !cfg!(target_os = "linux") {
+      return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the epoll backend is only available on Linux",
+      ));
+    }
+    if let Some(target) = self.access_log.take() {
+      let access_log = AccessLog::new(self.access_log_format, target)?;
+      self.connection.access_log = Some(Arc::new(access_log));
+    }
+    let handler: Arc<dyn Handler> = Arc::new(handler);
+    let https = match &self.tls_address {
+      Some(address) => Some(self.bind_tls(address, Arc::clone(&handler))?),
+      None => None,
+    };
+    let tls_addr = match &https {
+      Some(https) => Some(https.listener.local_addr()?),
+      None => None,
+    };
+    let http_handler: Arc<dyn Handler> = match (self.redirect_https, tls_addr) {
+      (false, _) => handler,
+      (true, Some(addr)) => Arc::new(HttpsRedirect { port: addr.port() }),
+      (true, None) => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidInput,
+          "redirect_https requires tls_address",
+        ))
+      }
+    };
+    let http = Listener {
+      listener: bind(&self.address, self.backlog)?,
+      handler: http_handler,
+      #[cfg(feature = "tls")]
+      tls: None,
+    };
+    let state = Arc::new(State {
+      local_addr: http.listener.local_addr()?,
+      tls_addr,
+      access_log: self.connection.access_log.clone(),
+      shutting_down: AtomicBool::new(false),
+      next_id: AtomicUsize::new(0),
+      upgraded: AtomicUsize::new(0),
+      connections: Mutex::new(HashMap::new()),
+      drained: Condvar::new(),
+    });
+    assert!(self.workers > 0);
+    assert!(self.event_loops > 0);
+    Ok(Server {
+      listeners: std::iter::once(http).chain(https).collect(),
+      workers: self.workers,
+      backend: self.backend,
+      event_loops: self.event_loops,
+      config: Arc::new(self.connection),
+      shutdown_timeout: self.shutdown_timeout,
+      state,
+    })
+  }
+
+  #[cfg(feature = "tls")]
+  fn bind_tls(&self, address: &str, handler: Arc<dyn Handler>) -> io::Result<Listener> {
+    let (cert, key) = match (&self.tls_cert, &self.tls_key) {
+      (Some(cert), Some(key)) => (cert, key),
+      _ => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidInput,
+          "HTTPS requires tls_cert and tls_key",
+        ))
+      }
+    };
+    Ok(Listener {
+      tls: Some(tls::load_config(cert, key)?),
+      listener: bind(address, self.backlog)?,
+      handler,
+    })
+  }
+
+  #[cfg(not(feature = "tls"))]
+  fn bind_tls(&self, _: &str, _: Arc<dyn Handler>) -> io::Result<Listener> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "HTTPS requires building with the `tls` feature",
+    ))
+  }
+}
+
+fn bind(address: &str, backlog: Option<u32>) -> io::Result<TcpListener> {
+  let listener = TcpListener::bind(address)?;
+  if let Some(backlog) = backlog {
+    set_backlog(&listener, backlog)?;
+  }
+  Ok(listener)
+}
+
+// 把HTTP请求重定向到HTTPS端口上的同一个目标, 使用308保留请求方法和请求体
+struct HttpsRedirect {
+  port: u16,
+}
+
+impl Handler for HttpsRedirect {
+  fn call(&self, request: &mut Request) -> Response {
+    let host = match request.header("Host").map(strip_port) {
+      Some(host) if !host.is_empty() => host,
+      _ => return Response::text(StatusCode::BadRequest, "missing Host header"),
+    };
+    let location = match self.port {
+      443 => format!("https://{}{}", host, request.target),
+      port => format!("https://{}:{}{}", host, port, request.target),
+    };
+    Response::redirect(StatusCode::PermanentRedirect, &location)
+  }
+}
+
+// IPv6地址写在方括号里, 例如`[::1]:8080`
+fn strip_port(host: &str) -> &str {
+  if host.starts_with('[') {
+    return match host.find(']') {
+      Some(end) => &host[..=end],
+      None => host,
+    };
+  }
+  host.split(':').next().unwrap_or(host)
+}
+
+// 标准库固定使用128, 对已经在监听的socket再次调用listen只会修改队列长度
+#[cfg(unix)]
+fn set_backlog(listener: &TcpListener, backlog: u32) -> io::Result<()> {
+  use std::os::raw::c_int;
+  use std::os::unix::io::AsRawFd;
+
+  extern "C" {
+    fn listen(fd: c_int, backlog: c_int) -> c_int;
+  }
+
+  let backlog = backlog.min(c_int::MAX as u32) as c_int;
+  if unsafe { listen(listener.as_raw_fd(), backlog) } != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_backlog(_: &TcpListener, _: u32) -> io::Result<()> {
+  Ok(())
+}
+
+/// [`Server`]的关闭句柄, 可以克隆并在任意线程中使用
+#[derive(Clone)]
+pub struct ServerHandle {
+  state: Arc<State>,
+}
+
+impl ServerHandle {
+  /// 请求关闭服务器, 立即返回, 多次调用没有额外效果
+  pub fn shutdown(&self) {
+    if self.state.shutting_down.swap(true, Ordering::SeqCst) {
+      return;
+    }
+    // accept线程阻塞在accept上, 连接一次把它唤醒
+    wake(self.state.local_addr);
+    if let Some(addr) = self.state.tls_addr {
+      wake(addr);
+    }
+  }
+
+  /// 重新打开访问日志文件, 用于日志轮转; 没有配置访问日志时什么也不做
+  pub fn reopen_access_log(&self) -> io::Result<()> {
+    match &self.state.access_log {
+      Some(access_log) => access_log.reopen(),
+      None => Ok(()),
+    }
+  }
+}
+
+fn wake(addr: SocketAddr) {
+  let ip = match addr.ip() {
+    IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+    IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+    ip => ip,
+  };
+  let _ = TcpStream::connect_timeout(&SocketAddr::new(ip, addr.port()), Duration::from_secs(1));
+}
+
+pub(crate) struct State {
+  local_addr: SocketAddr,
+  tls_addr: Option<SocketAddr>,
+  access_log: Option<Arc<AccessLog>>,
+  shutting_down: AtomicBool,
+  next_id: AtomicUsize,
+  // 正在运行的交出的连接数
+  upgraded: AtomicUsize,
+  connections: Mutex<HashMap<usize, Tracked>>,
+  drained: Condvar,
+}
+
+// 记录每个连接的一份克隆, 关闭时用来断开连接
+struct Tracked {
+  stream: TcpStream,
+  idle: bool,
+}
+
+impl State {
+  pub(crate) fn shutting_down(&self) -> bool {
+    self.shutting_down.load(Ordering::SeqCst)
+  }
+
+  pub(crate) fn register(self: &Arc<State>, stream: &TcpStream) -> io::Result<ConnectionGuard> {
+    let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+    let tracked = Tracked {
+      stream: stream.try_clone()?,
+      idle: false,
+    };
+    self.connections.lock().unwrap().insert(id, tracked);
+    Ok(ConnectionGuard {
+      state: Arc::clone(self),
+      id,
+    })
+  }
+
+  // 断开空闲连接, 等待其余连接结束, 超时后强制断开
+  fn drain(&self, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    let mut connections = self.connections.lock().unwrap();
+    eprintln!(
+      "Shutting down, waiting for {} connection(s).",
+      connections.len()
+    );
+    for tracked in connections.values().filter(|t| t.idle) {
+      let _ = tracked.stream.shutdown(Shutdown::Read);
+    }
+    while !connections.is_empty() {
+      let now = Instant::now();
+      if now >= deadline {
+        eprintln!(
+          "Closing {} connection(s) after shutdown timeout.",
+          connections.len()
+        );
+        for tracked in connections.values() {
+          let _ = tracked.stream.shutdown(Shutdown::Both);
+        }
+        return;
+      }
+      connections = self
+        .drained
+        .wait_timeout(connections, deadline - now)
+        .unwrap()
+        .0;
+    }
+  }
+}
+
+// 连接处理结束时从`State`中移除
+pub(crate) struct ConnectionGuard {
+  state: Arc<State>,
+  id: usize,
+}
+
+impl ConnectionGuard {
+  fn shutting_down(&self) -> bool {
+    self.state.shutting_down()
+  }
+
+  /// 标记连接是否在等待下一个请求, 已经开始关闭时返回false, 连接应当直接关闭
+  fn set_idle(&self, idle: bool) -> bool {
+    let mut connections = self.state.connections.lock().unwrap();
+    // 与`drain`持有同一把锁, 不会漏掉刚变为空闲的连接
+    if idle && self.shutting_down() {
+      return false;
+    }
+    if let Some(tracked) = connections.get_mut(&self.id) {
+      tracked.idle = idle;
+    }
+    true
+  }
+
+  /// 占用一个交出连接的名额, 已经有`max`个时返回None
+  fn reserve_upgrade(&self, max: usize) -> Option<UpgradeSlot> {
+    let upgraded = &self.state.upgraded;
+    upgraded
+      .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+        (n < max).then_some(n + 1)
+      })
+      .ok()?;
+    Some(UpgradeSlot(Arc::clone(&self.state)))
+  }
+}
+
+// 交出的连接占用的名额, 连接结束时归还
+struct UpgradeSlot(Arc<State>);
+
+impl Drop for UpgradeSlot {
+  fn drop(&mut self) {
+    self.0.upgraded.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+impl Drop for ConnectionGuard {
+  fn drop(&mut self) {
+    let mut connections = self.state.connections.lock().unwrap();
+    connections.remove(&self.id);
+    self.state.drained.notify_all();
+  }
+}
+
+// 无法继续处理这个连接时, 尽量告诉客户端原因再关闭, 并写一条访问日志;
+// 这时没有完整的请求行, 方法、目标和版本都记作"-"
+pub(crate) fn reject<W: Write>(
+  stream: &mut W,
+  status: StatusCode,
+  reason: &str,
+  config: &ConnectionConfig,
+  peer_addr: Option<SocketAddr>,
+) {
+  let started = (SystemTime::now(), Instant::now());
+  let mut response = Response::text(status, reason).with_header("Connection", "close");
+  let bytes = response.write_to(stream).unwrap_or(0);
+  if let Some(access_log) = &config.access_log {
+    access_log.log(&Entry {
+      peer_addr,
+      time: started.0,
+      method: "-",
+      target: "-",
+      version: "-",
+      status: status.code(),
+      bytes,
+      duration: started.1.elapsed(),
+      referer: None,
+      user_agent: None,
+    });
+  }
+}
+
+fn wants_keep_alive(request: &Request) -> bool {
+  match request.version {
+    Version::Http11 => !request.headers.has_token("Connection", "close"),
+    Version::Http10 => request.headers.has_token("Connection", "keep-alive"),
+  }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+  matches!(
+    e.kind(),
+    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs;
+  use std::net::TcpListener;
+  use std::thread;
+
+  use super::*;
+
+  fn serve_one(config: ConnectionConfig) -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+      let (stream, _) = listener.accept().unwrap();
+      let handler =
+        |request: &mut Request| Response::text(StatusCode::Ok, request.path().to_string());
+      handle_connection(stream, &handler, &config);
+    });
+    TcpStream::connect(addr).unwrap()
+  }
+
+  #[test]
+  fn pipelined_requests_in_order() {
+    let mut client = serve_one(ConnectionConfig::default());
+    client
+      .write_all(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\nConnection: close\r\n\r\n")
+      .unwrap();
+    let mut out = String::new();
+    client.read_to_string(&mut out).unwrap();
+
+    let bodies: Vec<&str> = out
+      .split("HTTP/1.1 200 OK")
+      .skip(1)
+      .map(|r| r.rsplit("\r\n\r\n").next().unwrap())
+      .collect();
+    assert_eq!(vec!["/a", "/b", "/c"], bodies);
+    assert!(out.ends_with("Connection: close\r\nContent-Length: 2\r\n\r\n/c"));
+  }
+
+  #[test]
+  fn http10_closes_by_default() {
+    let mut client = serve_one(ConnectionConfig::default());
+    client.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    let mut out = String::new();
+    client.read_to_string(&mut out).unwrap();
+    assert!(out.contains("Connection: close\r\n"));
+  }
+
+  #[test]
+  fn idle_timeout_closes_connection() {
+    let mut client = serve_one(ConnectionConfig {
+      idle_timeout: Duration::from_millis(100),
+      ..ConnectionConfig::default()
+    });
+    client
+      .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+      .unwrap();
+    let mut out = String::new();
+    client.read_to_string(&mut out).unwrap();
+    assert!(out.contains("Connection: keep-alive\r\n"));
+  }
+
+  #[test]
+  fn graceful_shutdown() {
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .workers(2)
+      .build(|request: &mut Request| {
+        if request.path() == "/slow" {
+          thread::sleep(Duration::from_millis(300));
+        }
+        Response::text(StatusCode::Ok, request.path().to_string())
+      })
+      .unwrap();
+    let addr = server.local_addr();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    // 一个空闲的keep-alive连接和一个正在处理的请求
+    let mut idle = TcpStream::connect(addr).unwrap();
+    idle.write_all(b"GET /idle HTTP/1.1\r\n\r\n").unwrap();
+    let mut buf = [0; 1024];
+    // 首部和响应体分两次写出, 要读到响应体为止
+    let mut out = Vec::new();
+    while !out.ends_with(b"/idle") {
+      let n = idle.read(&mut buf).unwrap();
+      assert!(n > 0);
+      out.extend_from_slice(&buf[..n]);
+    }
+    let mut slow = TcpStream::connect(addr).unwrap();
+    slow.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let started = Instant::now();
+    handle.shutdown();
+    let mut out = String::new();
+    slow.read_to_string(&mut out).unwrap();
+    assert!(out.contains("Connection: close\r\n"));
+    assert!(out.ends_with("/slow"));
+    assert_eq!(0, idle.read(&mut buf).unwrap());
+
+    running.join().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(TcpStream::connect(addr).is_err());
+  }
+
+  #[test]
+  fn shutdown_timeout_closes_connections() {
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .shutdown_timeout(Duration::from_millis(100))
+      .build(|request: &mut Request| {
+        thread::sleep(Duration::from_millis(500));
+        Response::text(StatusCode::Ok, request.path().to_string())
+      })
+      .unwrap();
+    let addr = server.local_addr();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    handle.shutdown();
+    let mut out = Vec::new();
+    let _ = client.read_to_end(&mut out);
+    assert!(out.is_empty());
+    running.join().unwrap();
+  }
+
+  #[test]
+  fn handler_panic_keeps_serving() {
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .workers(1)
+      .build(|request: &mut Request| {
+        if request.path() == "/panic" {
+          panic!("handler failed");
+        }
+        Response::text(StatusCode::Ok, request.path().to_string())
+      })
+      .unwrap();
+    let addr = server.local_addr();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.write_all(b"GET /panic HTTP/1.1\r\n\r\n").unwrap();
+    let mut out = String::new();
+    let _ = client.read_to_string(&mut out);
+    assert!(out.is_empty());
+
+    // 唯一的worker没有因为panic退出
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+      .write_all(b"GET /ok HTTP/1.1\r\nConnection: close\r\n\r\n")
+      .unwrap();
+    let mut out = String::new();
+    client.read_to_string(&mut out).unwrap();
+    assert!(out.ends_with("/ok"));
+
+    handle.shutdown();
+    running.join().unwrap();
+  }
+
+  #[test]
+  fn header_timeout_shorter_than_read_timeout() {
+    let config = ConnectionConfig {
+      header_timeout: Duration::from_millis(200),
+      read_timeout: Duration::from_secs(30),
+      ..ConnectionConfig::default()
+    };
+    // 发了一个字节之后就不再发送, 不用等到读超时
+    let started = Instant::now();
+    assert_eq!(
+      "HTTP/1.1 408 Request Timeout",
+      request_status(config.clone(), b"G")
+    );
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    // 每次发送都间隔不到读超时, 但首部始终收不完
+    let started = Instant::now();
+    let mut client = serve_one(config);
+    let mut out = String::new();
+    for _ in 0..10 {
+      if client.write_all(b"X").is_err() {
+        break;
+      }
+      thread::sleep(Duration::from_millis(100));
+    }
+    let _ = client.read_to_string(&mut out);
+    assert!(out.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    assert!(started.elapsed() < Duration::from_secs(2));
+  }
+
+  fn request_status(config: ConnectionConfig, raw: &[u8]) -> String {
+    let mut client = serve_one(config);
+    let _ = client.write_all(raw);
+    let mut out = String::new();
+    let _ = client.read_to_string(&mut out);
+    out.lines().next().unwrap_or("").to_string()
+  }
+
+  #[test]
+  fn reject_oversized_requests() {
+    let config = ConnectionConfig {
+      max_header_size: 64,
+      max_body_size: 4,
+      ..ConnectionConfig::default()
+    };
+    let long = format!("GET /{} HTTP/1.1\r\n\r\n", "x".repeat(100));
+    assert_eq!(
+      "HTTP/1.1 431 Request Header Fields Too Large",
+      request_status(config.clone(), long.as_bytes())
+    );
+    assert_eq!(
+      "HTTP/1.1 413 Payload Too Large",
+      request_status(config, b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello")
+    );
+  }
+
+  #[test]
+  fn rejections_are_logged() {
+    let path = env::temp_dir().join(format!("web-server-reject-{}.log", std::process::id()));
+    let log = AccessLog::new(LogFormat::Common, LogTarget::File(path.clone())).unwrap();
+    let config = ConnectionConfig {
+      max_body_size: 4,
+      access_log: Some(Arc::new(log)),
+      ..ConnectionConfig::default()
+    };
+    assert_eq!(
+      "HTTP/1.1 400 Bad Request",
+      request_status(config.clone(), b"GET\r\n\r\n")
+    );
+    assert_eq!(
+      "HTTP/1.1 413 Payload Too Large",
+      request_status(config, b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello")
+    );
+    let lines = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let statuses: Vec<&str> = lines
+      .lines()
+      .map(|line| line.split("\"- - -\" ").nth(1).unwrap())
+      .collect();
+    assert_eq!(2, statuses.len());
+    assert!(statuses[0].starts_with("400 ") && statuses[1].starts_with("413 "));
+  }
+
+  #[test]
+  fn slow_client_gets_request_timeout() {
+    let config = ConnectionConfig {
+      header_timeout: Duration::from_millis(100),
+      ..ConnectionConfig::default()
+    };
+    let mut client = serve_one(config.clone());
+    client.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    let mut out = String::new();
+    // 一点一点地发送首部, 每次都在读超时之内, 但总时间超过了首部的期限
+    for _ in 0..3 {
+      thread::sleep(Duration::from_millis(40));
+      client.write_all(b"X").unwrap();
+    }
+    let _ = client.read_to_string(&mut out);
+    assert!(out.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+
+    // 发了一部分之后就不再发送
+    let config = ConnectionConfig {
+      read_timeout: Duration::from_millis(100),
+      ..config
+    };
+    assert_eq!(
+      "HTTP/1.1 408 Request Timeout",
+      request_status(config, b"GET / HTTP/1.1\r\n")
+    );
+  }
+
+  #[test]
+  fn trickling_body_gets_request_timeout() {
+    let config = ConnectionConfig {
+      body_timeout: Duration::from_millis(200),
+      ..ConnectionConfig::default()
+    };
+    let started = Instant::now();
+    let mut client = serve_one(config);
+    client
+      .write_all(b"POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\n")
+      .unwrap();
+    // 每次发送都间隔不到读超时, 但请求体始终收不完
+    for _ in 0..20 {
+      thread::sleep(Duration::from_millis(50));
+      if client.write_all(b"x").is_err() {
+        break;
+      }
+    }
+    let mut out = String::new();
+    let _ = client.read_to_string(&mut out);
+    assert!(out.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    assert!(started.elapsed() < Duration::from_secs(2));
+  }
+
+  #[test]
+  fn slow_reader_is_disconnected() {
+    let config = ConnectionConfig {
+      response_timeout: Duration::from_millis(200),
+      ..ConnectionConfig::default()
+    };
+    let size = 64 * 1024 * 1024;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+      let (stream, _) = listener.accept().unwrap();
+      let handler =
+        move |_: &mut Request| Response::new(StatusCode::Ok).with_body(vec![b'x'; size]);
+      handle_connection(stream, &handler, &config);
+    });
+
+    // 不读响应, 发送缓冲区满了之后服务器一直写不完
+    let started = Instant::now();
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    server.join().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    let mut out = Vec::new();
+    let _ = client.read_to_end(&mut out);
+    assert!(out.len() < size);
+  }
+}
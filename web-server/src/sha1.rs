@@ -0,0 +1,83 @@
+//! SHA-1摘要(RFC 3174)
+//!
+//! 只用于计算WebSocket握手的`Sec-WebSocket-Accept`, 不要用于任何安全相关的场景。
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+  let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+  // 补一个1位, 再补0直到长度模64余56, 最后是以位为单位的原始长度
+  let mut message = data.to_vec();
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+  for block in message.chunks(64) {
+    let mut w = [0u32; 80];
+    for (i, word) in block.chunks(4).enumerate() {
+      w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..80 {
+      w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = h;
+    for (i, &word) in w.iter().enumerate() {
+      let (f, k) = match i {
+        0..=19 => ((b & c) | (!b & d), 0x5A827999),
+        20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+        40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+        _ => (b ^ c ^ d, 0xCA62C1D6),
+      };
+      let temp = a
+        .rotate_left(5)
+        .wrapping_add(f)
+        .wrapping_add(e)
+        .wrapping_add(k)
+        .wrapping_add(word);
+      e = d;
+      d = c;
+      c = b.rotate_left(30);
+      b = a;
+      a = temp;
+    }
+    for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+      *h = h.wrapping_add(v);
+    }
+  }
+
+  let mut digest = [0; 20];
+  for (i, word) in h.iter().enumerate() {
+    digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+  }
+  digest
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hex(digest: [u8; 20]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+
+  #[test]
+  fn known_digests() {
+    assert_eq!("da39a3ee5e6b4b0d3255bfef95601890afd80709", hex(sha1(b"")));
+    assert_eq!(
+      "a9993e364706816aba3e25717850c26c9cd0d89d",
+      hex(sha1(b"abc"))
+    );
+    assert_eq!(
+      "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+      hex(sha1(
+        b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+      ))
+    );
+    assert_eq!(
+      "34aa973cd4c4daa4f61eeb2bdbad27316534016f",
+      hex(sha1(&vec![b'a'; 1_000_000]))
+    );
+  }
+}
@@ -0,0 +1,66 @@
+//! Unix信号处理
+//!
+//! 信号处理函数里只能做异步信号安全的操作, 这里只记录收到的信号,
+//! 由一个单独的线程轮询并调用回调。
+
+use std::io;
+use std::os::raw::c_int;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+pub const SIGHUP: c_int = 1;
+pub const SIGINT: c_int = 2;
+pub const SIGTERM: c_int = 15;
+
+// 每个信号占一位
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+const SIG_ERR: usize = !0;
+
+extern "C" {
+  fn signal(signum: c_int, handler: usize) -> usize;
+}
+
+extern "C" fn record(signum: c_int) {
+  PENDING.fetch_or(1 << signum, Ordering::SeqCst);
+}
+
+/// 为`signals`安装处理函数, 之后每收到一个信号就在后台线程中调用一次`f`
+///
+/// # Examples
+///
+/// ```no_run
+/// use web_server::signal;
+///
+/// signal::notify(&[signal::SIGINT, signal::SIGTERM], |signum| {
+///   println!("received signal {}", signum);
+/// })
+/// .unwrap();
+/// ```
+pub fn notify<F>(signals: &[c_int], f: F) -> io::Result<()>
+where
+  F: Fn(c_int) + Send + 'static,
+{
+  let mut mask = 0;
+  for &signum in signals {
+    assert!((1..64).contains(&signum), "invalid signal {}", signum);
+    // 处理函数只修改一个原子变量, 是异步信号安全的
+    if unsafe { signal(signum, record as extern "C" fn(c_int) as usize) } == SIG_ERR {
+      return Err(io::Error::last_os_error());
+    }
+    mask |= 1 << signum;
+  }
+
+  thread::spawn(move || loop {
+    let pending = PENDING.fetch_and(!mask, Ordering::SeqCst) & mask;
+    for signum in 1..64 {
+      if pending & (1 << signum) != 0 {
+        f(signum);
+      }
+    }
+    thread::sleep(Duration::from_millis(100));
+  });
+  Ok(())
+}
@@ -0,0 +1,387 @@
+//! Server-Sent Events
+//!
+//! [`Sse::response`]返回一个`text/event-stream`响应和发送端, handler把响应返回之后,
+//! 从任意线程通过发送端推送事件。事件流在单独的线程中写出, 不占用线程池的worker;
+//! 所有发送端都被drop之后事件流结束。
+//!
+//! ```no_run
+//! use std::thread;
+//! use web_server::request::Request;
+//! use web_server::sse::{Event, Sse};
+//!
+//! let clock = |request: &mut Request| {
+//!   let (events, response) = Sse::new().response(request);
+//!   thread::spawn(move || {
+//!     for i in 0.. {
+//!       if events.send(Event::new(i.to_string()).event("tick")).is_err() {
+//!         break;
+//!       }
+//!       thread::sleep(std::time::Duration::from_secs(1));
+//!     }
+//!   });
+//!   response
+//! };
+//! ```
+//!
+//! 需要断线重连时补发错过的事件, 可以使用[`Broadcaster`]。
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::request::Request;
+use crate::request::Version;
+use crate::response::Response;
+use crate::response::StatusCode;
+use crate::server::Transport;
+
+/// 检查客户端是否断开的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 一个事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+  pub id: Option<String>,
+  pub event: Option<String>,
+  pub data: String,
+}
+
+impl Event {
+  /// 只有数据的事件, 浏览器中触发`message`事件
+  pub fn new(data: impl Into<String>) -> Event {
+    Event {
+      id: None,
+      event: None,
+      data: data.into(),
+    }
+  }
+
+  /// 事件类型
+  pub fn event(mut self, event: &str) -> Event {
+    self.event = Some(event.to_string());
+    self
+  }
+
+  /// 事件ID, 客户端重连时会在`Last-Event-ID`首部中带上最后收到的ID
+  pub fn id(mut self, id: &str) -> Event {
+    self.id = Some(id.to_string());
+    self
+  }
+
+  /// 编码为`id:`/`event:`/`data:`行, 以空行结尾; 多行数据拆成多个`data:`行
+  pub fn encode(&self) -> String {
+    let mut out = String::new();
+    if let Some(id) = &self.id {
+      out.push_str(&format!("id: {}\n", single_line(id)));
+    }
+    if let Some(event) = &self.event {
+      out.push_str(&format!("event: {}\n", single_line(event)));
+    }
+    for line in self.data.split("\r\n").flat_map(|l| l.split(['\r', '\n'])) {
+      out.push_str(&format!("data: {}\n", line));
+    }
+    out.push('\n');
+    out
+  }
+}
+
+// 换行会把字段截断成两行, 其余部分变成一个新字段
+fn single_line(s: &str) -> String {
+  s.replace(['\r', '\n', '\0'], "")
+}
+
+/// 客户端重连时带上的最后一个事件ID
+pub fn last_event_id(request: &Request) -> Option<&str> {
+  request.header("Last-Event-ID").map(str::trim)
+}
+
+/// 推送事件的发送端, 可以克隆到多个线程
+#[derive(Debug, Clone)]
+pub struct EventSender {
+  sender: mpsc::Sender<Event>,
+}
+
+impl EventSender {
+  /// 发送一个事件, 客户端已经断开时返回错误
+  pub fn send(&self, event: Event) -> Result<(), Event> {
+    self.sender.send(event).map_err(|e| e.0)
+  }
+}
+
+/// 事件流的设置
+#[derive(Debug, Clone)]
+pub struct Sse {
+  heartbeat: Duration,
+  retry: Option<Duration>,
+}
+
+impl Sse {
+  /// 默认每15秒发送一次心跳
+  pub fn new() -> Sse {
+    Sse {
+      heartbeat: Duration::from_secs(15),
+      retry: None,
+    }
+  }
+
+  /// 一段时间没有事件时发送一行注释, 防止代理因为连接空闲而断开
+  pub fn heartbeat(mut self, interval: Duration) -> Sse {
+    self.heartbeat = interval;
+    self
+  }
+
+  /// 告诉浏览器断开之后等待多久再重连
+  pub fn retry(mut self, retry: Duration) -> Sse {
+    self.retry = Some(retry);
+    self
+  }
+
+  /// 创建事件流响应, 返回推送事件用的发送端
+  ///
+  /// 事件流使用chunked编码, 需要HTTP/1.1; HTTP/1.0请求返回400, 发送端立即失效。
+  pub fn response(self, request: &Request) -> (EventSender, Response) {
+    let (sender, receiver) = mpsc::channel();
+    let sender = EventSender { sender };
+    if request.version != Version::Http11 {
+      let response = Response::text(StatusCode::BadRequest, "event streams require HTTP/1.1");
+      return (sender, response);
+    }
+    let response = Response::ok()
+      .with_header("Content-Type", "text/event-stream")
+      .with_header("Cache-Control", "no-cache")
+      .with_header("Transfer-Encoding", "chunked")
+      .with_upgrade(move |mut stream| {
+        if let Err(e) = self.stream(&mut *stream, receiver) {
+          eprintln!("Event stream closed: {}", e);
+        }
+      });
+    (sender, response)
+  }
+
+  fn stream(&self, stream: &mut dyn Transport, events: mpsc::Receiver<Event>) -> io::Result<()> {
+    if let Some(retry) = self.retry {
+      write_chunk(stream, &format!("retry: {}\n\n", retry.as_millis()))?;
+    }
+    let mut last_write = Instant::now();
+    loop {
+      match events.recv_timeout(POLL_INTERVAL.min(self.heartbeat)) {
+        Ok(event) => {
+          write_chunk(stream, &event.encode())?;
+          last_write = Instant::now();
+        }
+        Err(RecvTimeoutError::Timeout) => {
+          if peer_closed(stream) {
+            return Ok(());
+          }
+          if last_write.elapsed() >= self.heartbeat {
+            write_chunk(stream, ": heartbeat\n\n")?;
+            last_write = Instant::now();
+          }
+        }
+        // 所有发送端都已经drop, 结束响应体
+        Err(RecvTimeoutError::Disconnected) => {
+          stream.write_all(b"0\r\n\r\n")?;
+          return stream.flush();
+        }
+      }
+    }
+  }
+}
+
+impl Default for Sse {
+  fn default() -> Sse {
+    Sse::new()
+  }
+}
+
+fn write_chunk(stream: &mut dyn Transport, data: &str) -> io::Result<()> {
+  write!(stream, "{:x}\r\n{}\r\n", data.len(), data)?;
+  stream.flush()
+}
+
+// 客户端不会再发送数据, 读到EOF说明连接已经关闭(包括服务器关闭时断开了读方向)
+fn peer_closed(stream: &mut dyn Transport) -> bool {
+  if stream
+    .set_read_timeout(Some(Duration::from_millis(1)))
+    .is_err()
+  {
+    return true;
+  }
+  let mut buf = [0; 512];
+  match stream.read(&mut buf) {
+    Ok(0) => true,
+    Ok(_) => false,
+    Err(e) => !matches!(
+      e.kind(),
+      io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+    ),
+  }
+}
+
+/// 向所有订阅的客户端广播事件, 并保留最近的事件
+///
+/// 每个事件自动分配递增的数字ID。客户端重连时带上`Last-Event-ID`,
+/// 会先补发之后的事件, 超出保留数量的事件无法补发。
+#[derive(Debug)]
+pub struct Broadcaster {
+  sse: Sse,
+  capacity: usize,
+  state: Mutex<BroadcastState>,
+}
+
+#[derive(Debug, Default)]
+struct BroadcastState {
+  next_id: u64,
+  history: VecDeque<(u64, Event)>,
+  subscribers: Vec<EventSender>,
+}
+
+impl Broadcaster {
+  /// 最多保留`capacity`个事件用于补发
+  pub fn new(sse: Sse, capacity: usize) -> Broadcaster {
+    Broadcaster {
+      sse,
+      capacity,
+      state: Mutex::new(BroadcastState {
+        next_id: 1,
+        ..BroadcastState::default()
+      }),
+    }
+  }
+
+  /// 为这个请求创建事件流, 补发`Last-Event-ID`之后的事件
+  pub fn subscribe(&self, request: &Request) -> Response {
+    let (sender, response) = self.sse.clone().response(request);
+    let mut state = self.state.lock().unwrap();
+    if let Some(last) = last_event_id(request).and_then(|id| id.parse::<u64>().ok()) {
+      for (_, event) in state.history.iter().filter(|(id, _)| *id > last) {
+        let _ = sender.send(event.clone());
+      }
+    }
+    state.subscribers.push(sender);
+    response
+  }
+
+  /// 广播一个事件, 返回分配的ID; 已经断开的客户端会被移除
+  pub fn send(&self, event: Event) -> u64 {
+    let mut state = self.state.lock().unwrap();
+    let id = state.next_id;
+    state.next_id += 1;
+    let event = event.id(&id.to_string());
+    if self.capacity > 0 {
+      if state.history.len() == self.capacity {
+        state.history.pop_front();
+      }
+      state.history.push_back((id, event.clone()));
+    }
+    state
+      .subscribers
+      .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    id
+  }
+
+  /// 当前连接的客户端数量
+  pub fn subscribers(&self) -> usize {
+    self.state.lock().unwrap().subscribers.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Read;
+  use std::io::Write;
+  use std::net::TcpStream;
+  use std::sync::Arc;
+  use std::thread;
+
+  use super::*;
+  use crate::server::Server;
+
+  #[test]
+  fn encode_events() {
+    assert_eq!("data: hello\n\n", Event::new("hello").encode());
+    assert_eq!(
+      "id: 7\nevent: update\ndata: a\ndata: b\ndata: c\n\n",
+      Event::new("a\nb\r\nc").event("update").id("7").encode()
+    );
+    assert_eq!(
+      "event: xdata: y\ndata: \n\n",
+      Event::new("").event("x\ndata: y").encode()
+    );
+  }
+
+  // 读到`count`个空行分隔的块为止, 返回去掉chunked编码之后的内容
+  fn read_events(stream: &mut TcpStream, count: usize) -> String {
+    let mut raw = Vec::new();
+    let mut byte = [0];
+    while raw.windows(2).filter(|w| w == b"\n\n").count() < count {
+      stream.read_exact(&mut byte).unwrap();
+      raw.push(byte[0]);
+    }
+    String::from_utf8(raw).unwrap()
+  }
+
+  #[test]
+  fn broadcast_and_resume() {
+    let broadcaster = Arc::new(Broadcaster::new(
+      Sse::new().heartbeat(Duration::from_millis(200)),
+      10,
+    ));
+    let subscribe = Arc::clone(&broadcaster);
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .workers(1)
+      .build(move |request: &mut Request| subscribe.subscribe(request))
+      .unwrap();
+    let addr = server.local_addr();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    broadcaster.send(Event::new("missed"));
+    let mut first = TcpStream::connect(addr).unwrap();
+    first.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+    let head = read_until_blank_line(&mut first);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(head.contains("Content-Type: text/event-stream\r\n"));
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(head.contains("Connection: close\r\n"));
+
+    while broadcaster.subscribers() < 1 {
+      thread::sleep(Duration::from_millis(10));
+    }
+    broadcaster.send(Event::new("one").event("update"));
+    broadcaster.send(Event::new("two"));
+    let events = read_events(&mut first, 2);
+    assert!(events.contains("id: 2\nevent: update\ndata: one\n\n"));
+    assert!(events.ends_with("id: 3\ndata: two\n\n"));
+    // 没有事件时发送心跳
+    assert!(read_events(&mut first, 1).ends_with(": heartbeat\n\n"));
+
+    // 重连时补发ID 2之后的事件, 唯一的worker也没有被第一个事件流占用
+    let mut second = TcpStream::connect(addr).unwrap();
+    second
+      .write_all(b"GET /events HTTP/1.1\r\nLast-Event-ID: 2\r\n\r\n")
+      .unwrap();
+    read_until_blank_line(&mut second);
+    assert!(read_events(&mut second, 1).ends_with("id: 3\ndata: two\n\n"));
+
+    drop(first);
+    drop(second);
+    handle.shutdown();
+    running.join().unwrap();
+  }
+
+  fn read_until_blank_line(stream: &mut TcpStream) -> String {
+    let mut raw = Vec::new();
+    let mut byte = [0];
+    while !raw.ends_with(b"\r\n\r\n") {
+      stream.read_exact(&mut byte).unwrap();
+      raw.push(byte[0]);
+    }
+    String::from_utf8(raw).unwrap()
+  }
+}
@@ -0,0 +1,556 @@
+//! 静态文件服务
+//!
+//! 挂载在带通配符的路由上, 例如`router.get("/static/*", StaticFiles::new("public"))`,
+//! 通配符`*`匹配到的部分作为相对于根目录的路径; 没有通配符参数时使用整个请求路径。
+//!
+//! 文件响应带有`ETag`和`Last-Modified`, 请求中的`If-None-Match`/`If-Modified-Since`
+//! 命中时返回304。`Cache-Control`按挂载点分别配置。
+//!
+//! 文件内容边读边发送, 不会整个读入内存。GET请求支持`Range`(包括多个区间和`If-Range`),
+//! 可以用来断点续传大文件。
+
+use std::fs;
+use std::fs::File;
+use std::fs::Metadata;
+use std::io;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use crate::date;
+use crate::handler::Handler;
+use crate::mime;
+use crate::range;
+use crate::range::ByteRange;
+use crate::range::Ranges;
+use crate::request::Request;
+use crate::response::Response;
+use crate::response::StatusCode;
+use crate::url;
+
+pub struct StaticFiles {
+  root: PathBuf,
+  listing: bool,
+  cache_control: Option<String>,
+}
+
+impl StaticFiles {
+  pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+    StaticFiles {
+      root: root.into(),
+      listing: false,
+      cache_control: None,
+    }
+  }
+
+  /// 目录中没有`index.html`时是否生成目录列表, 默认不生成
+  pub fn listing(mut self, enabled: bool) -> StaticFiles {
+    self.listing = enabled;
+    self
+  }
+
+  /// 文件响应的`Cache-Control`首部, 例如`public, max-age=3600`, 默认不设置
+  pub fn cache_control(mut self, value: &str) -> StaticFiles {
+    self.cache_control = Some(value.to_string());
+    self
+  }
+
+  /// 把请求中的相对路径解析为根目录下的文件路径
+  ///
+  /// 百分号解码之后任何一段为`..`、包含反斜杠或NUL都会被拒绝,
+  /// 最终路径还会再规范化一次, 防止通过符号链接跳出根目录。
+  fn resolve(&self, relative: &str) -> Result<PathBuf, StatusCode> {
+    let decoded = url::percent_decode(relative).ok_or(StatusCode::BadRequest)?;
+    let decoded = String::from_utf8(decoded).map_err(|_| StatusCode::BadRequest)?;
+
+    let mut path = self.root.clone();
+    for segment in decoded.split('/') {
+      match segment {
+        "" | "." => {}
+        ".." => return Err(StatusCode::Forbidden),
+        s if s.contains('\\') || s.contains('\0') => return Err(StatusCode::Forbidden),
+        s => path.push(s),
+      }
+    }
+
+    let root = self.root.canonicalize().map_err(|_| StatusCode::NotFound)?;
+    let canonical = path.canonicalize().map_err(|_| StatusCode::NotFound)?;
+    if !canonical.starts_with(&root) {
+      return Err(StatusCode::Forbidden);
+    }
+    Ok(canonical)
+  }
+}
+
+impl Handler for StaticFiles {
+  fn call(&self, request: &mut Request) -> Response {
+    if request.method != "GET" && request.method != "HEAD" {
+      return Response::new(StatusCode::MethodNotAllowed).with_header("Allow", "GET, HEAD");
+    }
+    let relative = match request.params.get("*") {
+      Some(relative) => relative.clone(),
+      None => request.path().to_string(),
+    };
+    let path = match self.resolve(&relative) {
+      Ok(path) => path,
+      Err(status) => return Response::text(status, status.reason()),
+    };
+
+    if path.is_dir() {
+      // 目录必须以`/`结尾, 否则页面中的相对链接会指向上一级目录
+      if !request.path().ends_with('/') {
+        let location = match request.query() {
+          Some(query) => format!("{}/?{}", request.path(), query),
+          None => format!("{}/", request.path()),
+        };
+        return Response::redirect(StatusCode::MovedPermanently, &location);
+      }
+      let index = path.join("index.html");
+      if index.is_file() {
+        return self.serve_file(request, &index);
+      }
+      if !self.listing {
+        return Response::text(StatusCode::Forbidden, StatusCode::Forbidden.reason());
+      }
+      return match list_directory(&path, request.path(), relative.trim_matches('/').is_empty()) {
+        Ok(html) => Response::html(StatusCode::Ok, html),
+        Err(e) => internal_error(e),
+      };
+    }
+    self.serve_file(request, &path)
+  }
+}
+
+impl StaticFiles {
+  fn serve_file(&self, request: &Request, path: &Path) -> Response {
+    let metadata = match fs::metadata(path) {
+      Ok(metadata) => metadata,
+      Err(e) => return io_error(e),
+    };
+    let modified = metadata.modified().ok();
+    let etag = etag(&metadata);
+
+    let last_modified = modified.map(date::format);
+
+    let mut response = if is_not_modified(request, &etag, modified) {
+      Response::new(StatusCode::NotModified)
+    } else {
+      let content_type = mime::from_path(path);
+      let ranges = match request.header("Range") {
+        Some(header)
+          if request.method == "GET" && if_range_matches(request, &etag, &last_modified) =>
+        {
+          range::parse(header, metadata.len())
+        }
+        _ => None,
+      };
+      let response = match ranges {
+        None => open_range(path, 0, metadata.len()).map(|(file, length)| {
+          Response::ok()
+            .with_header("Content-Type", content_type)
+            .with_reader(file, Some(length))
+        }),
+        Some(Ranges::Unsatisfiable) => Ok(
+          Response::new(StatusCode::RangeNotSatisfiable)
+            .with_header("Content-Range", format!("bytes */{}", metadata.len())),
+        ),
+        Some(Ranges::Satisfiable(ranges)) if ranges.len() == 1 => {
+          let range = ranges[0];
+          open_range(path, range.start, range.size()).map(|(file, length)| {
+            Response::new(StatusCode::PartialContent)
+              .with_header("Content-Type", content_type)
+              .with_header("Content-Range", range.content_range(metadata.len()))
+              .with_reader(file, Some(length))
+          })
+        }
+        Some(Ranges::Satisfiable(ranges)) => {
+          multipart(path, content_type, &ranges, metadata.len(), &etag)
+        }
+      };
+      match response {
+        Ok(response) => response.with_header("Accept-Ranges", "bytes"),
+        Err(e) => return io_error(e),
+      }
+    };
+
+    response.headers.insert("ETag", etag);
+    if let Some(last_modified) = last_modified {
+      response.headers.insert("Last-Modified", last_modified);
+    }
+    if let Some(cache_control) = &self.cache_control {
+      response
+        .headers
+        .insert("Cache-Control", cache_control.as_str());
+    }
+    response
+  }
+}
+
+// 由修改时间和文件大小生成ETag, 文件内容变化时两者至少有一个会变
+fn etag(metadata: &Metadata) -> String {
+  let mtime = metadata
+    .modified()
+    .ok()
+    .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+    .unwrap_or_default();
+  format!("\"{:x}-{:x}\"", mtime.as_nanos(), metadata.len())
+}
+
+/// 按RFC 7232判断条件请求是否可以返回304
+///
+/// 有`If-None-Match`时忽略`If-Modified-Since`。
+fn is_not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+  if let Some(if_none_match) = request.header("If-None-Match") {
+    // If-None-Match使用弱比较, 忽略`W/`前缀
+    let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    return if_none_match.trim() == "*" || if_none_match.split(',').any(|t| weak(t) == weak(etag));
+  }
+  let since = match request.header("If-Modified-Since").and_then(date::parse) {
+    Some(since) => since,
+    None => return false,
+  };
+  match modified.and_then(|m| m.duration_since(UNIX_EPOCH).ok()) {
+    // HTTP日期只精确到秒
+    Some(modified) => UNIX_EPOCH + std::time::Duration::from_secs(modified.as_secs()) <= since,
+    None => false,
+  }
+}
+
+/// `If-Range`与当前版本一致时才处理`Range`, 否则返回完整文件
+///
+/// ETag使用强比较, 弱ETag永远不匹配。
+fn if_range_matches(request: &Request, etag: &str, last_modified: &Option<String>) -> bool {
+  match request.header("If-Range").map(str::trim) {
+    None => true,
+    Some(tag) if tag.starts_with('"') => tag == etag,
+    Some(since) => last_modified.as_deref() == Some(since),
+  }
+}
+
+// 打开文件并定位到`start`, 返回文件和实际可读的长度
+fn open_range(path: &Path, start: u64, length: u64) -> io::Result<(File, u64)> {
+  let mut file = File::open(path)?;
+  file.seek(SeekFrom::Start(start))?;
+  Ok((file, length))
+}
+
+/// 多个区间时返回`multipart/byteranges`响应
+///
+/// 每个区间单独打开一次文件, 依次拼接成一个流, 总长度事先算好。
+fn multipart(
+  path: &Path,
+  content_type: &str,
+  ranges: &[ByteRange],
+  total: u64,
+  etag: &str,
+) -> io::Result<Response> {
+  let boundary = format!("{:016x}", boundary_seed(etag));
+  let mut body: Box<dyn Read + Send> = Box::new(io::empty());
+  let mut length = 0;
+  for range in ranges {
+    let head = format!(
+      "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+      boundary,
+      content_type,
+      range.content_range(total)
+    );
+    let (file, size) = open_range(path, range.start, range.size())?;
+    length += head.len() as u64 + size;
+    body = Box::new(body.chain(Cursor::new(head)).chain(file.take(size)));
+  }
+  let tail = format!("\r\n--{}--\r\n", boundary);
+  length += tail.len() as u64;
+  body = Box::new(body.chain(Cursor::new(tail)));
+
+  Ok(
+    Response::new(StatusCode::PartialContent)
+      .with_header(
+        "Content-Type",
+        format!("multipart/byteranges; boundary={}", boundary),
+      )
+      .with_reader(body, Some(length)),
+  )
+}
+
+// 分隔符不能出现在文件内容中, 用ETag和当前时间混合出一个随机性足够的值
+fn boundary_seed(etag: &str) -> u64 {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_nanos() as u64;
+  etag
+    .bytes()
+    .fold(now, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn io_error(e: io::Error) -> Response {
+  if e.kind() == io::ErrorKind::NotFound {
+    return Response::text(StatusCode::NotFound, StatusCode::NotFound.reason());
+  }
+  internal_error(e)
+}
+
+fn internal_error(e: io::Error) -> Response {
+  eprintln!("Failed to serve static file: {}", e);
+  Response::text(
+    StatusCode::InternalServerError,
+    StatusCode::InternalServerError.reason(),
+  )
+}
+
+fn list_directory(dir: &Path, request_path: &str, is_root: bool) -> io::Result<String> {
+  let mut entries = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let mut name = entry.file_name().to_string_lossy().into_owned();
+    if entry.file_type()?.is_dir() {
+      name.push('/');
+    }
+    entries.push(name);
+  }
+  entries.sort();
+
+  let title = escape_html(request_path);
+  let mut html = format!(
+    "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n",
+    title
+  );
+  if !is_root {
+    html.push_str("<li><a href=\"../\">../</a></li>\n");
+  }
+  for name in entries {
+    let href = match name.strip_suffix('/') {
+      Some(dir) => format!("{}/", url::percent_encode(dir)),
+      None => url::percent_encode(&name),
+    };
+    html.push_str(&format!(
+      "<li><a href=\"{}\">{}</a></li>\n",
+      href,
+      escape_html(&name)
+    ));
+  }
+  html.push_str("</ul>\n</body>\n</html>\n");
+  Ok(html)
+}
+
+fn escape_html(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+
+  use super::*;
+
+  struct TempDir(PathBuf);
+
+  impl TempDir {
+    fn new(name: &str) -> TempDir {
+      let dir = env::temp_dir().join(format!("web-server-{}-{}", name, std::process::id()));
+      let _ = fs::remove_dir_all(&dir);
+      fs::create_dir_all(dir.join("public/docs")).unwrap();
+      fs::write(
+        dir.join("public/logo.png"),
+        [0x89, b'P', b'N', b'G', 0, 0xff],
+      )
+      .unwrap();
+      fs::write(dir.join("public/docs/a <b>.txt"), "hello").unwrap();
+      fs::write(dir.join("secret.txt"), "secret").unwrap();
+      TempDir(dir)
+    }
+  }
+
+  impl Drop for TempDir {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.0);
+    }
+  }
+
+  fn get(files: &StaticFiles, path: &str, relative: &str) -> Response {
+    get_with(files, path, relative, &[])
+  }
+
+  fn get_with(
+    files: &StaticFiles,
+    path: &str,
+    relative: &str,
+    headers: &[(&str, &str)],
+  ) -> Response {
+    let mut request = Request::new("GET", path);
+    request.params.insert("*".to_string(), relative.to_string());
+    for (name, value) in headers {
+      request.headers.insert(name, *value);
+    }
+    files.call(&mut request)
+  }
+
+  #[test]
+  fn serve_binary_with_mime_type() {
+    let dir = TempDir::new("binary");
+    let files = StaticFiles::new(dir.0.join("public"));
+    let response = get(&files, "/static/logo.png", "logo.png");
+    assert_eq!(StatusCode::Ok, response.status);
+    assert_eq!(Some("image/png"), response.headers.get("Content-Type"));
+    assert_eq!(Some("bytes"), response.headers.get("Accept-Ranges"));
+    assert_eq!(
+      vec![0x89, b'P', b'N', b'G', 0, 0xff],
+      response.body.into_bytes().unwrap()
+    );
+  }
+
+  #[test]
+  fn reject_traversal() {
+    let dir = TempDir::new("traversal");
+    let files = StaticFiles::new(dir.0.join("public"));
+    for relative in &[
+      "../secret.txt",
+      "docs/../../secret.txt",
+      "%2e%2e/secret.txt",
+      "..%2fsecret.txt",
+    ] {
+      assert_eq!(
+        StatusCode::Forbidden,
+        get(&files, "/static/x", relative).status
+      );
+    }
+    assert_eq!(
+      StatusCode::NotFound,
+      get(&files, "/static/nope", "nope").status
+    );
+  }
+
+  #[test]
+  fn conditional_requests() {
+    let dir = TempDir::new("conditional");
+    let files = StaticFiles::new(dir.0.join("public")).cache_control("public, max-age=60");
+    let response = get(&files, "/static/logo.png", "logo.png");
+    let etag = response.headers.get("ETag").unwrap().to_string();
+    let last_modified = response.headers.get("Last-Modified").unwrap().to_string();
+    assert_eq!(
+      Some("public, max-age=60"),
+      response.headers.get("Cache-Control")
+    );
+
+    let conditional =
+      |name: &str, value: &str| get_with(&files, "/static/logo.png", "logo.png", &[(name, value)]);
+    let response = conditional("If-None-Match", &format!("\"x\", W/{}", etag));
+    assert_eq!(StatusCode::NotModified, response.status);
+    assert!(response.body.is_empty());
+    assert_eq!(Some(etag.as_str()), response.headers.get("ETag"));
+    assert_eq!(
+      StatusCode::NotModified,
+      conditional("If-Modified-Since", &last_modified).status
+    );
+    assert_eq!(
+      StatusCode::Ok,
+      conditional("If-None-Match", "\"other\"").status
+    );
+    assert_eq!(
+      StatusCode::Ok,
+      conditional("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT").status
+    );
+  }
+
+  #[test]
+  fn directory_listing() {
+    let dir = TempDir::new("listing");
+    let files = StaticFiles::new(dir.0.join("public"));
+    assert_eq!(
+      StatusCode::Forbidden,
+      get(&files, "/static/docs/", "docs").status
+    );
+
+    let files = files.listing(true);
+    let response = get(&files, "/static/docs", "docs");
+    assert_eq!(StatusCode::MovedPermanently, response.status);
+    assert_eq!(Some("/static/docs/"), response.headers.get("Location"));
+
+    let response = get(&files, "/static/docs/", "docs");
+    let html = String::from_utf8(response.body.into_bytes().unwrap()).unwrap();
+    assert!(html.contains("<a href=\"../\">../</a>"));
+    assert!(html.contains("<a href=\"a%20%3Cb%3E.txt\">a &lt;b&gt;.txt</a>"));
+  }
+
+  #[test]
+  fn range_requests() {
+    let dir = TempDir::new("range");
+    fs::write(dir.0.join("public/digits.txt"), "0123456789").unwrap();
+    let files = StaticFiles::new(dir.0.join("public"));
+    let range = |value: &str| {
+      get_with(
+        &files,
+        "/static/digits.txt",
+        "digits.txt",
+        &[("Range", value)],
+      )
+    };
+
+    let response = range("bytes=2-4");
+    assert_eq!(StatusCode::PartialContent, response.status);
+    assert_eq!(Some("bytes 2-4/10"), response.headers.get("Content-Range"));
+    assert_eq!(b"234".to_vec(), response.body.into_bytes().unwrap());
+    assert_eq!(
+      b"789".to_vec(),
+      range("bytes=-3").body.into_bytes().unwrap()
+    );
+
+    let response = range("bytes=20-");
+    assert_eq!(StatusCode::RangeNotSatisfiable, response.status);
+    assert_eq!(Some("bytes */10"), response.headers.get("Content-Range"));
+
+    // 语法错误时忽略Range
+    assert_eq!(StatusCode::Ok, range("bytes=4-2").status);
+
+    let response = range("bytes=0-1,8-");
+    let content_type = response.headers.get("Content-Type").unwrap();
+    let boundary = content_type
+      .strip_prefix("multipart/byteranges; boundary=")
+      .unwrap()
+      .to_string();
+    let length = response.body.len();
+    let body = String::from_utf8(response.body.into_bytes().unwrap()).unwrap();
+    assert_eq!(Some(body.len() as u64), length);
+    assert_eq!(
+      format!(
+        "\r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+         \r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+         \r\n--{0}--\r\n",
+        boundary
+      ),
+      body
+    );
+  }
+
+  #[test]
+  fn if_range() {
+    let dir = TempDir::new("if-range");
+    fs::write(dir.0.join("public/digits.txt"), "0123456789").unwrap();
+    let files = StaticFiles::new(dir.0.join("public"));
+    let response = get(&files, "/static/digits.txt", "digits.txt");
+    let etag = response.headers.get("ETag").unwrap().to_string();
+    let last_modified = response.headers.get("Last-Modified").unwrap().to_string();
+
+    let if_range = |value: &str| {
+      get_with(
+        &files,
+        "/static/digits.txt",
+        "digits.txt",
+        &[("Range", "bytes=0-0"), ("If-Range", value)],
+      )
+      .status
+    };
+    assert_eq!(StatusCode::PartialContent, if_range(&etag));
+    assert_eq!(StatusCode::PartialContent, if_range(&last_modified));
+    assert_eq!(StatusCode::Ok, if_range("\"stale\""));
+    assert_eq!(StatusCode::Ok, if_range(&format!("W/{}", etag)));
+  }
+}
@@ -0,0 +1,205 @@
+//! HTTPS支持, 需要启用`tls` feature
+//!
+//! 使用rustls在已经accept的`TcpStream`上完成TLS握手, 之后的读写与普通连接相同。
+
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::ServerConfig;
+use rustls::ServerConnection;
+use rustls::StreamOwned;
+
+use crate::server::Transport;
+
+/// 从PEM文件读取证书链和私钥
+pub fn load_config(cert: &Path, key: &Path) -> io::Result<Arc<ServerConfig>> {
+  let cert_pem = fs::read(cert).map_err(|e| with_path(e, cert))?;
+  let key_pem = fs::read(key).map_err(|e| with_path(e, key))?;
+  server_config(&cert_pem, &key_pem)
+}
+
+/// 用PEM格式的证书链和私钥创建服务端配置, 只支持HTTP/1.1
+pub fn server_config(cert_pem: &[u8], key_pem: &[u8]) -> io::Result<Arc<ServerConfig>> {
+  let certs = CertificateDer::pem_slice_iter(cert_pem)
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| invalid(format!("invalid certificate: {}", e)))?;
+  if certs.is_empty() {
+    return Err(invalid("no certificate found".to_string()));
+  }
+  let key = PrivateKeyDer::from_pem_slice(key_pem)
+    .map_err(|e| invalid(format!("invalid private key: {}", e)))?;
+
+  let provider = Arc::new(rustls::crypto::ring::default_provider());
+  let mut config = ServerConfig::builder_with_provider(provider)
+    .with_safe_default_protocol_versions()
+    .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+    .map_err(|e| invalid(e.to_string()))?;
+  config.alpn_protocols = vec![b"http/1.1".to_vec()];
+  Ok(Arc::new(config))
+}
+
+fn invalid(message: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn with_path(e: io::Error, path: &Path) -> io::Error {
+  io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+/// 服务端的TLS连接
+pub struct TlsStream {
+  inner: StreamOwned<ServerConnection, TcpStream>,
+}
+
+impl TlsStream {
+  /// 在`stream`上完成TLS握手, 握手必须在`timeout`内完成
+  pub fn accept(
+    config: Arc<ServerConfig>,
+    stream: TcpStream,
+    timeout: Duration,
+  ) -> io::Result<TlsStream> {
+    let mut conn = ServerConnection::new(config).map_err(io::Error::other)?;
+    let mut stream = stream;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    while conn.is_handshaking() {
+      conn.complete_io(&mut stream)?;
+    }
+    Ok(TlsStream {
+      inner: StreamOwned::new(conn, stream),
+    })
+  }
+}
+
+impl Read for TlsStream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self.inner.read(buf) {
+      // 客户端没有发送close_notify就关闭了连接, 当作正常关闭处理
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+      result => result,
+    }
+  }
+}
+
+impl Write for TlsStream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.inner.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+impl Transport for TlsStream {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.inner.sock.set_read_timeout(timeout)
+  }
+
+  fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.inner.sock.set_write_timeout(timeout)
+  }
+
+  fn peer_addr(&self) -> io::Result<SocketAddr> {
+    self.inner.sock.peer_addr()
+  }
+
+  fn is_secure(&self) -> bool {
+    true
+  }
+}
+
+impl Drop for TlsStream {
+  // 尽量发送close_notify, 让客户端知道响应没有被截断
+  fn drop(&mut self) {
+    self.inner.conn.send_close_notify();
+    let _ = self.inner.conn.write_tls(&mut self.inner.sock);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs;
+  use std::process;
+  use std::thread;
+
+  use rustls::ClientConfig;
+  use rustls::ClientConnection;
+  use rustls::RootCertStore;
+
+  use super::*;
+  use crate::request::Request;
+  use crate::response::Response;
+  use crate::response::StatusCode;
+  use crate::server::Server;
+
+  #[test]
+  fn https_with_redirect() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let dir = env::temp_dir().join(format!("web-server-tls-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("cert.pem"), cert.cert.pem()).unwrap();
+    fs::write(dir.join("key.pem"), cert.key_pair.serialize_pem()).unwrap();
+
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .tls_address("127.0.0.1:0")
+      .tls_cert(dir.join("cert.pem"))
+      .tls_key(dir.join("key.pem"))
+      .redirect_https(true)
+      .build(|request: &mut Request| {
+        let body = format!("{} {}", request.scheme(), request.path());
+        Response::text(StatusCode::Ok, body)
+      })
+      .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let http_addr = server.local_addr();
+    let https_addr = server.tls_local_addr().unwrap();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.cert.der().clone()).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+      .with_safe_default_protocol_versions()
+      .unwrap()
+      .with_root_certificates(roots)
+      .with_no_client_auth();
+    let conn = ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+    let mut client = StreamOwned::new(conn, TcpStream::connect(https_addr).unwrap());
+    client
+      .write_all(b"GET /secure HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+      .unwrap();
+    let mut out = String::new();
+    client.read_to_string(&mut out).unwrap();
+    assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(out.ends_with("https /secure"));
+
+    let mut plain = TcpStream::connect(http_addr).unwrap();
+    plain
+      .write_all(b"GET /a?b=1 HTTP/1.1\r\nHost: localhost:8080\r\nConnection: close\r\n\r\n")
+      .unwrap();
+    let mut out = String::new();
+    plain.read_to_string(&mut out).unwrap();
+    assert!(out.starts_with("HTTP/1.1 308 Permanent Redirect\r\n"));
+    assert!(out.contains(&format!(
+      "Location: https://localhost:{}/a?b=1\r\n",
+      https_addr.port()
+    )));
+
+    handle.shutdown();
+    running.join().unwrap();
+  }
+}
@@ -0,0 +1,46 @@
+//! URL编码相关的工具函数
+
+/// 解码`%XX`转义, 遇到不合法的转义时返回None
+pub fn percent_decode(s: &str) -> Option<Vec<u8>> {
+  let bytes = s.as_bytes();
+  let mut out = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] == b'%' {
+      let hex = bytes.get(i + 1..i + 3)?;
+      let hex = std::str::from_utf8(hex).ok()?;
+      out.push(u8::from_str_radix(hex, 16).ok()?);
+      i += 3;
+    } else {
+      out.push(bytes[i]);
+      i += 1;
+    }
+  }
+  Some(out)
+}
+
+/// 编码路径中的一段, 只保留RFC 3986中的非保留字符
+pub fn percent_encode(segment: &str) -> String {
+  let mut out = String::with_capacity(segment.len());
+  for b in segment.bytes() {
+    if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+      out.push(b as char);
+    } else {
+      out.push_str(&format!("%{:02X}", b));
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn round_trip() {
+    assert_eq!(Some(b"a b/..".to_vec()), percent_decode("a%20b%2F%2e."));
+    assert_eq!(None, percent_decode("bad%2"));
+    assert_eq!(None, percent_decode("bad%zz"));
+    assert_eq!("a%20b%2F..", percent_encode("a b/.."));
+  }
+}
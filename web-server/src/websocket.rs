@@ -0,0 +1,817 @@
+//! WebSocket(RFC 6455)
+//!
+//! handler调用[`upgrade`]返回101响应, 握手完成之后连接交给回调。回调在单独的线程中
+//! 运行, 不占用线程池中处理普通请求的worker:
+//!
+//! ```no_run
+//! use web_server::request::Request;
+//! use web_server::websocket::{self, Message};
+//!
+//! let echo = |request: &mut Request| {
+//!   websocket::upgrade(request, |mut ws| {
+//!     while let Ok(Some(message)) = ws.recv() {
+//!       if let Message::Text(text) = message {
+//!         let _ = ws.send(Message::Text(text));
+//!       }
+//!     }
+//!   })
+//! };
+//! ```
+//!
+//! 需要在等待消息的同时主动推送时, 用[`WebSocket::sender`]取得一个[`Sender`]交给其他线程。
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::base64;
+use crate::request::Request;
+use crate::request::Version;
+use crate::response::Response;
+use crate::response::StatusCode;
+use crate::server::Transport;
+use crate::sha1::sha1;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// 常用的关闭状态码
+pub mod close_code {
+  pub const NORMAL: u16 = 1000;
+  pub const GOING_AWAY: u16 = 1001;
+  pub const PROTOCOL_ERROR: u16 = 1002;
+  pub const INVALID_DATA: u16 = 1007;
+  pub const MESSAGE_TOO_BIG: u16 = 1009;
+}
+
+/// 一条完整的消息, 分片的消息会被合并
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+  Text(String),
+  Binary(Vec<u8>),
+  /// 收到ping时已经自动回复了pong
+  Ping(Vec<u8>),
+  Pong(Vec<u8>),
+  /// 对方没有给出状态码时为None
+  Close(Option<CloseFrame>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+  pub code: u16,
+  pub reason: String,
+}
+
+/// 根据客户端的`Sec-WebSocket-Key`计算`Sec-WebSocket-Accept`
+pub fn accept_key(key: &str) -> String {
+  base64::encode(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+/// 检查WebSocket握手请求, 返回101响应, 响应发送之后把连接交给`f`
+///
+/// 不是合法的握手请求时返回400, 版本不是13时返回426。
+pub fn upgrade<F>(request: &Request, f: F) -> Response
+where
+  F: FnOnce(WebSocket) + Send + 'static,
+{
+  if request.method != "GET"
+    || request.version != Version::Http11
+    || !request.headers.has_token("Upgrade", "websocket")
+    || !request.headers.has_token("Connection", "upgrade")
+  {
+    return Response::text(StatusCode::BadRequest, "expected a WebSocket handshake");
+  }
+  if request.header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+    return Response::new(StatusCode::UpgradeRequired).with_header("Sec-WebSocket-Version", "13");
+  }
+  // key是随机的16个字节经过base64编码
+  let key = match request.header("Sec-WebSocket-Key").map(str::trim) {
+    Some(key) if base64::decode(key).is_some_and(|k| k.len() == 16) => key,
+    _ => return Response::text(StatusCode::BadRequest, "invalid Sec-WebSocket-Key"),
+  };
+  Response::new(StatusCode::SwitchingProtocols)
+    .with_header("Upgrade", "websocket")
+    .with_header("Connection", "Upgrade")
+    .with_header("Sec-WebSocket-Accept", accept_key(key))
+    .with_upgrade(move |stream| f(WebSocket::new(stream)))
+}
+
+/// 服务端的WebSocket连接
+///
+/// 默认有空闲超时: `recv`等待60秒没有收到任何数据时发送一个ping, 再过60秒仍然没有
+/// 数据就以1001关闭连接并返回`TimedOut`错误, 不让失联的客户端一直占用线程。
+///
+/// 也可以改为自己设置读超时, `recv`超时返回`WouldBlock`或`TimedOut`错误,
+/// 已经收到的半个帧不会丢失, 之后可以继续调用`recv`。
+pub struct WebSocket {
+  stream: Box<dyn Transport + Send>,
+  // 所有写入都在这把锁内进行, 与`Sender`的写入不会交错
+  writer: Arc<Mutex<Writer>>,
+  buf: Vec<u8>,
+  // 分片消息的类型和已经收到的数据
+  fragments: Option<(u8, Vec<u8>)>,
+  max_message_size: usize,
+  // 为None时由调用者自己处理读超时
+  idle_timeout: Option<Duration>,
+  ping_sent: bool,
+  closed: bool,
+}
+
+struct Writer {
+  // 创建过`Sender`之后是复制出来的写端, 否则直接写`WebSocket`自己的流
+  stream: Option<Box<dyn Write + Send>>,
+  close_sent: bool,
+}
+
+/// 在其他线程中向同一个WebSocket连接发送消息, 由[`WebSocket::sender`]创建
+///
+/// 可以clone, 所有`Sender`和`WebSocket`自己的写入按帧串行进行。`WebSocket`被丢弃之后
+/// 连接要等到所有`Sender`也被丢弃才会真正关闭。
+///
+/// ```no_run
+/// use std::thread;
+/// use std::time::Duration;
+/// use web_server::request::Request;
+/// use web_server::websocket::{self, Message};
+///
+/// let clock = |request: &mut Request| {
+///   websocket::upgrade(request, |mut ws| {
+///     let sender = ws.sender().unwrap();
+///     thread::spawn(move || {
+///       while sender.send(Message::Text("tick".to_string())).is_ok() {
+///         thread::sleep(Duration::from_secs(1));
+///       }
+///     });
+///     while let Ok(Some(_)) = ws.recv() {}
+///   })
+/// };
+/// ```
+#[derive(Clone)]
+pub struct Sender {
+  writer: Arc<Mutex<Writer>>,
+}
+
+impl Sender {
+  /// 与[`WebSocket::send`]相同
+  pub fn send(&self, message: Message) -> io::Result<()> {
+    let mut writer = self.writer.lock().unwrap();
+    let writer = &mut *writer;
+    // 创建Sender时已经复制了写端
+    let stream = writer.stream.as_mut().unwrap();
+    send_message(stream, &mut writer.close_sent, message)
+  }
+
+  /// 与[`WebSocket::close`]相同, 关闭之后由`WebSocket::recv`等待对方的确认
+  pub fn close(&self, code: u16, reason: &str) -> io::Result<()> {
+    self.send(close_message(code, reason))
+  }
+}
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+impl WebSocket {
+  fn new(stream: Box<dyn Transport + Send>) -> WebSocket {
+    // 升级之前设置的是HTTP请求的读超时
+    let _ = stream.set_read_timeout(Some(IDLE_TIMEOUT));
+    WebSocket {
+      stream,
+      writer: Arc::new(Mutex::new(Writer {
+        stream: None,
+        close_sent: false,
+      })),
+      buf: Vec::new(),
+      fragments: None,
+      max_message_size: 16 * 1024 * 1024,
+      idle_timeout: Some(IDLE_TIMEOUT),
+      ping_sent: false,
+      closed: false,
+    }
+  }
+
+  /// 单条消息的最大字节数, 超过时以1009关闭连接, 默认16MiB
+  pub fn set_max_message_size(&mut self, size: usize) {
+    self.max_message_size = size;
+  }
+
+  /// 空闲多久之后发送ping, 再空闲同样长的时间后关闭连接, 默认60秒
+  pub fn set_idle_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+    self.stream.set_read_timeout(Some(timeout))?;
+    self.idle_timeout = Some(timeout);
+    Ok(())
+  }
+
+  /// 自己处理读超时, 不再使用空闲超时; 为None时`recv`会一直阻塞到收到数据
+  pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+    self.stream.set_read_timeout(timeout)?;
+    self.idle_timeout = None;
+    Ok(())
+  }
+
+  pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+    self.stream.peer_addr()
+  }
+
+  /// 创建一个可以交给其他线程的[`Sender`], 这样一个线程阻塞在`recv`时其他线程仍然可以推送
+  ///
+  /// 需要复制底层连接的写端, TLS连接目前不支持, 返回`Unsupported`错误。
+  pub fn sender(&self) -> io::Result<Sender> {
+    let mut writer = self.writer.lock().unwrap();
+    if writer.stream.is_none() {
+      writer.stream = Some(self.stream.try_clone_writer()?);
+    }
+    Ok(Sender {
+      writer: Arc::clone(&self.writer),
+    })
+  }
+
+  /// 接收下一条消息, 连接已经关闭时返回None
+  ///
+  /// 收到ping时自动回复pong, 收到close时回复close。对方违反协议时发送对应的
+  /// 关闭状态码并返回`InvalidData`错误。
+  pub fn recv(&mut self) -> io::Result<Option<Message>> {
+    loop {
+      if self.closed {
+        return Ok(None);
+      }
+      let frame = match self.read_frame() {
+        Ok(Some(frame)) => frame,
+        Ok(None) => {
+          self.closed = true;
+          return Ok(None);
+        }
+        Err(e) if self.idle_timeout.is_some() && is_timeout(&e) => {
+          if self.ping_sent || self.writer.lock().unwrap().close_sent {
+            self.fail(close_code::GOING_AWAY, "idle timeout");
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"));
+          }
+          self.write_frame(PING, b"")?;
+          self.ping_sent = true;
+          continue;
+        }
+        Err(e) => return Err(e),
+      };
+      self.ping_sent = false;
+      match frame.opcode {
+        PING => {
+          self.with_writer(|stream, close_sent| match close_sent {
+            true => Ok(()),
+            false => write_frame(stream, PONG, &frame.payload),
+          })?;
+          return Ok(Some(Message::Ping(frame.payload)));
+        }
+        PONG => return Ok(Some(Message::Pong(frame.payload))),
+        CLOSE => return self.on_close(&frame.payload).map(Some),
+        TEXT | BINARY if self.fragments.is_some() => {
+          return Err(self.fail(close_code::PROTOCOL_ERROR, "expected a continuation frame"))
+        }
+        TEXT | BINARY if frame.fin => return self.message(frame.opcode, frame.payload).map(Some),
+        TEXT | BINARY => self.fragments = Some((frame.opcode, frame.payload)),
+        CONTINUATION => {
+          let (opcode, mut data) = match self.fragments.take() {
+            Some(fragments) => fragments,
+            None => {
+              return Err(self.fail(close_code::PROTOCOL_ERROR, "unexpected continuation frame"))
+            }
+          };
+          if data.len() + frame.payload.len() > self.max_message_size {
+            return Err(self.fail(close_code::MESSAGE_TOO_BIG, "message too big"));
+          }
+          data.extend_from_slice(&frame.payload);
+          if frame.fin {
+            return self.message(opcode, data).map(Some);
+          }
+          self.fragments = Some((opcode, data));
+        }
+        _ => return Err(self.fail(close_code::PROTOCOL_ERROR, "unknown opcode")),
+      }
+    }
+  }
+
+  /// 发送一条消息, 发送`Message::Close`之后不能再发送其他消息
+  pub fn send(&mut self, message: Message) -> io::Result<()> {
+    self.with_writer(|stream, close_sent| send_message(stream, close_sent, message))
+  }
+
+  /// 发起关闭, 之后继续调用`recv`可以等到对方的确认
+  pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+    self.send(close_message(code, reason))
+  }
+
+  fn message(&mut self, opcode: u8, data: Vec<u8>) -> io::Result<Message> {
+    if opcode == BINARY {
+      return Ok(Message::Binary(data));
+    }
+    match String::from_utf8(data) {
+      Ok(text) => Ok(Message::Text(text)),
+      Err(_) => Err(self.fail(close_code::INVALID_DATA, "invalid UTF-8 in text message")),
+    }
+  }
+
+  fn on_close(&mut self, payload: &[u8]) -> io::Result<Message> {
+    let frame = match payload.len() {
+      0 => None,
+      1 => return Err(self.fail(close_code::PROTOCOL_ERROR, "invalid close frame")),
+      _ => match String::from_utf8(payload[2..].to_vec()) {
+        Ok(reason) => Some(CloseFrame {
+          code: u16::from_be_bytes([payload[0], payload[1]]),
+          reason,
+        }),
+        Err(_) => return Err(self.fail(close_code::INVALID_DATA, "invalid UTF-8 in close reason")),
+      },
+    };
+    // 对方先发起关闭时原样回复状态码
+    let code = frame.as_ref().map(|f| f.code.to_be_bytes().to_vec());
+    self.close_once(&code.unwrap_or_default());
+    self.closed = true;
+    Ok(Message::Close(frame))
+  }
+
+  // 发送关闭帧之后返回错误, 不再处理这个连接上的数据
+  fn fail(&mut self, code: u16, reason: &'static str) -> io::Error {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    self.close_once(&payload);
+    self.closed = true;
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+  }
+
+  // 读到一个完整的帧才返回, 对方关闭连接时返回None
+  fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+    let mut chunk = [0; 4096];
+    loop {
+      match parse_frame(&self.buf, self.max_message_size) {
+        Ok(Some((frame, used))) => {
+          self.buf.drain(..used);
+          return Ok(Some(frame));
+        }
+        Ok(None) => {}
+        Err((code, reason)) => return Err(self.fail(code, reason)),
+      }
+      let n = self.stream.read(&mut chunk)?;
+      if n == 0 {
+        return Ok(None);
+      }
+      self.buf.extend_from_slice(&chunk[..n]);
+    }
+  }
+
+  // 还没有发送过关闭帧时发送一个, 写入失败也不再发送
+  fn close_once(&mut self, payload: &[u8]) {
+    self.with_writer(|stream, close_sent| {
+      if !*close_sent {
+        *close_sent = true;
+        let _ = write_frame(stream, CLOSE, payload);
+      }
+    })
+  }
+
+  fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    self.with_writer(|stream, _| write_frame(stream, opcode, payload))
+  }
+
+  fn with_writer<T>(&mut self, f: impl FnOnce(&mut dyn Write, &mut bool) -> T) -> T {
+    let mut writer = self.writer.lock().unwrap();
+    let Writer { stream, close_sent } = &mut *writer;
+    match stream {
+      Some(stream) => f(stream, close_sent),
+      None => f(&mut self.stream, close_sent),
+    }
+  }
+}
+
+fn close_message(code: u16, reason: &str) -> Message {
+  Message::Close(Some(CloseFrame {
+    code,
+    reason: reason.to_string(),
+  }))
+}
+
+fn send_message(stream: &mut dyn Write, close_sent: &mut bool, message: Message) -> io::Result<()> {
+  if *close_sent {
+    return Err(io::Error::new(
+      io::ErrorKind::NotConnected,
+      "close frame already sent",
+    ));
+  }
+  match message {
+    Message::Text(text) => write_frame(stream, TEXT, text.as_bytes()),
+    Message::Binary(data) => write_frame(stream, BINARY, &data),
+    Message::Ping(data) => write_control(stream, PING, &data),
+    Message::Pong(data) => write_control(stream, PONG, &data),
+    Message::Close(frame) => {
+      let mut payload = Vec::new();
+      if let Some(frame) = frame {
+        payload.extend_from_slice(&frame.code.to_be_bytes());
+        payload.extend_from_slice(frame.reason.as_bytes());
+      }
+      write_control(stream, CLOSE, &payload)?;
+      *close_sent = true;
+      Ok(())
+    }
+  }
+}
+
+fn write_control(stream: &mut dyn Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+  if payload.len() > 125 {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "control frame payload is longer than 125 bytes",
+    ));
+  }
+  write_frame(stream, opcode, payload)
+}
+
+fn write_frame(stream: &mut dyn Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+  stream.write_all(&encode_frame(true, opcode, payload, None))?;
+  stream.flush()
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+  matches!(
+    e.kind(),
+    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+  )
+}
+
+struct Frame {
+  fin: bool,
+  opcode: u8,
+  payload: Vec<u8>,
+}
+
+// 从`buf`开头解析一个客户端发来的帧, 数据还不完整时返回Ok(None)
+fn parse_frame(buf: &[u8], max_size: usize) -> Result<Option<(Frame, usize)>, (u16, &'static str)> {
+  if buf.len() < 2 {
+    return Ok(None);
+  }
+  let fin = buf[0] & 0x80 != 0;
+  let opcode = buf[0] & 0x0f;
+  if buf[0] & 0x70 != 0 {
+    return Err((close_code::PROTOCOL_ERROR, "reserved bits are set"));
+  }
+  if buf[1] & 0x80 == 0 {
+    return Err((close_code::PROTOCOL_ERROR, "client frames must be masked"));
+  }
+  let (len, mut pos) = match buf[1] & 0x7f {
+    126 if buf.len() < 4 => return Ok(None),
+    126 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+    127 if buf.len() < 10 => return Ok(None),
+    127 => (u64::from_be_bytes(buf[2..10].try_into().unwrap()), 10),
+    len => (len as u64, 2),
+  };
+  if opcode >= CLOSE && (!fin || len > 125) {
+    return Err((close_code::PROTOCOL_ERROR, "invalid control frame"));
+  }
+  if len > max_size as u64 {
+    return Err((close_code::MESSAGE_TOO_BIG, "message too big"));
+  }
+  let len = len as usize;
+  if buf.len() < pos + 4 + len {
+    return Ok(None);
+  }
+  let mask = [buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]];
+  pos += 4;
+  let payload = buf[pos..pos + len]
+    .iter()
+    .enumerate()
+    .map(|(i, b)| b ^ mask[i % 4])
+    .collect();
+  Ok(Some((
+    Frame {
+      fin,
+      opcode,
+      payload,
+    },
+    pos + len,
+  )))
+}
+
+// 服务端发送的帧不加掩码, 客户端发送的帧必须加掩码
+fn encode_frame(fin: bool, opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+  let mut frame = Vec::with_capacity(payload.len() + 14);
+  frame.push(((fin as u8) << 7) | opcode);
+  let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+  match payload.len() {
+    len @ 0..=125 => frame.push(mask_bit | len as u8),
+    len @ 126..=0xffff => {
+      frame.push(mask_bit | 126);
+      frame.extend_from_slice(&(len as u16).to_be_bytes());
+    }
+    len => {
+      frame.push(mask_bit | 127);
+      frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+  }
+  match mask {
+    Some(mask) => {
+      frame.extend_from_slice(&mask);
+      frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    }
+    None => frame.extend_from_slice(payload),
+  }
+  frame
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::TcpStream;
+  use std::thread;
+
+  use super::*;
+  use crate::router::Router;
+  use crate::server::Server;
+
+  const MASK: Option<[u8; 4]> = Some([0x37, 0xfa, 0x21, 0x3d]);
+
+  #[test]
+  fn handshake_key() {
+    assert_eq!(
+      "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+      accept_key("dGhlIHNhbXBsZSBub25jZQ==")
+    );
+
+    let mut request = Request::new("GET", "/ws");
+    request.headers.insert("Upgrade", "websocket");
+    request.headers.insert("Connection", "keep-alive, Upgrade");
+    request
+      .headers
+      .insert("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==");
+    assert_eq!(
+      StatusCode::UpgradeRequired,
+      upgrade(&request, |_| {}).status
+    );
+    request.headers.insert("Sec-WebSocket-Version", "13");
+    let response = upgrade(&request, |_| {});
+    assert_eq!(StatusCode::SwitchingProtocols, response.status);
+    assert!(response.upgrade.is_some());
+    request.headers.insert("Sec-WebSocket-Key", "c2hvcnQ=");
+    assert_eq!(StatusCode::BadRequest, upgrade(&request, |_| {}).status);
+  }
+
+  #[test]
+  fn frames() {
+    let frame = encode_frame(true, TEXT, b"Hello", MASK);
+    let (parsed, used) = parse_frame(&frame, 1024).ok().unwrap().unwrap();
+    assert_eq!(frame.len(), used);
+    assert_eq!(
+      (true, TEXT, &b"Hello"[..]),
+      (parsed.fin, parsed.opcode, &parsed.payload[..])
+    );
+    assert!(parse_frame(&frame[..frame.len() - 1], 1024)
+      .ok()
+      .unwrap()
+      .is_none());
+
+    let long = vec![7; 70000];
+    let frame = encode_frame(false, BINARY, &long, MASK);
+    let (parsed, _) = parse_frame(&frame, 1 << 20).ok().unwrap().unwrap();
+    assert_eq!(long, parsed.payload);
+    assert_eq!(
+      Some(close_code::MESSAGE_TOO_BIG),
+      parse_frame(&frame, 1024).err().map(|(code, _)| code)
+    );
+    assert_eq!(
+      Some(close_code::PROTOCOL_ERROR),
+      parse_frame(&encode_frame(true, TEXT, b"x", None), 1024)
+        .err()
+        .map(|(code, _)| code)
+    );
+  }
+
+  // 读取服务端发来的一个不带掩码的帧
+  fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).unwrap();
+    let len = match head[1] {
+      126 => {
+        let mut len = [0; 2];
+        stream.read_exact(&mut len).unwrap();
+        u16::from_be_bytes(len) as usize
+      }
+      len => len as usize,
+    };
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).unwrap();
+    (head[0] & 0x0f, payload)
+  }
+
+  const HANDSHAKE: &[u8] = b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+
+  // 读到响应首部结束为止, 不多读
+  fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+      let mut byte = [0];
+      stream.read_exact(&mut byte).unwrap();
+      head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+  }
+
+  #[test]
+  fn idle_connection_is_pinged_then_closed() {
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .build(|request: &mut Request| {
+        upgrade(request, |mut ws| {
+          ws.set_idle_timeout(Duration::from_millis(100)).unwrap();
+          while let Ok(Some(_)) = ws.recv() {}
+        })
+      })
+      .unwrap();
+    let addr = server.local_addr();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    let mut ws = TcpStream::connect(addr).unwrap();
+    ws.write_all(HANDSHAKE).unwrap();
+    assert!(read_head(&mut ws).starts_with("HTTP/1.1 101 "));
+    // 回复了pong, 连接继续保持
+    assert_eq!((PING, Vec::new()), read_frame(&mut ws));
+    ws.write_all(&encode_frame(true, PONG, b"", MASK)).unwrap();
+    assert_eq!((PING, Vec::new()), read_frame(&mut ws));
+    // 不再回复, 服务端关闭连接
+    let (opcode, payload) = read_frame(&mut ws);
+    assert_eq!(CLOSE, opcode);
+    assert_eq!(close_code::GOING_AWAY.to_be_bytes(), payload[..2]);
+    let mut rest = Vec::new();
+    ws.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    handle.shutdown();
+    running.join().unwrap();
+  }
+
+  #[test]
+  fn limit_upgraded_connections() {
+    let mut router = Router::new();
+    router
+      .get("/ws", |request: &mut Request| {
+        upgrade(request, |mut ws| while let Ok(Some(_)) = ws.recv() {})
+      })
+      .get("/events", |request: &mut Request| {
+        let (events, response) = crate::sse::Sse::new().response(request);
+        drop(events);
+        response
+      });
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .max_upgraded(1)
+      .build(router)
+      .unwrap();
+    let addr = server.local_addr();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    let handshake = || {
+      let mut stream = TcpStream::connect(addr).unwrap();
+      stream.write_all(HANDSHAKE).unwrap();
+      let head = read_head(&mut stream);
+      (stream, head)
+    };
+    let (mut first, head) = handshake();
+    assert!(head.starts_with("HTTP/1.1 101 "));
+
+    // WebSocket和事件流共用同一个上限
+    assert!(handshake().1.starts_with("HTTP/1.1 503 "));
+    let mut events = TcpStream::connect(addr).unwrap();
+    events
+      .write_all(b"GET /events HTTP/1.1\r\nConnection: close\r\n\r\n")
+      .unwrap();
+    assert!(read_head(&mut events).starts_with("HTTP/1.1 503 "));
+
+    // 第一个连接关闭之后名额被归还, 回调线程可能稍晚才退出
+    first
+      .write_all(&encode_frame(true, CLOSE, &1000u16.to_be_bytes(), MASK))
+      .unwrap();
+    first.read_to_end(&mut Vec::new()).unwrap();
+    let upgraded = (0..50).any(|_| {
+      let accepted = handshake().1.starts_with("HTTP/1.1 101 ");
+      if !accepted {
+        thread::sleep(Duration::from_millis(20));
+      }
+      accepted
+    });
+    assert!(upgraded);
+
+    handle.shutdown();
+    running.join().unwrap();
+  }
+
+  #[test]
+  fn push_while_waiting_in_recv() {
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .build(|request: &mut Request| {
+        upgrade(request, |mut ws| {
+          let sender = ws.sender().unwrap();
+          let pusher = thread::spawn(move || {
+            for i in 0..3 {
+              sender.send(Message::Text(format!("push {}", i))).unwrap();
+            }
+          });
+          // 回调线程一直阻塞在recv中, 推送由另一个线程完成
+          while let Ok(Some(message)) = ws.recv() {
+            if let Message::Text(text) = message {
+              ws.send(Message::Text(text.to_uppercase())).unwrap();
+            }
+          }
+          pusher.join().unwrap();
+        })
+      })
+      .unwrap();
+    let addr = server.local_addr();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    let mut ws = TcpStream::connect(addr).unwrap();
+    ws.write_all(HANDSHAKE).unwrap();
+    assert!(read_head(&mut ws).starts_with("HTTP/1.1 101 "));
+    for i in 0..3 {
+      assert_eq!(
+        (TEXT, format!("push {}", i).into_bytes()),
+        read_frame(&mut ws)
+      );
+    }
+    ws.write_all(&encode_frame(true, TEXT, b"echo", MASK))
+      .unwrap();
+    assert_eq!((TEXT, b"ECHO".to_vec()), read_frame(&mut ws));
+    ws.write_all(&encode_frame(true, CLOSE, &1000u16.to_be_bytes(), MASK))
+      .unwrap();
+    assert_eq!((CLOSE, 1000u16.to_be_bytes().to_vec()), read_frame(&mut ws));
+
+    handle.shutdown();
+    running.join().unwrap();
+  }
+
+  #[test]
+  fn echo_without_holding_worker() {
+    let mut router = Router::new();
+    router
+      .get("/ws", |request: &mut Request| {
+        upgrade(request, |mut ws| {
+          while let Ok(Some(message)) = ws.recv() {
+            if let Message::Text(text) = message {
+              ws.send(Message::Text(text.to_uppercase())).unwrap();
+            }
+          }
+        })
+      })
+      .get("/", |_: &mut Request| Response::text(StatusCode::Ok, "hi"));
+    let server = Server::builder()
+      .address("127.0.0.1:0")
+      .workers(1)
+      .build(router)
+      .unwrap();
+    let addr = server.local_addr();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run().unwrap());
+
+    // 握手之后紧跟着第一个帧, 一起发送
+    let mut ws = TcpStream::connect(addr).unwrap();
+    let mut handshake = HANDSHAKE.to_vec();
+    handshake.extend(encode_frame(true, TEXT, b"first", MASK));
+    ws.write_all(&handshake).unwrap();
+    let head = read_head(&mut ws);
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    assert_eq!((TEXT, b"FIRST".to_vec()), read_frame(&mut ws));
+
+    // 唯一的worker没有被WebSocket连接占用
+    let mut http = TcpStream::connect(addr).unwrap();
+    http
+      .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+      .unwrap();
+    let mut out = String::new();
+    http.read_to_string(&mut out).unwrap();
+    assert!(out.ends_with("hi"));
+
+    ws.write_all(&encode_frame(false, TEXT, b"frag", MASK))
+      .unwrap();
+    ws.write_all(&encode_frame(true, PING, b"p", MASK)).unwrap();
+    ws.write_all(&encode_frame(true, CONTINUATION, b"ment", MASK))
+      .unwrap();
+    assert_eq!((PONG, b"p".to_vec()), read_frame(&mut ws));
+    assert_eq!((TEXT, b"FRAGMENT".to_vec()), read_frame(&mut ws));
+
+    ws.write_all(&encode_frame(true, CLOSE, &1000u16.to_be_bytes(), MASK))
+      .unwrap();
+    assert_eq!((CLOSE, 1000u16.to_be_bytes().to_vec()), read_frame(&mut ws));
+    let mut rest = Vec::new();
+    ws.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    handle.shutdown();
+    running.join().unwrap();
+  }
+}